@@ -0,0 +1,476 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::time::Duration;
+
+use yaml_rust::{Yaml, YamlLoader};
+
+use crate::database::meta;
+use crate::database::naming::{self, NamingStrategy};
+use crate::database::operations::Risk;
+use crate::database::policy;
+
+pub const CONFIG_FILE: &str = "config.yml";
+const DEFAULT_POSTGRES_PORT: u16 = 5432;
+const DEFAULT_MYSQL_PORT: u16 = 3306;
+const DEFAULT_DUCKDB_CLI: &str = "duckdb";
+const DEFAULT_LOCK_TIMEOUT_SECONDS: u64 = 60;
+
+// keys under the app that configure the app rather than name an environment
+const NAMING_KEY: &str = "naming";
+const SCHEMA_MAPPING_KEY: &str = "schema_mapping";
+
+// the key in an environment for the risks its migrations may take
+const ALLOW_KEY: &str = "allow";
+// and for how many seconds to wait for another migration to finish
+const LOCK_TIMEOUT_KEY: &str = "lock_timeout";
+
+const REDACTED: &str = "******";
+
+#[derive(Debug)]
+pub enum ConfigError {
+  Unreadable(String, String)
+, BadYaml(String)
+, NoSuchApp(String)
+, NoSuchEnvironment(String, Vec<String>)
+, NoEnvironmentChosen(Vec<String>)
+, NoDatabase(String)
+, MissingSetting(String, String)
+, BadSetting(String, String)
+, UnsetVariable(String)
+, ConflictingSettings(String, String)
+, UnsupportedSetting(String, String)
+}
+
+impl std::error::Error for ConfigError { }
+
+impl fmt::Display for ConfigError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ConfigError::Unreadable(path, reason) => write!(f, "I couldn't read the configuration file {} because: {}", path, reason)
+    , ConfigError::BadYaml(reason) => write!(f, "I couldn't understand the configuration file because: {}", reason)
+    , ConfigError::NoSuchApp(name) => write!(f, "I couldn't find any configuration for an app called {}.", name)
+    , ConfigError::NoSuchEnvironment(name, envs) => write!(f, "I couldn't find an environment called {}, the environments are: {}.", name, envs.join(", "))
+    , ConfigError::NoEnvironmentChosen(envs) => write!(f, "I don't know which environment to use, choose one of {} with --env.", envs.join(", "))
+    , ConfigError::NoDatabase(env) => write!(f, "I couldn't find a database for the {} environment.", env)
+    , ConfigError::MissingSetting(setting, env) => write!(f, "I need a {} setting for the {} environment.", setting, env)
+    , ConfigError::BadSetting(setting, value) => write!(f, "I don't understand {} as a value for {}.", value, setting)
+    , ConfigError::UnsetVariable(name) => write!(f, "I need the environment variable {} but it isn't set and has no default.", name)
+    , ConfigError::ConflictingSettings(first, second) => write!(f, "I can only use one of {} and {}.", first, second)
+    , ConfigError::UnsupportedSetting(setting, database) => write!(f, "I can't use {} with {}.", setting, database)
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct AppConfig {
+  schema_mapping: meta::SchemaMapping
+, naming: NamingStrategy
+, environments: BTreeMap<String, Yaml>
+, variables: fn(&str) -> Option<String>
+}
+
+impl AppConfig {
+  pub fn environment_names(&self) -> Vec<String> {
+    self.environments.keys().cloned().collect()
+  }
+
+  // with no environment named the only one there is gets used
+  fn environment(&self, env: Option<&str>) -> Result<(String, &Yaml), ConfigError> {
+    let env_name = match env {
+      Some(e) => e.to_string()
+    , None if self.environments.len() == 1 => self.environment_names().remove(0)
+    , None => return Err(ConfigError::NoEnvironmentChosen(self.environment_names()))
+    };
+    let env_yaml = self.environments.get(&env_name).ok_or_else(|| ConfigError::NoSuchEnvironment(env_name.clone(), self.environment_names()))?;
+    Ok((env_name, env_yaml))
+  }
+
+  pub fn database_config(&self, env: Option<&str>) -> Result<meta::DatabaseConfig, ConfigError> {
+    let (env_name, env_yaml) = self.environment(env)?;
+    let postgres = &env_yaml["postgres"];
+    let sqlite = &env_yaml["sqlite"];
+    let mysql = &env_yaml["mysql"];
+    let duckdb = &env_yaml["duckdb"];
+    if !sqlite.is_badvalue() {
+      return self.sqlite_config(sqlite, &env_name);
+    }
+    if !mysql.is_badvalue() {
+      return self.mysql_config(mysql, &env_name);
+    }
+    if !duckdb.is_badvalue() {
+      return Ok(meta::DatabaseConfig::Duckdb(meta::DuckdbConfig{
+        path: required_str(duckdb, "path", &env_name, self.variables)?
+      , cli: optional_str(duckdb, "cli", self.variables)?.unwrap_or_else(|| DEFAULT_DUCKDB_CLI.to_string())
+      , schema_mapping: self.schema_mapping
+      , naming: self.naming.clone()
+      }));
+    }
+    if postgres.is_badvalue() {
+      return Err(ConfigError::NoDatabase(env_name));
+    }
+    Ok(meta::DatabaseConfig::Postgres(meta::PostgresConfig{
+      host: required_str(postgres, "host", &env_name, self.variables)?
+    , port: self.port(postgres, DEFAULT_POSTGRES_PORT)?
+    , database: required_str(postgres, "database", &env_name, self.variables)?
+    , user: required_str(postgres, "user", &env_name, self.variables)?
+    , password: self.password(postgres)?
+    , schema_mapping: self.schema_mapping
+    , naming: self.naming.clone()
+    }))
+  }
+
+  // the path is relative to the directory of the main file, like the migrations
+  fn sqlite_config(&self, sqlite: &Yaml, env_name: &str) -> Result<meta::DatabaseConfig, ConfigError> {
+    if self.schema_mapping == meta::SchemaMapping::PerNamespace {
+      return Err(ConfigError::UnsupportedSetting("schema_mapping: per_namespace".to_string(), "sqlite".to_string()));
+    }
+    Ok(meta::DatabaseConfig::Sqlite(meta::SqliteConfig{
+      path: required_str(sqlite, "path", env_name, self.variables)?
+    , naming: self.naming.clone()
+    }))
+  }
+
+  fn mysql_config(&self, mysql: &Yaml, env_name: &str) -> Result<meta::DatabaseConfig, ConfigError> {
+    if self.schema_mapping == meta::SchemaMapping::PerNamespace {
+      return Err(ConfigError::UnsupportedSetting("schema_mapping: per_namespace".to_string(), "mysql".to_string()));
+    }
+    Ok(meta::DatabaseConfig::Mysql(meta::MysqlConfig{
+      host: required_str(mysql, "host", env_name, self.variables)?
+    , port: self.port(mysql, DEFAULT_MYSQL_PORT)?
+    , database: required_str(mysql, "database", env_name, self.variables)?
+    , user: required_str(mysql, "user", env_name, self.variables)?
+    , password: self.password(mysql)?
+    , naming: self.naming.clone()
+    }))
+  }
+
+  // allow lists the risks migrate may run in the environment, every risk when it isn't set
+  pub fn policy(&self, env: Option<&str>) -> Result<policy::Policy, ConfigError> {
+    let (env_name, env_yaml) = self.environment(env)?;
+    let allow = &env_yaml[ALLOW_KEY];
+    if allow.is_badvalue() {
+      return Ok(policy::Policy::allow_all(&env_name));
+    }
+    let bad_setting = || ConfigError::BadSetting(ALLOW_KEY.to_string(), format!("{:?}", allow));
+    let allowed = allow.as_vec().ok_or_else(bad_setting)?.iter()
+      .map(|r| r.as_str().and_then(Risk::from_name).ok_or_else(bad_setting))
+      .collect::<Result<Vec<Risk>, ConfigError>>()?;
+    Ok(policy::Policy::new(&env_name, allowed))
+  }
+
+  // how long migrate waits for the app's migration lock, a minute when it isn't set
+  pub fn lock_timeout(&self, env: Option<&str>) -> Result<Duration, ConfigError> {
+    let (_, env_yaml) = self.environment(env)?;
+    let seconds = optional_int(env_yaml, LOCK_TIMEOUT_KEY, self.variables)?
+      .map_or(Ok(DEFAULT_LOCK_TIMEOUT_SECONDS), |s| u64::try_from(s).map_err(|_| ConfigError::BadSetting(LOCK_TIMEOUT_KEY.to_string(), s.to_string())))?;
+    Ok(Duration::from_secs(seconds))
+  }
+
+  fn port(&self, yaml: &Yaml, default: u16) -> Result<u16, ConfigError> {
+    optional_int(yaml, "port", self.variables)?.map_or(Ok(default), |p| u16::try_from(p).map_err(|_| ConfigError::BadSetting("port".to_string(), p.to_string())))
+  }
+
+  // the password can be given directly or read from a file, a mounted secret for instance
+  fn password(&self, yaml: &Yaml) -> Result<Option<meta::Secret>, ConfigError> {
+    let password = optional_str(yaml, "password", self.variables)?;
+    let password_file = optional_str(yaml, "password_file", self.variables)?;
+    match (password, password_file) {
+      (Some(_), Some(_)) => Err(ConfigError::ConflictingSettings("password".to_string(), "password_file".to_string()))
+    , (Some(p), None) => Ok(Some(meta::Secret::new(&p)))
+    , (None, Some(path)) => {
+        let text = fs::read_to_string(&path).map_err(|e| ConfigError::Unreadable(path.clone(), e.to_string()))?;
+        Ok(Some(meta::Secret::new(text.trim_end_matches(&['\r', '\n'][..]))))
+      }
+    , (None, None) => Ok(None)
+    }
+  }
+}
+
+pub fn load(path: &str, app: &str) -> Result<AppConfig, ConfigError> {
+  let text = fs::read_to_string(path).map_err(|e| ConfigError::Unreadable(path.to_string(), e.to_string()))?;
+  parse(&text, app)
+}
+
+pub fn parse(text: &str, app: &str) -> Result<AppConfig, ConfigError> {
+  parse_with_variables(text, app, |name| env::var(name).ok())
+}
+
+fn parse_with_variables(text: &str, app: &str, variables: fn(&str) -> Option<String>) -> Result<AppConfig, ConfigError> {
+  let docs = YamlLoader::load_from_str(text).map_err(|e| ConfigError::BadYaml(e.to_string()))?;
+  let app_yaml = docs.first().map(|d| &d[app]).filter(|a| a.as_hash().is_some()).ok_or_else(|| ConfigError::NoSuchApp(app.to_string()))?;
+  let environments = app_yaml.as_hash().unwrap().iter()
+    .filter_map(|(k, v)| Some((k.as_str()?.to_string(), v.clone())))
+    .filter(|(k, _)| k != NAMING_KEY && k != SCHEMA_MAPPING_KEY)
+    .collect();
+  Ok(AppConfig{ schema_mapping: schema_mapping(&app_yaml[SCHEMA_MAPPING_KEY])?, naming: naming_strategy(&app_yaml[NAMING_KEY], variables)?, environments, variables })
+}
+
+// hides every secret the configuration holds, anything printed that could contain one goes through here
+pub fn redact(message: &str, db_config: &meta::DatabaseConfig) -> String {
+  db_config.secrets().iter().filter(|s| !s.expose().is_empty())
+    .fold(message.to_string(), |m, s| m.replace(s.expose(), REDACTED))
+}
+
+// ${NAME} is replaced by the environment variable NAME, ${NAME:-default} falls back to default when it is unset
+// and $$ is a literal $
+fn interpolate(text: &str, variables: fn(&str) -> Option<String>) -> Result<String, ConfigError> {
+  let mut result = String::new();
+  let mut rest = text;
+  while let Some(start) = rest.find('$') {
+    result.push_str(&rest[..start]);
+    let after = &rest[start + 1..];
+    if let Some(r) = after.strip_prefix('$') {
+      result.push('$');
+      rest = r;
+    } else if let (Some(body), Some(end)) = (after.strip_prefix('{'), after.find('}')) {
+      let expression = &body[..end - 1];
+      let (name, default) = match expression.find(":-") {
+        Some(i) => (&expression[..i], Some(&expression[i + 2..]))
+      , None => (expression, None)
+      };
+      let value = variables(name).or_else(|| default.map(|d| d.to_string())).ok_or_else(|| ConfigError::UnsetVariable(name.to_string()))?;
+      result.push_str(&value);
+      rest = &after[end + 1..];
+    } else {
+      result.push('$');
+      rest = after;
+    }
+  }
+  result.push_str(rest);
+  Ok(result)
+}
+
+fn schema_mapping(yaml: &Yaml) -> Result<meta::SchemaMapping, ConfigError> {
+  match yaml.as_str() {
+    None if yaml.is_badvalue() => Ok(meta::SchemaMapping::Flatten)
+  , Some("flatten") => Ok(meta::SchemaMapping::Flatten)
+  , Some("per_namespace") => Ok(meta::SchemaMapping::PerNamespace)
+  , _ => Err(ConfigError::BadSetting(SCHEMA_MAPPING_KEY.to_string(), format!("{:?}", yaml)))
+  }
+}
+
+fn naming_strategy(yaml: &Yaml, variables: fn(&str) -> Option<String>) -> Result<NamingStrategy, ConfigError> {
+  let default = NamingStrategy::default();
+  if yaml.is_badvalue() {
+    return Ok(default);
+  }
+  Ok(NamingStrategy{
+    snake_case: optional_bool(yaml, "snake_case")?.unwrap_or(default.snake_case)
+  , pluralise: optional_bool(yaml, "pluralise")?.unwrap_or(default.pluralise)
+  , prefix: optional_str(yaml, "prefix", variables)?.unwrap_or(default.prefix)
+  , max_length: optional_int(yaml, "max_length", variables)?.map_or(naming::DEFAULT_MAX_LENGTH, |l| l as usize)
+  })
+}
+
+fn required_str(yaml: &Yaml, key: &str, env: &str, variables: fn(&str) -> Option<String>) -> Result<String, ConfigError> {
+  optional_str(yaml, key, variables)?.ok_or_else(|| ConfigError::MissingSetting(key.to_string(), env.to_string()))
+}
+
+fn optional_str(yaml: &Yaml, key: &str, variables: fn(&str) -> Option<String>) -> Result<Option<String>, ConfigError> {
+  match &yaml[key] {
+    Yaml::BadValue | Yaml::Null => Ok(None)
+  , Yaml::String(s) => Ok(Some(interpolate(s, variables)?))
+  , Yaml::Integer(i) => Ok(Some(i.to_string()))
+  , Yaml::Real(r) => Ok(Some(r.clone()))
+  , Yaml::Boolean(b) => Ok(Some(b.to_string()))
+  , other => Err(ConfigError::BadSetting(key.to_string(), format!("{:?}", other)))
+  }
+}
+
+fn optional_int(yaml: &Yaml, key: &str, variables: fn(&str) -> Option<String>) -> Result<Option<i64>, ConfigError> {
+  match &yaml[key] {
+    Yaml::BadValue | Yaml::Null => Ok(None)
+  , Yaml::Integer(i) if *i >= 0 => Ok(Some(*i))
+  , Yaml::String(s) => interpolate(s, variables)?.parse::<i64>().map(Some).map_err(|_| ConfigError::BadSetting(key.to_string(), s.clone()))
+  , other => Err(ConfigError::BadSetting(key.to_string(), format!("{:?}", other)))
+  }
+}
+
+fn optional_bool(yaml: &Yaml, key: &str) -> Result<Option<bool>, ConfigError> {
+  match &yaml[key] {
+    Yaml::BadValue | Yaml::Null => Ok(None)
+  , Yaml::Boolean(b) => Ok(Some(*b))
+  , other => Err(ConfigError::BadSetting(key.to_string(), format!("{:?}", other)))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const CONFIG: &str = r#"
+postgres_app:
+  naming:
+    snake_case: true
+  dev:
+    postgres:
+      host: localhost
+      database: postgres
+      user: postgres
+      password: password
+  prod:
+    postgres:
+      host: db.internal
+      port: 6543
+      database: app
+      user: app
+"#;
+
+  #[test]
+  fn test_environment() {
+    let config = parse(CONFIG, "postgres_app").unwrap();
+    assert_eq!(config.environment_names(), vec!("dev", "prod"));
+    match config.database_config(Some("prod")).unwrap() {
+      meta::DatabaseConfig::Postgres(p) => {
+        assert_eq!(p.host, "db.internal");
+        assert_eq!(p.port, 6543);
+        assert_eq!(p.password, None);
+        assert!(p.naming.snake_case);
+      }
+    , _ => unreachable!()
+    }
+  }
+
+  #[test]
+  fn test_policy() {
+    let text = CONFIG.replace("  prod:\n", "  prod:\n    allow: [safe, data-rewriting]\n");
+    let config = parse(&text, "postgres_app").unwrap();
+    let prod = config.policy(Some("prod")).unwrap();
+    assert!(prod.allows(Risk::DataRewriting));
+    assert!(!prod.allows(Risk::Destructive));
+    assert!(config.policy(Some("dev")).unwrap().allows(Risk::Destructive));
+    let bad = parse(&CONFIG.replace("  prod:\n", "  prod:\n    allow: [reckless]\n"), "postgres_app").unwrap();
+    assert!(matches!(bad.policy(Some("prod")), Err(ConfigError::BadSetting(_, _))));
+  }
+
+  #[test]
+  fn test_lock_timeout() {
+    let config = parse(&CONFIG.replace("  prod:\n", "  prod:\n    lock_timeout: 300\n"), "postgres_app").unwrap();
+    assert_eq!(config.lock_timeout(Some("prod")).unwrap(), Duration::from_secs(300));
+    assert_eq!(config.lock_timeout(Some("dev")).unwrap(), Duration::from_secs(DEFAULT_LOCK_TIMEOUT_SECONDS));
+    let bad = parse(&CONFIG.replace("  prod:\n", "  prod:\n    lock_timeout: soon\n"), "postgres_app").unwrap();
+    assert!(matches!(bad.lock_timeout(Some("prod")), Err(ConfigError::BadSetting(_, _))));
+    let negative = parse(&CONFIG.replace("  prod:\n", "  prod:\n    lock_timeout: -5\n"), "postgres_app").unwrap();
+    assert!(matches!(negative.lock_timeout(Some("prod")), Err(ConfigError::BadSetting(_, _))));
+    // a string, as a variable would be, gets past the integer check
+    let negative = parse(&CONFIG.replace("  prod:\n", "  prod:\n    lock_timeout: '-5'\n"), "postgres_app").unwrap();
+    assert_eq!(negative.lock_timeout(Some("prod")).unwrap_err().to_string(), "I don't understand -5 as a value for lock_timeout.");
+  }
+
+  #[test]
+  fn test_environment_errors() {
+    let config = parse(CONFIG, "postgres_app").unwrap();
+    assert!(matches!(config.database_config(None), Err(ConfigError::NoEnvironmentChosen(_))));
+    assert!(matches!(config.database_config(Some("test")), Err(ConfigError::NoSuchEnvironment(_, _))));
+    assert!(matches!(parse(CONFIG, "other_app"), Err(ConfigError::NoSuchApp(_))));
+  }
+
+  #[test]
+  fn test_interpolation() {
+    let variables = |name: &str| match name {
+      "PG_HOST" => Some("db.internal".to_string())
+    , "PG_PORT" => Some("6543".to_string())
+    , "PG_PASSWORD" => Some("s3cret".to_string())
+    , _ => None
+    };
+    let text = r#"
+app:
+  prod:
+    postgres:
+      host: ${PG_HOST}
+      port: ${PG_PORT}
+      database: ${PG_DATABASE:-app}
+      user: app_$${USER}
+      password: ${PG_PASSWORD}
+"#;
+    let config = parse_with_variables(text, "app", variables).unwrap();
+    let db_config = config.database_config(None).unwrap();
+    match &db_config {
+      meta::DatabaseConfig::Postgres(p) => {
+        assert_eq!(p.host, "db.internal");
+        assert_eq!(p.port, 6543);
+        assert_eq!(p.database, "app");
+        assert_eq!(p.user, "app_${USER}");
+        assert_eq!(p.password.as_ref().unwrap().expose(), "s3cret");
+        assert!(!format!("{:?}", p).contains("s3cret"));
+      }
+    , _ => unreachable!()
+    }
+    assert_eq!(redact("password=s3cret failed", &db_config), "password=****** failed");
+
+    let unset = parse_with_variables(&text.replace("${PG_HOST}", "${PG_UNSET}"), "app", variables).unwrap();
+    assert!(matches!(unset.database_config(None), Err(ConfigError::UnsetVariable(_))));
+  }
+
+  #[test]
+  fn test_sqlite() {
+    let text = r#"
+app:
+  naming:
+    pluralise: true
+  local:
+    sqlite:
+      path: app.db
+"#;
+    match parse(text, "app").unwrap().database_config(None).unwrap() {
+      meta::DatabaseConfig::Sqlite(s) => {
+        assert_eq!(s.path, "app.db");
+        assert!(s.naming.pluralise);
+      }
+    , _ => unreachable!()
+    }
+    let per_namespace = parse(&text.replace("app:\n", "app:\n  schema_mapping: per_namespace\n"), "app").unwrap();
+    assert!(matches!(per_namespace.database_config(None), Err(ConfigError::UnsupportedSetting(_, _))));
+  }
+
+  #[test]
+  fn test_mysql() {
+    let text = r#"
+app:
+  local:
+    mysql:
+      host: localhost
+      database: app
+      user: root
+      password: s3cret
+"#;
+    let db_config = parse(text, "app").unwrap().database_config(None).unwrap();
+    match &db_config {
+      meta::DatabaseConfig::Mysql(m) => {
+        assert_eq!(m.port, 3306);
+        assert_eq!(m.database, "app");
+      }
+    , _ => unreachable!()
+    }
+    assert_eq!(db_config.dialect(), "mysql");
+    assert_eq!(redact("password=s3cret failed", &db_config), "password=****** failed");
+  }
+
+  #[test]
+  fn test_duckdb() {
+    let text = r#"
+app:
+  schema_mapping: per_namespace
+  analytics:
+    duckdb:
+      path: analytics.duckdb
+"#;
+    match parse(text, "app").unwrap().database_config(Some("analytics")).unwrap() {
+      meta::DatabaseConfig::Duckdb(d) => {
+        assert_eq!(d.path, "analytics.duckdb");
+        assert_eq!(d.cli, "duckdb");
+        assert_eq!(d.schema_mapping, meta::SchemaMapping::PerNamespace);
+      }
+    , _ => unreachable!()
+    }
+  }
+
+  #[test]
+  fn test_example() {
+    let config = load(concat!(env!("CARGO_MANIFEST_DIR"), "/examples/postgres/config.yml"), "postgres_app").unwrap();
+    assert!(matches!(config.database_config(None).unwrap(), meta::DatabaseConfig::Postgres(_)));
+  }
+}
@@ -0,0 +1,35 @@
+use crate::database::drivers::{duckdb, postgres};
+use crate::database::integration;
+use crate::database::meta;
+use crate::lang::ast;
+
+// copies every table of the model from a postgres database into a duckdb one, replacing the rows
+// the copy held before. Both must match the model so the columns line up, rows are streamed as CSV
+// from postgres straight into duckdb
+pub fn copy_tables(ast: &ast::Application, from: &meta::DatabaseConfig, to: &meta::DatabaseConfig) -> Result<Vec<String>, String> {
+  match (from, to) {
+    (meta::DatabaseConfig::Postgres(_), meta::DatabaseConfig::Duckdb(_)) => {}
+  , _ => return Err(format!("I can only copy from postgres into duckdb, not from {} into {}.", from.dialect(), to.dialect()))
+  }
+  let sources = integration::diagnose_db_diffs(ast, from)?;
+  let targets = integration::diagnose_db_diffs(ast, to)?;
+  integration::check_matches_model(&sources, "The database to copy from")?;
+  integration::check_matches_model(&targets, "The database to copy into")?;
+  sources.iter().zip(targets.iter()).map(|(source, target)| {
+    duckdb::copy_in(to, target.db_table(), &mut |sink| postgres::copy_out(from, source.db_table(), sink))?;
+    Ok(target.db_table().name())
+  }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lang::ast_builder;
+
+  #[test]
+  fn test_copy_dialects() {
+    let ast = ast_builder::build("\napp database\n\nnamespace db where\n\nstruct persists Agent\n\nname:: Agent -> String").unwrap();
+    let mock_db_config = meta::DatabaseConfig::MockDb(meta::MockDbConfig::new(vec!()));
+    assert_eq!(copy_tables(&ast, &mock_db_config, &mock_db_config).unwrap_err(), "I can only copy from postgres into duckdb, not from mock into mock.");
+  }
+}
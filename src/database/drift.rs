@@ -0,0 +1,183 @@
+use crate::database::integration::{DbDiff, DiffDiagnosis};
+use crate::database::meta;
+
+// exit codes of the drift command so CI can tell an out of date database from an unreachable one
+pub const IN_SYNC: i32 = 0;
+pub const DRIFT_FOUND: i32 = 1;
+pub const UNREACHABLE: i32 = 2;
+
+// one way the database differs from the model, tables are named as schema.table or just table
+// when the model flattens namespaces
+#[derive(Debug, PartialEq)]
+pub enum Drift {
+  SchemaMissing(String)
+, TableMissing(String)
+, TableMoved(String, String)
+, TableExtra(String)
+, ColumnMissing(String, String)
+, ColumnExtra(String, String)
+, ColumnTypeMismatch(String, String, String, String)
+, ColumnNotNull(String, String)
+}
+
+impl Drift {
+  fn kind(&self) -> &str {
+    match self {
+      Drift::SchemaMissing(_) => "schema_missing"
+    , Drift::TableMissing(_) => "table_missing"
+    , Drift::TableMoved(_, _) => "table_moved"
+    , Drift::TableExtra(_) => "table_extra"
+    , Drift::ColumnMissing(_, _) => "column_missing"
+    , Drift::ColumnExtra(_, _) => "column_extra"
+    , Drift::ColumnTypeMismatch(_, _, _, _) => "column_type_mismatch"
+    , Drift::ColumnNotNull(_, _) => "column_not_null"
+    }
+  }
+
+  fn describe(&self) -> String {
+    match self {
+      Drift::SchemaMissing(s) => format!("schema {} is missing", s)
+    , Drift::TableMissing(t) => format!("table {} is missing", t)
+    , Drift::TableMoved(t, from) => format!("table {} is in schema {}", t, from)
+    , Drift::TableExtra(t) => format!("table {} is not in the model", t)
+    , Drift::ColumnMissing(t, c) => format!("column {}.{} is missing", t, c)
+    , Drift::ColumnExtra(t, c) => format!("column {}.{} is not in the model", t, c)
+    , Drift::ColumnTypeMismatch(t, c, expected, found) => format!("column {}.{} is {} but the model has {}", t, c, found, expected)
+    , Drift::ColumnNotNull(t, c) => format!("column {}.{} is not null but the model allows it to be empty", t, c)
+    }
+  }
+
+  fn json_fields(&self) -> Vec<(&str, &str)> {
+    match self {
+      Drift::SchemaMissing(s) => vec!(("schema", s))
+    , Drift::TableMissing(t) | Drift::TableExtra(t) => vec!(("table", t))
+    , Drift::TableMoved(t, from) => vec!(("table", t), ("found_in", from))
+    , Drift::ColumnMissing(t, c) | Drift::ColumnExtra(t, c) | Drift::ColumnNotNull(t, c) => vec!(("table", t), ("column", c))
+    , Drift::ColumnTypeMismatch(t, c, expected, found) => vec!(("table", t), ("column", c), ("expected", expected), ("found", found))
+    }
+  }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct DriftReport {
+  drifts: Vec<Drift>
+}
+
+impl DriftReport {
+  pub fn exit_code(&self) -> i32 {
+    if self.drifts.is_empty() { IN_SYNC } else { DRIFT_FOUND }
+  }
+
+  pub fn to_text(&self) -> String {
+    if self.drifts.is_empty() {
+      return "The database matches the model".to_string();
+    }
+    let lines = self.drifts.iter().map(|d| format!("  {}", d.describe())).collect::<Vec<String>>().join("\n");
+    format!("The database has drifted from the model:\n{}", lines)
+  }
+
+  pub fn to_json(&self) -> String {
+    let drifts = self.drifts.iter().map(|d| {
+      let mut fields = vec!(format!("\"kind\":{}", json_string(d.kind())));
+      fields.extend(d.json_fields().into_iter().map(|(k, v)| format!("{}:{}", json_string(k), json_string(v))));
+      format!("{{{}}}", fields.join(","))
+    }).collect::<Vec<String>>();
+    format!("{{\"in_sync\":{},\"drift\":[{}]}}", self.drifts.is_empty(), drifts.join(","))
+  }
+}
+
+// the error when the database couldn't be read, in the same shape as a report
+pub fn unreachable_json(message: &str) -> String {
+  format!("{{\"in_sync\":null,\"error\":{}}}", json_string(message))
+}
+
+pub fn drift_report(db_diffs: &[DbDiff], extra_tables: &[meta::Table]) -> DriftReport {
+  let mut drifts: Vec<Drift> = Vec::new();
+  db_diffs.iter().for_each(|d| {
+    let table = table_label(d.db_table());
+    d.diff_diagnosis().iter().for_each(|diagnosis| {
+      let drift = match diagnosis {
+        DiffDiagnosis::NoDiff => None
+      , DiffDiagnosis::SchemaMissing => Some(Drift::SchemaMissing(d.db_table().schema()))
+      , DiffDiagnosis::TableMissing => Some(Drift::TableMissing(table.clone()))
+      , DiffDiagnosis::TableMoved(from) => Some(Drift::TableMoved(table.clone(), from.clone()))
+      , DiffDiagnosis::ColumnMissing(c) => Some(Drift::ColumnMissing(table.clone(), c.clone()))
+      , DiffDiagnosis::ColumnTypeMismatch(c, expected, found) => {
+          Some(Drift::ColumnTypeMismatch(table.clone(), c.clone(), expected.name(), found.name()))
+        }
+      };
+      if let Some(drift) = drift.filter(|drift| !drifts.contains(drift)) {
+        drifts.push(drift);
+      }
+    });
+    if let Some(database_table) = d.database_table() {
+      database_table.columns().iter().for_each(|c| {
+        match d.db_table().columns().iter().find(|ec| ec.name() == c.name()) {
+          None => drifts.push(Drift::ColumnExtra(table.clone(), c.name()))
+        , Some(ec) if ec.nullable() && !c.nullable() => drifts.push(Drift::ColumnNotNull(table.clone(), c.name()))
+        , Some(_) => {}
+        }
+      });
+    }
+  });
+  drifts.extend(extra_tables.iter().map(|t| Drift::TableExtra(table_label(t))));
+  DriftReport{ drifts }
+}
+
+fn table_label(table: &meta::Table) -> String {
+  if table.schema().is_empty() { table.name() } else { format!("{}.{}", table.schema(), table.name()) }
+}
+
+fn json_string(value: &str) -> String {
+  let escaped: String = value.chars().map(|c| match c {
+    '"' => "\\\"".to_string()
+  , '\\' => "\\\\".to_string()
+  , '\n' => "\\n".to_string()
+  , c if (c as u32) < 0x20 => format!("\\u{:04x}", c as u32)
+  , c => c.to_string()
+  }).collect();
+  format!("\"{}\"", escaped)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::database::integration;
+  use crate::lang::ast_builder;
+  use crate::lang::internal;
+
+  #[test]
+  fn test_drift_report() {
+    let code = r#"
+app database
+
+namespace db where
+
+struct persists Agent
+
+name:: Agent -> String"#;
+
+    let ast = ast_builder::build(code).unwrap();
+    let agent = meta::Table::new("", "db_Agent", vec!(
+      meta::Column::new("name", internal::LeafType::Float)
+    , meta::Column::new("nickname", internal::LeafType::String)
+    ));
+    let extra = meta::Table::new("", "audit", vec!());
+    let mock_db_config = meta::DatabaseConfig::MockDb(meta::MockDbConfig::new(vec!(agent, extra)));
+    let database = integration::introspect(None, &mock_db_config).unwrap();
+    let db_diffs = integration::diagnose_snapshot(&ast, &mock_db_config, &database);
+    let extras = integration::extra_tables(&db_diffs, &database);
+    let report = drift_report(&db_diffs, &extras);
+    assert_eq!(report.exit_code(), DRIFT_FOUND);
+    assert!(report.drifts.contains(&Drift::ColumnTypeMismatch("db_Agent".to_string(), "name".to_string(), "String".to_string(), "Float".to_string())));
+    assert!(report.drifts.contains(&Drift::ColumnExtra("db_Agent".to_string(), "nickname".to_string())));
+    assert!(report.drifts.contains(&Drift::TableExtra("audit".to_string())));
+    assert!(report.to_json().starts_with("{\"in_sync\":false,\"drift\":[{\"kind\":"));
+  }
+
+  #[test]
+  fn test_json_string() {
+    assert_eq!(json_string("a \"b\"\\\n"), "\"a \\\"b\\\"\\\\\\n\"");
+    assert_eq!(DriftReport{ drifts: vec!() }.to_json(), "{\"in_sync\":true,\"drift\":[]}");
+  }
+}
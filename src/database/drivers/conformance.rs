@@ -0,0 +1,51 @@
+// what every driver has to do the same way. Each driver's tests run it against a database that has
+// neither the model's tables nor a migration history, and it leaves the database that way
+use std::time::{Duration, Instant};
+
+use crate::database::drivers::DatabaseDriver;
+use crate::database::integration::{self, DiffDiagnosis};
+use crate::database::meta;
+use crate::database::migrations::HistoryChange;
+use crate::database::operations::{self, Operation};
+use crate::lang::ast_builder;
+
+const MODEL: &str = r#"
+app conformance
+
+namespace db where
+
+struct persists Agent
+
+name:: Agent -> String
+age:: Agent -> Int"#;
+
+pub fn check(driver: &dyn DatabaseDriver, db_config: &meta::DatabaseConfig) {
+  let ast = ast_builder::build(MODEL).unwrap();
+  let versions = || driver.applied_migrations(db_config).unwrap().iter().map(|m| m.version).collect::<Vec<i32>>();
+  let diagnoses = || integration::diagnose_db_diffs(&ast, db_config).unwrap();
+  let all_missing = || diagnoses().iter().all(|d| d.diff_diagnosis().contains(&DiffDiagnosis::TableMissing));
+  assert!(versions().is_empty());
+  assert!(all_missing());
+
+  let planned = operations::plan(&diagnoses());
+  let up = HistoryChange::Record{ version: 1, checksum: "up".to_string(), started: Instant::now() };
+  driver.execute_changes(&driver.changes(&planned, db_config).unwrap(), Some(&up), db_config).unwrap();
+  assert!(diagnoses().iter().all(|d| d.diff_diagnosis().iter().all(|d| *d == DiffDiagnosis::NoDiff)));
+  assert_eq!(versions(), vec!(1));
+
+  // a migration that fails isn't recorded
+  let dropped = vec!(Operation::DropTable(meta::Table::new("", "conformance_missing", vec!())));
+  let failing = HistoryChange::Record{ version: 2, checksum: "failing".to_string(), started: Instant::now() };
+  assert!(driver.execute_changes(&driver.changes(&dropped, db_config).unwrap(), Some(&failing), db_config).is_err());
+  assert_eq!(versions(), vec!(1));
+
+  let held = driver.lock("conformance", "first", Duration::ZERO, db_config).unwrap();
+  assert!(driver.lock("conformance", "second", Duration::ZERO, db_config).is_err());
+  drop(held);
+  assert!(driver.lock("conformance", "second", Duration::ZERO, db_config).is_ok());
+
+  let down = driver.changes(&operations::reverse(&planned), db_config).unwrap();
+  driver.execute_changes(&down, Some(&HistoryChange::Remove(1)), db_config).unwrap();
+  assert!(all_missing());
+  assert!(versions().is_empty());
+}
@@ -0,0 +1,435 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use crate::database::drivers::{self, DatabaseDriver};
+use crate::database::meta;
+use crate::database::migrations;
+use crate::database::operations::{ColumnChange, Operation, TableRef};
+use crate::database::seed;
+use crate::lang::internal;
+
+// query results come back from the command line one row a line, with these between fields and for null
+const FIELD_SEPARATOR: &str = "\u{1f}";
+const NULL_VALUE: &str = "\\N";
+
+pub struct DuckdbDriver;
+
+impl DatabaseDriver for DuckdbDriver {
+  fn dialect(&self) -> &'static str {
+    "duckdb"
+  }
+
+  fn introspect(&self, schemas: Option<&[String]>, db_config: &meta::DatabaseConfig) -> Result<meta::Database, String> {
+    introspect(schemas, db_config)
+  }
+
+  fn changes(&self, operations: &[Operation], _db_config: &meta::DatabaseConfig) -> Result<meta::DatabaseChange, String> {
+    operations.iter().map(operation_ddl).collect::<Result<Vec<String>, String>>().map(meta::DatabaseChange::SqlDb)
+  }
+
+  // the history change is the last statement of the migration's transaction, a prepared statement
+  // the values are bound to since the command line takes no parameters of its own
+  fn execute_changes(&self, db_changes: &meta::DatabaseChange, history: Option<&migrations::HistoryChange>, db_config: &meta::DatabaseConfig) -> Result<(), String> {
+    let change = history.map(|h| match h.applied() {
+      Some(migration) => vec!(
+        format!("PREPARE gimbal_history AS INSERT INTO {} (version, checksum, duration_ms) VALUES ($1, $2, $3)", quote_ident(migrations::HISTORY_TABLE))
+      , format!("EXECUTE gimbal_history({}, {}, {})", migration.version, quote_literal(&migration.checksum), migration.duration_ms)
+      )
+    , None => vec!(
+        format!("PREPARE gimbal_history AS DELETE FROM {} WHERE version = $1", quote_ident(migrations::HISTORY_TABLE))
+      , format!("EXECUTE gimbal_history({})", h.version())
+      )
+    });
+    run_changes(&db_changes.commands().iter().cloned().chain(change.into_iter().flatten()).collect::<Vec<String>>(), db_config)
+  }
+
+  fn applied_migrations(&self, db_config: &meta::DatabaseConfig) -> Result<Vec<migrations::AppliedMigration>, String> {
+    let history = quote_ident(migrations::HISTORY_TABLE);
+    let sql = format!(
+      "CREATE TABLE IF NOT EXISTS {} (version integer PRIMARY KEY, checksum varchar NOT NULL, applied_at timestamp NOT NULL DEFAULT current_timestamp, duration_ms bigint NOT NULL);
+       SELECT version, checksum, applied_at::varchar, duration_ms FROM {} ORDER BY version;", history, history);
+    let rows = query(db_config, &sql).map_err(|e| format!("I couldn't read the migration history because: {}", e))?;
+    rows.iter().map(|r| {
+      let number = |i: usize| r[i].as_deref().unwrap_or_default().parse().map_err(|_| format!("I couldn't read the migration history row {:?}", r));
+      Ok(migrations::AppliedMigration{ version: number(0)? as i32, checksum: text(r, 1), applied_at: text(r, 2), duration_ms: number(3)? })
+    }).collect()
+  }
+
+  fn upsert(&self, rows: &[seed::Row], db_config: &meta::DatabaseConfig) -> Result<(), String> {
+    let dialect = seed::SqlDialect{ quote_ident, literal: seed::literal, select_from: "" };
+    let statements: Vec<String> = rows.iter().flat_map(|r| seed::upsert_statements(r, &qualified_table_name(&r.table), &dialect)).collect();
+    run_changes(&statements, db_config)
+  }
+
+  fn lock<'a>(&self, app: &str, holder: &str, timeout: Duration, db_config: &'a meta::DatabaseConfig) -> Result<Box<dyn migrations::HeldLock + 'a>, String> {
+    lock(app, holder, timeout, db_config)
+  }
+}
+
+// each run of the command line is a session of its own, so the lock is a row of the lock table
+// that is deleted when it is dropped
+struct TableLock<'a> {
+  db_config: &'a meta::DatabaseConfig
+, app: String
+, holder: String
+}
+
+impl migrations::HeldLock for TableLock<'_> {}
+
+impl Drop for TableLock<'_> {
+  fn drop(&mut self) {
+    let delete = format!("DELETE FROM {} WHERE app = {} AND holder = {};", quote_ident(migrations::LOCK_TABLE), quote_literal(&self.app), quote_literal(&self.holder));
+    let _ = run(self.db_config, &delete);
+  }
+}
+
+// the insert does nothing when someone else holds the lock, whoever holds it is read back either way
+fn lock<'a>(app: &str, holder: &str, timeout: Duration, db_config: &'a meta::DatabaseConfig) -> Result<Box<dyn migrations::HeldLock + 'a>, String> {
+  let lock_table = quote_ident(migrations::LOCK_TABLE);
+  let sql = format!(
+    "CREATE TABLE IF NOT EXISTS {table} (app varchar PRIMARY KEY, holder varchar NOT NULL, acquired_at timestamp NOT NULL DEFAULT current_timestamp);
+     INSERT INTO {table} (app, holder) VALUES ({app}, {holder}) ON CONFLICT DO NOTHING;
+     SELECT holder, acquired_at::varchar FROM {table} WHERE app = {app};", table = lock_table, app = quote_literal(app), holder = quote_literal(holder));
+  let mut other = (String::new(), String::new());
+  let taken = drivers::wait_for_lock(timeout, || {
+    let rows = query(db_config, &sql).map_err(|e| format!("I couldn't take the migration lock because: {}", e))?;
+    other = rows.first().map(|r| (text(r, 0), text(r, 1))).unwrap_or_default();
+    Ok(if other.0 == holder { Some(()) } else { None })
+  })?;
+  match taken {
+    Some(()) => Ok(Box::new(TableLock{ db_config, app: app.to_string(), holder: holder.to_string() }))
+  , None => Err(drivers::lock_table_error(app, timeout, &other.0, &other.1))
+  }
+}
+
+// every statement runs in one transaction, the command line stops at the first error and the
+// transaction is never committed
+fn run_changes(commands: &[String], db_config: &meta::DatabaseConfig) -> Result<(), String> {
+  let statements = commands.iter().filter(|c| !c.trim().is_empty()).map(|c| format!("{};\n", c)).collect::<String>();
+  run(db_config, &format!("BEGIN TRANSACTION;\n{}COMMIT;\n", statements))
+    .map(|_| ())
+    .map_err(|e| format!("I couldn't run the migration because: {}\nNothing was changed.", e))
+}
+
+fn duckdb_config(db_config: &meta::DatabaseConfig) -> &meta::DuckdbConfig {
+  match db_config {
+    meta::DatabaseConfig::Duckdb(c) => c
+  , _ => unreachable!()
+  }
+}
+
+fn command(config: &meta::DuckdbConfig) -> Command {
+  let mut command = Command::new(&config.cli);
+  command.args(["-bail", "-noheader", "-list", "-separator", FIELD_SEPARATOR, "-nullvalue", NULL_VALUE, &config.path]);
+  command
+}
+
+fn cli_error(config: &meta::DuckdbConfig, e: std::io::Error) -> String {
+  format!("I couldn't run the duckdb command line {} because: {}", config.cli, e)
+}
+
+// runs the statements read from stdin against the database file and returns what they printed
+fn run(db_config: &meta::DatabaseConfig, sql: &str) -> Result<String, String> {
+  let config = duckdb_config(db_config);
+  let mut child = command(config).stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().map_err(|e| cli_error(config, e))?;
+  child.stdin.take().expect("stdin is piped").write_all(sql.as_bytes()).map_err(|e| cli_error(config, e))?;
+  let output = child.wait_with_output().map_err(|e| cli_error(config, e))?;
+  if output.status.success() {
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+  } else {
+    Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+  }
+}
+
+fn query(db_config: &meta::DatabaseConfig, sql: &str) -> Result<Vec<Vec<Option<String>>>, String> {
+  run(db_config, sql).map(|output| parse_rows(&output))
+}
+
+fn parse_rows(output: &str) -> Vec<Vec<Option<String>>> {
+  output.lines().filter(|l| !l.is_empty())
+    .map(|l| l.split(FIELD_SEPARATOR).map(|f| if f == NULL_VALUE { None } else { Some(f.to_string()) }).collect())
+    .collect()
+}
+
+fn text(row: &[Option<String>], i: usize) -> String {
+  row.get(i).cloned().flatten().unwrap_or_default()
+}
+
+// replaces the rows of a table with CSV written by fill, which streams straight into the command line.
+// Closing stdin ends the COPY and commits it, so when fill fails the command line is killed while stdin is
+// still open and the transaction never commits
+pub fn copy_in(db_config: &meta::DatabaseConfig, table: &meta::Table, fill: &mut dyn FnMut(&mut dyn Write) -> Result<u64, String>) -> Result<(), String> {
+  let config = duckdb_config(db_config);
+  let columns = table.columns().iter().map(|c| quote_ident(&c.name())).collect::<Vec<String>>().join(", ");
+  let sql = format!("BEGIN TRANSACTION; DELETE FROM {table}; COPY {table} ({columns}) FROM '/dev/stdin' (FORMAT csv, NULLSTR '{null}'); COMMIT;",
+                    table = qualified_table_name(&TableRef::of(table)), columns = columns, null = NULL_VALUE);
+  let mut child = command(config).arg("-c").arg(sql).stdin(Stdio::piped()).stdout(Stdio::null()).stderr(Stdio::piped()).spawn().map_err(|e| cli_error(config, e))?;
+  let mut stdin = child.stdin.take().expect("stdin is piped");
+  if let Err(e) = fill(&mut stdin) {
+    let _ = child.kill();
+    let _ = child.wait();
+    return Err(format!("I couldn't load {} because: {}\nNothing was changed.", table.name(), e));
+  }
+  drop(stdin);
+  let output = child.wait_with_output().map_err(|e| cli_error(config, e))?;
+  if output.status.success() {
+    Ok(())
+  } else {
+    Err(format!("I couldn't load {} because: {}", table.name(), String::from_utf8_lossy(&output.stderr).trim()))
+  }
+}
+
+fn introspection_error(e: String) -> String {
+  format!("I couldn't read the database structure because: {}", e)
+}
+
+type TableKey = (String, String);
+
+// read from the duckdb_ catalog functions in one run of the command line, each row is tagged with
+// the query it came from. Indexes aren't read, duckdb only reports them as expressions. Only the app's
+// schemas are read when there are some, along with the current one where a flattened model's tables are
+fn introspect(schemas: Option<&[String]>, db_config: &meta::DatabaseConfig) -> Result<meta::Database, String> {
+  let in_schemas = schemas.map_or("".to_string(), |s| {
+    format!(" AND schema_name IN (current_schema(){})", s.iter().map(|s| format!(", {}", quote_literal(s))).collect::<String>())
+  });
+  let rows = query(db_config, &format!("
+    SELECT 'default', current_schema();
+    SELECT 'schema', schema_name FROM duckdb_schemas() WHERE database_name = current_database() AND NOT internal{in_schemas} ORDER BY 2;
+    SELECT 'table', schema_name, table_name FROM duckdb_tables() WHERE database_name = current_database() AND NOT internal{in_schemas} ORDER BY 2, 3;
+    SELECT 'column', schema_name, table_name, column_name, data_type, is_nullable::varchar, column_default
+      FROM duckdb_columns() WHERE database_name = current_database() AND NOT internal{in_schemas} ORDER BY 2, 3, column_index;
+    SELECT 'constraint', schema_name, table_name, constraint_name, constraint_type, array_to_string(constraint_column_names, ','), referenced_table
+      FROM duckdb_constraints() WHERE database_name = current_database() AND constraint_type <> 'NOT NULL'{in_schemas} ORDER BY 2, 3, constraint_index;
+  ", in_schemas = in_schemas)).map_err(introspection_error)?;
+  let mut default_schema = "".to_string();
+  let mut schemas: Vec<String> = Vec::new();
+  let mut table_keys: Vec<TableKey> = Vec::new();
+  let mut columns: BTreeMap<TableKey, Vec<meta::Column>> = BTreeMap::new();
+  let mut constraints: BTreeMap<TableKey, Vec<meta::Constraint>> = BTreeMap::new();
+  rows.iter().for_each(|r| match text(r, 0).as_str() {
+    "default" => default_schema = text(r, 1)
+  , "schema" => schemas.push(text(r, 1))
+  , "table" => table_keys.push((text(r, 1), text(r, 2)))
+  , "column" => {
+      let sql_type = parse_data_type(&text(r, 4));
+      let column = meta::Column::introspected(&text(r, 3), sql_type_to_column_type(&sql_type), sql_type, text(r, 5) == "true", r[6].clone());
+      columns.entry((text(r, 1), text(r, 2))).or_default().push(column);
+    }
+  , "constraint" => {
+      let kind = match (text(r, 4).as_str(), &r[6]) {
+        ("PRIMARY KEY", _) => meta::ConstraintKind::PrimaryKey
+      , ("UNIQUE", _) => meta::ConstraintKind::Unique
+      , ("FOREIGN KEY", Some(referenced)) => meta::ConstraintKind::ForeignKey(referenced.clone())
+      , ("CHECK", _) => meta::ConstraintKind::Check
+      , _ => meta::ConstraintKind::Other
+      };
+      let constraint = meta::Constraint{ name: text(r, 3), kind, columns: text(r, 5).split(',').filter(|c| !c.is_empty()).map(|c| c.to_string()).collect() };
+      constraints.entry((text(r, 1), text(r, 2))).or_default().push(constraint);
+    }
+  , _ => {}
+  });
+  let tables = table_keys.into_iter().map(|key| {
+    meta::Table::new(&key.0, &key.1, columns.remove(&key).unwrap_or_default()).with_keys(constraints.remove(&key).unwrap_or_default(), vec!())
+  }).collect();
+  Ok(meta::Database::new(tables).with_schemas(schemas, &default_schema))
+}
+
+// duckdb names types in capitals with their arguments, VARCHAR or DECIMAL(10,2)
+fn parse_data_type(data_type: &str) -> meta::SqlType {
+  let (name, arguments) = match data_type.find('(') {
+    Some(i) => (&data_type[..i], data_type[i + 1..].trim_end_matches(')'))
+  , None => (data_type, "")
+  };
+  let numbers: Vec<i32> = arguments.split(',').filter_map(|a| a.trim().parse().ok()).collect();
+  meta::SqlType{ name: name.trim().to_lowercase(), length: None, precision: numbers.first().copied(), scale: numbers.get(1).copied() }
+}
+
+// the types gimbal creates map back to their leaf types, along with the close relatives
+// someone may have used by hand. Anything else is kept as its declaration
+fn sql_type_to_column_type(sql_type: &meta::SqlType) -> meta::ColumnType {
+  match sql_type.name.as_str() {
+    "varchar" | "text" | "char" | "bpchar" | "string" => meta::ColumnType::Known(internal::LeafType::String)
+  , "tinyint" | "smallint" | "integer" | "bigint" | "hugeint" => meta::ColumnType::Known(internal::LeafType::Int)
+  , "float" | "double" | "real" | "decimal" => meta::ColumnType::Known(internal::LeafType::Float)
+  , "boolean" => meta::ColumnType::Known(internal::LeafType::Bool)
+  , "uuid" => meta::ColumnType::Known(internal::LeafType::Id)
+  , _ => meta::ColumnType::Unknown(sql_type.declaration())
+  }
+}
+
+fn operation_ddl(operation: &Operation) -> Result<String, String> {
+  match operation {
+    Operation::CreateSchema(schema) => Ok(format!("CREATE SCHEMA IF NOT EXISTS {}", quote_ident(schema)))
+  , Operation::DropSchema(schema) => Ok(format!("DROP SCHEMA {}", quote_ident(schema)))
+  , Operation::Staged(_) => Err(format!("I can only stage changes to run without downtime on postgres, so I can't {} on duckdb.", operation.describe()))
+  , Operation::CreateTable(table) => Ok(table_ddl(table))
+  , Operation::DropTable(table) => Ok(format!("DROP TABLE {}", qualified_table_name(&TableRef::of(table))))
+  , Operation::MoveTable(table, to) => Ok(move_table_ddl(table, &TableRef::new(to, &table.name)))
+  , Operation::AddColumn(table, column) => Ok(format!("ALTER TABLE {} ADD COLUMN {}", qualified_table_name(table), column_ddl(column)))
+  , Operation::DropColumn(table, column) => Ok(format!("ALTER TABLE {} DROP COLUMN {}", qualified_table_name(table), quote_ident(&column.name())))
+  , Operation::AlterColumnType(change) => Ok(alter_type_ddl(change))
+  , Operation::RenameColumn(table, from, to) => {
+      Ok(format!("ALTER TABLE {} RENAME COLUMN {} TO {}", qualified_table_name(table), quote_ident(from), quote_ident(to)))
+    }
+  , Operation::AddForeignKey(_, _) | Operation::DropForeignKey(_, _) => {
+      Err(format!("duckdb only declares foreign keys when it creates a table, so I can't {}.", operation.describe()))
+    }
+  , Operation::CreateIndex(table, index) => {
+      let columns = index.columns.iter().map(|c| quote_ident(c)).collect::<Vec<String>>().join(", ");
+      Ok(format!("CREATE {}INDEX {} ON {} ({})", if index.unique { "UNIQUE " } else { "" }, quote_ident(&index.name), qualified_table_name(table), columns))
+    }
+  , Operation::DropIndex(table, index) => Ok(format!("DROP INDEX {}", qualified_table_name(&TableRef::new(&table.schema, &index.name))))
+  }
+}
+
+// duckdb can't move a table between schemas so it is copied across and the original dropped
+fn move_table_ddl(from: &TableRef, to: &TableRef) -> String {
+  format!("CREATE TABLE {} AS SELECT * FROM {};\nDROP TABLE {}", qualified_table_name(to), qualified_table_name(from), qualified_table_name(from))
+}
+
+fn alter_type_ddl(change: &ColumnChange) -> String {
+  let type_ddl = change.declaration(column_type_ddl);
+  let column = quote_ident(&change.column);
+  format!("ALTER TABLE {} ALTER COLUMN {} TYPE {} USING CAST({} AS {})", qualified_table_name(&change.table), column, type_ddl, column, type_ddl)
+}
+
+fn table_ddl(table: &meta::Table) -> String {
+  let columns = table.columns().iter().map(column_ddl).collect::<Vec<String>>().join(", ");
+  format!("CREATE TABLE {} ({})", qualified_table_name(&TableRef::of(table)), columns)
+}
+
+fn qualified_table_name(table: &TableRef) -> String {
+  if table.schema.is_empty() {
+    quote_ident(&table.name)
+  } else {
+    format!("{}.{}", quote_ident(&table.schema), quote_ident(&table.name))
+  }
+}
+
+fn column_ddl(column: &meta::Column) -> String {
+  format!("{} {}", quote_ident(&column.name()), column_type_ddl(&column.data_type()))
+}
+
+fn column_type_ddl(data_type: &meta::ColumnType) -> String {
+  match data_type {
+    meta::ColumnType::Known(internal::LeafType::String) => "varchar".to_string()
+  , meta::ColumnType::Known(internal::LeafType::Int) => "integer".to_string()
+  , meta::ColumnType::Known(internal::LeafType::Float) => "double".to_string()
+  , meta::ColumnType::Known(internal::LeafType::Bool) => "boolean".to_string()
+  , meta::ColumnType::Known(internal::LeafType::Id) => "uuid".to_string()
+  , meta::ColumnType::Unknown(declaration) => declaration.clone()
+  }
+}
+
+fn quote_ident(ident: &str) -> String {
+  format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+fn quote_literal(value: &str) -> String {
+  format!("'{}'", value.replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::database::drivers::conformance;
+  use crate::database::integration::{self, DiffDiagnosis};
+  use crate::database::naming::NamingStrategy;
+  use crate::database::operations;
+  use crate::lang::ast_builder;
+
+  fn test_config(name: &str) -> meta::DatabaseConfig {
+    let path = std::env::temp_dir().join(format!("gimbal_{}_{}.duckdb", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    meta::DatabaseConfig::Duckdb(meta::DuckdbConfig{
+      path: path.to_string_lossy().to_string(), cli: "duckdb".to_string(), schema_mapping: meta::SchemaMapping::PerNamespace, naming: NamingStrategy::default()
+    })
+  }
+
+  #[test]
+  fn test_reverse_ddl() {
+    let moved = Operation::MoveTable(TableRef::new("main", "User"), "db".to_string());
+    assert_eq!(operation_ddl(&moved).unwrap(), "CREATE TABLE \"db\".\"User\" AS SELECT * FROM \"main\".\"User\";\nDROP TABLE \"main\".\"User\"");
+    assert_eq!(operation_ddl(&moved.reverse()).unwrap(), "CREATE TABLE \"main\".\"User\" AS SELECT * FROM \"db\".\"User\";\nDROP TABLE \"db\".\"User\"");
+    let retyped = Operation::AlterColumnType(ColumnChange{
+      table: TableRef::new("db", "User"), column: "age".to_string(), database_table: None
+    , from: meta::ColumnType::Unknown("decimal(10,2)".to_string()), to: meta::ColumnType::Known(internal::LeafType::Int)
+    });
+    assert_eq!(operation_ddl(&retyped).unwrap(), r#"ALTER TABLE "db"."User" ALTER COLUMN "age" TYPE integer USING CAST("age" AS integer)"#);
+    assert_eq!(operation_ddl(&retyped.reverse()).unwrap(), r#"ALTER TABLE "db"."User" ALTER COLUMN "age" TYPE decimal(10,2) USING CAST("age" AS decimal(10,2))"#);
+  }
+
+  #[test]
+  fn test_sql_types() {
+    assert_eq!(sql_type_to_column_type(&parse_data_type("VARCHAR")), meta::ColumnType::Known(internal::LeafType::String));
+    assert_eq!(sql_type_to_column_type(&parse_data_type("BIGINT")), meta::ColumnType::Known(internal::LeafType::Int));
+    assert_eq!(sql_type_to_column_type(&parse_data_type("DECIMAL(10,2)")), meta::ColumnType::Known(internal::LeafType::Float));
+    assert_eq!(sql_type_to_column_type(&parse_data_type("UUID")), meta::ColumnType::Known(internal::LeafType::Id));
+    assert_eq!(sql_type_to_column_type(&parse_data_type("INTEGER[]")), meta::ColumnType::Unknown("integer[]".to_string()));
+    // every leaf type survives a round trip through its ddl
+    internal::LeafType::all().into_iter().chain(vec!(internal::LeafType::Id)).for_each(|l| {
+      let declared = column_type_ddl(&meta::ColumnType::Known(l.clone()));
+      assert_eq!(sql_type_to_column_type(&parse_data_type(&declared.to_uppercase())), meta::ColumnType::Known(l));
+    });
+  }
+
+  #[test]
+  fn test_parse_rows() {
+    let output = format!("a{}\\N{}1\n\nb{}c{}2\n", FIELD_SEPARATOR, FIELD_SEPARATOR, FIELD_SEPARATOR, FIELD_SEPARATOR);
+    assert_eq!(parse_rows(&output), vec!(
+      vec!(Some("a".to_string()), None, Some("1".to_string()))
+    , vec!(Some("b".to_string()), Some("c".to_string()), Some("2".to_string()))
+    ));
+  }
+
+  // runs only when the duckdb command line is installed
+  #[test]
+  fn test_conformance() {
+    if Command::new("duckdb").arg("-version").output().is_err() {
+      return;
+    }
+    conformance::check(&DuckdbDriver, &test_config("conformance"));
+  }
+
+  // runs only when the duckdb command line is installed
+  #[test]
+  fn test_pipeline() {
+    if Command::new("duckdb").arg("-version").output().is_err() {
+      return;
+    }
+    let code = r#"
+app database
+
+namespace db where
+
+struct persists Agent
+
+name:: Agent -> String
+age:: Agent -> Int"#;
+
+    let ast = ast_builder::build(code).unwrap();
+    let db_config = test_config("pipeline");
+    let driver = DuckdbDriver;
+    let diffs = integration::diagnose_db_diffs(&ast, &db_config).unwrap();
+    driver.execute_changes(&driver.changes(&operations::plan(&diffs), &db_config).unwrap(), None, &db_config).unwrap();
+    let diffs = integration::diagnose_db_diffs(&ast, &db_config).unwrap();
+    assert!(diffs[0].diff_diagnosis().iter().all(|d| *d == DiffDiagnosis::NoDiff));
+
+    let table = diffs[0].db_table();
+    copy_in(&db_config, table, &mut |sink| sink.write_all(b"Ann,\\N\n").map(|_| 1).map_err(|e| e.to_string())).unwrap();
+    assert_eq!(query(&db_config, "SELECT name, age FROM db.\"Agent\";").unwrap(), vec!(vec!(Some("Ann".to_string()), None)));
+    let truncated = copy_in(&db_config, table, &mut |sink| {
+      sink.write_all(b"Bob,").map_err(|e| e.to_string())?;
+      Err("the source went away".to_string())
+    });
+    assert!(truncated.unwrap_err().contains("the source went away"));
+    assert_eq!(query(&db_config, "SELECT name, age FROM db.\"Agent\";").unwrap(), vec!(vec!(Some("Ann".to_string()), None)));
+
+    let history = migrations::HistoryChange::Record{ version: 1, checksum: "abc".to_string(), started: std::time::Instant::now() };
+    driver.execute_changes(&meta::DatabaseChange::SqlDb(vec!("DELETE FROM db.\"Agent\"".to_string())), Some(&history), &db_config).unwrap();
+    assert_eq!(driver.applied_migrations(&db_config).unwrap()[0].checksum, "abc");
+    assert!(query(&db_config, "SELECT name FROM db.\"Agent\";").unwrap().is_empty());
+  }
+}
@@ -0,0 +1,3 @@
+ALTER TABLE `db_Agent` MODIFY COLUMN `age` text NOT NULL DEFAULT ('0');
+ALTER TABLE `db_Agent` DROP COLUMN `active`;
+DROP TABLE `db_Team`;
@@ -0,0 +1,3 @@
+CREATE TABLE `db_Team` (`rating` double, `title` varchar(255));
+ALTER TABLE `db_Agent` ADD COLUMN `active` tinyint(1);
+ALTER TABLE `db_Agent` MODIFY COLUMN `age` int NOT NULL DEFAULT '0';
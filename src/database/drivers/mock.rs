@@ -1,71 +1,440 @@
-use std::collections::HashMap;
-use std::error;
+use std::sync::MutexGuard;
+use std::time::Duration;
 
+use crate::database::drivers::{self, DatabaseDriver};
+use crate::database::expand_contract::{self, Step};
 use crate::database::meta;
-use crate::database::integration::{DbDiff, DiffDiagnosis};
+use crate::database::migrations;
+use crate::database::operations::{ColumnChange, Operation, TableRef};
+use crate::database::seed;
 
-static mut TABLES: Vec<meta::Table> = Vec::new();
+pub struct MockDriver;
 
+impl DatabaseDriver for MockDriver {
+  fn dialect(&self) -> &'static str {
+    "mock"
+  }
 
-pub fn execute_changes(db_changes: &meta::DatabaseChange, db_config: &meta::DatabaseConfig) -> Result<(), String> {
-  Ok(())
+  // the mock has no default schema, every table a test puts in it could be where the model's are
+  fn introspect(&self, _schemas: Option<&[String]>, db_config: &meta::DatabaseConfig) -> Result<meta::Database, String> {
+    introspect(db_config)
+  }
+
+  // the mock runs the operations themselves
+  fn changes(&self, operations: &[Operation], _db_config: &meta::DatabaseConfig) -> Result<meta::DatabaseChange, String> {
+    Ok(meta::DatabaseChange::MockDb(operations.to_vec()))
+  }
+
+  fn execute_changes(&self, db_changes: &meta::DatabaseChange, history: Option<&migrations::HistoryChange>, db_config: &meta::DatabaseConfig) -> Result<(), String> {
+    execute_changes(db_changes, history, db_config)
+  }
+
+  fn applied_migrations(&self, db_config: &meta::DatabaseConfig) -> Result<Vec<migrations::AppliedMigration>, String> {
+    Ok(state(db_config).history.clone())
+  }
+
+  fn upsert(&self, rows: &[seed::Row], db_config: &meta::DatabaseConfig) -> Result<(), String> {
+    upsert(rows, db_config)
+  }
+
+  fn lock<'a>(&self, app: &str, holder: &str, timeout: Duration, db_config: &'a meta::DatabaseConfig) -> Result<Box<dyn migrations::HeldLock + 'a>, String> {
+    let taken = drivers::wait_for_lock(timeout, || {
+      let mut state = state(db_config);
+      Ok(if state.lock.is_none() { state.lock = Some(holder.to_string()); Some(()) } else { None })
+    })?;
+    match taken {
+      Some(()) => Ok(Box::new(MockLock(db_config)))
+    , None => Err(drivers::lock_error(app, timeout, state(db_config).lock.as_deref().unwrap_or_default()))
+    }
+  }
 }
 
-pub fn diffs_to_changes(db_diffs: &Vec<DbDiff>, db_config: &meta::DatabaseConfig) -> meta::DatabaseChange {
-  //just going to copy all the tables from the diff for the purposes of mock
-  let tables: Vec<meta::Table> = db_diffs.iter().map(|d| copy_table(d.db_table())).collect();
-  meta::DatabaseChange::MockDb(meta::DatabaseConfig::MockDb(meta::MockDbConfig{ tables }))
+struct MockLock<'a>(&'a meta::DatabaseConfig);
+
+impl migrations::HeldLock for MockLock<'_> {}
+
+impl Drop for MockLock<'_> {
+  fn drop(&mut self) {
+    state(self.0).lock = None;
+  }
 }
 
-pub fn db_table_for_ast_table(db_config: &meta::DatabaseConfig, ast_table: &meta::Table) -> Option<meta::Table>  {
+fn state(db_config: &meta::DatabaseConfig) -> MutexGuard<'_, meta::MockState> {
   match db_config {
-    meta::DatabaseConfig::MockDb(config) => {
-      let t_copy = copy_tables(&config.tables);
-      Some(t_copy.into_iter().find(|t| t.name() == ast_table.name()))?
-    }
+    meta::DatabaseConfig::MockDb(config) => config.state.lock().expect("a test panicked while changing the mock database")
   , _ => unreachable!()
   }
 }
 
-fn copy_tables(tables: &Vec<meta::Table>) -> Vec<meta::Table> {
-  tables.iter().map(|t| copy_table(t)).collect()
+fn introspect(db_config: &meta::DatabaseConfig) -> Result<meta::Database, String> {
+  let state = state(db_config);
+  Ok(meta::Database::new(state.tables.clone()).with_empty_schemas(state.schemas.clone()))
 }
 
-fn copy_table(table: &meta::Table) -> meta::Table {
-  meta::Table::new("schema", &table.name(), copy_columns(table.columns()))
+// the operations are applied to a copy which replaces the database only if they all succeed,
+// the way a transaction would, along with a migration's history change
+fn execute_changes(db_changes: &meta::DatabaseChange, history: Option<&migrations::HistoryChange>, db_config: &meta::DatabaseConfig) -> Result<(), String> {
+  let operations = match db_changes {
+    meta::DatabaseChange::MockDb(operations) => operations
+  , meta::DatabaseChange::SqlDb(_) => return Err("The mock database can't run SQL.".to_string())
+  };
+  let mut state = state(db_config);
+  let mut changed = state.clone();
+  for (i, operation) in operations.iter().enumerate() {
+    apply(&mut changed, operation).map_err(|e| format!("I couldn't run operation {} because: {}\n{:?}\nNothing was changed.", i + 1, e, operation))?;
+  }
+  match history.map(|h| (h.version(), h.applied())) {
+    Some((_, Some(migration))) => changed.history.push(migration)
+  , Some((version, None)) => changed.history.retain(|m| m.version != version)
+  , None => ()
+  }
+  *state = changed;
+  Ok(())
 }
 
-fn copy_columns(columns: &Vec<meta::Column>) -> Vec<meta::Column> {
-  columns.iter().map(|c| copy_column(c)).collect()
+fn apply(state: &mut meta::MockState, operation: &Operation) -> Result<(), String> {
+  match operation {
+    Operation::CreateSchema(schema) if state.schemas.contains(schema) => Err(format!("schema {} already exists", schema))
+  , Operation::CreateSchema(schema) => {
+      state.schemas.push(schema.clone());
+      state.schemas.sort();
+      Ok(())
+    }
+  , Operation::DropSchema(schema) => {
+      if state.tables.iter().any(|t| &t.schema() == schema) {
+        return Err(format!("schema {} still has tables", schema));
+      }
+      let count = state.schemas.len();
+      state.schemas.retain(|s| s != schema);
+      if state.schemas.len() == count { Err(format!("schema {} does not exist", schema)) } else { Ok(()) }
+    }
+  , Operation::CreateTable(table) => {
+      if !table.schema().is_empty() && !state.schemas.contains(&table.schema()) {
+        return Err(format!("schema {} does not exist", table.schema()));
+      }
+      if find_table(state, &TableRef::of(table)).is_ok() {
+        return Err(format!("table {} already exists", table.name()));
+      }
+      state.tables.push(table.clone());
+      Ok(())
+    }
+  , Operation::DropTable(table) => {
+      let i = find_table(state, &TableRef::of(table))?;
+      state.tables.remove(i);
+      Ok(())
+    }
+  , Operation::MoveTable(table, to) => {
+      if !to.is_empty() && !state.schemas.contains(to) {
+        return Err(format!("schema {} does not exist", to));
+      }
+      let i = find_table(state, table)?;
+      let moved = &state.tables[i];
+      state.tables[i] = meta::Table::new(to, &table.name, moved.columns().clone()).with_keys(moved.constraints().clone(), moved.indexes().clone());
+      Ok(())
+    }
+  , Operation::AddColumn(table, column) => {
+      change_table(state, table, |columns, _, _| {
+        if columns.iter().any(|c| c.name() == column.name()) {
+          return Err(format!("column {} already exists", column.name()));
+        }
+        columns.push(column.clone());
+        Ok(())
+      })
+    }
+  , Operation::DropColumn(table, column) => {
+      change_table(state, table, |columns, _, _| {
+        columns.remove(find_column(columns, &column.name())?);
+        Ok(())
+      })
+    }
+  , Operation::AlterColumnType(ColumnChange{ table, column, to, .. }) => {
+      change_table(state, table, |columns, _, _| {
+        let i = find_column(columns, column)?;
+        columns[i] = columns[i].clone().with_data_type(to.clone());
+        Ok(())
+      })
+    }
+  , Operation::RenameColumn(table, from, to) => {
+      change_table(state, table, |columns, _, _| {
+        if columns.iter().any(|c| &c.name() == to) {
+          return Err(format!("column {} already exists", to));
+        }
+        let i = find_column(columns, from)?;
+        columns[i] = columns[i].clone().with_name(to);
+        Ok(())
+      })
+    }
+  , Operation::AddForeignKey(table, constraint) => {
+      change_table(state, table, |_, constraints, _| {
+        if constraints.iter().any(|k| k.name == constraint.name) {
+          return Err(format!("constraint {} already exists", constraint.name));
+        }
+        constraints.push(constraint.clone());
+        Ok(())
+      })
+    }
+  , Operation::DropForeignKey(table, constraint) => {
+      change_table(state, table, |_, constraints, _| {
+        let i = constraints.iter().position(|k| k.name == constraint.name).ok_or_else(|| format!("constraint {} does not exist", constraint.name))?;
+        constraints.remove(i);
+        Ok(())
+      })
+    }
+  , Operation::CreateIndex(table, index) => {
+      change_table(state, table, |_, _, indexes| {
+        if indexes.iter().any(|i| i.name == index.name) {
+          return Err(format!("index {} already exists", index.name));
+        }
+        indexes.push(index.clone());
+        Ok(())
+      })
+    }
+  , Operation::DropIndex(table, index) => {
+      change_table(state, table, |_, _, indexes| {
+        let i = indexes.iter().position(|i| i.name == index.name).ok_or_else(|| format!("index {} does not exist", index.name))?;
+        indexes.remove(i);
+        Ok(())
+      })
+    }
+  , Operation::Staged(step) => apply_step(state, step)
+  }
 }
 
-fn copy_column(column: &meta::Column) -> meta::Column {
-  meta::Column::new(&column.name(), column.data_type())
+// steps that only move data or check it have nothing to change in the structure, the swap puts
+// the shadow in the column's place with the column's type changed
+fn apply_step(state: &mut meta::MockState, step: &Step) -> Result<(), String> {
+  match step {
+    Step::SyncColumn(change) | Step::UnsyncColumn(change) | Step::Backfill(change) => {
+      change_table(state, &change.table, |columns, _, _| {
+        find_column(columns, &change.column)?;
+        find_column(columns, &expand_contract::shadow_name(change)).map(|_| ())
+      })
+    }
+  , Step::SwapColumn(change) => {
+      change_table(state, &change.table, |columns, _, indexes| {
+        let shadow = columns.remove(find_column(columns, &expand_contract::shadow_name(change))?);
+        let i = find_column(columns, &change.column)?;
+        columns[i] = columns[i].clone().with_data_type(shadow.data_type());
+        indexes.retain(|index| !index.columns.contains(&change.column));
+        for (index, shadow_index) in expand_contract::shadow_indexes(change) {
+          if let Some(found) = indexes.iter_mut().find(|i| i.name == shadow_index.name) {
+            *found = index;
+          }
+        }
+        Ok(())
+      })
+    }
+  , Step::CreateIndex(table, index) => apply(state, &Operation::CreateIndex(table.clone(), index.clone()))
+  , Step::DropIndex(table, index) => apply(state, &Operation::DropIndex(table.clone(), index.clone()))
+  , Step::AddForeignKey(table, constraint) => apply(state, &Operation::AddForeignKey(table.clone(), constraint.clone()))
+  , Step::ValidateForeignKey(table, constraint) => {
+      change_table(state, table, |_, constraints, _| {
+        constraints.iter().find(|k| k.name == constraint.name).map(|_| ()).ok_or_else(|| format!("constraint {} does not exist", constraint.name))
+      })
+    }
+  }
 }
 
-fn tables_for_entities(entities: Vec<String>) -> HashMap<String, Option<&'static meta::Table>> {
-  unsafe {
-   entities.into_iter().map(|e| (e.clone(), TABLES.iter().find(|t| t.name() == e).clone())).collect()
+// rows are written to a copy too, each one's columns must be in its table
+fn upsert(rows: &[seed::Row], db_config: &meta::DatabaseConfig) -> Result<(), String> {
+  let mut state = state(db_config);
+  let mut changed = state.clone();
+  for (i, row) in rows.iter().enumerate() {
+    upsert_row(&mut changed, row).map_err(|e| format!("I couldn't write row {} because: {}\n{:?}\nNothing was changed.", i + 1, e, row))?;
   }
+  *state = changed;
+  Ok(())
 }
 
-fn tables() -> Vec<meta::Table> {
-  unsafe {
-    TABLES.iter().map(|t| meta::Table::new(&t.schema(), &t.name(), vec!())).collect::<Vec<meta::Table>>()
+fn upsert_row(state: &mut meta::MockState, row: &seed::Row) -> Result<(), String> {
+  let columns = state.tables[find_table(state, &row.table)?].columns().clone();
+  for (column, _) in row.key.iter().chain(row.values.iter()) {
+    find_column(&columns, column)?;
+  }
+  let table_rows = state.rows.entry(row.table.to_string()).or_default();
+  match table_rows.iter_mut().find(|r| row.key.iter().all(|k| r.contains(k))) {
+    Some(existing) => {
+      existing.retain(|(c, _)| !row.values.iter().any(|(column, _)| c == column));
+      existing.extend(row.values.iter().cloned());
+    }
+  , None => table_rows.push(row.key.iter().chain(row.values.iter()).cloned().collect())
   }
+  Ok(())
 }
 
-fn create_missing(table_list: &HashMap<String, Option<&meta::Table>>) -> Result<(), Box<dyn error::Error>> {
+fn find_table(state: &meta::MockState, table: &TableRef) -> Result<usize, String> {
+  state.tables.iter().position(|t| t.schema() == table.schema && t.name() == table.name).ok_or_else(|| format!("table {} does not exist", table.name))
+}
 
-  table_list.iter().for_each(|(k, v)| {
-    match v {
-      None => unsafe{ 
-        TABLES.push(meta::Table::new("", &k.clone(), vec!())) 
-      }
-    , Some(table) => {}
-    }
-  });
+fn find_column(columns: &[meta::Column], name: &str) -> Result<usize, String> {
+  columns.iter().position(|c| c.name() == name).ok_or_else(|| format!("column {} does not exist", name))
+}
 
+type TableParts = (Vec<meta::Column>, Vec<meta::Constraint>, Vec<meta::Index>);
+
+fn change_table<F: FnOnce(&mut Vec<meta::Column>, &mut Vec<meta::Constraint>, &mut Vec<meta::Index>) -> Result<(), String>>(state: &mut meta::MockState, table: &TableRef, f: F) -> Result<(), String> {
+  let i = find_table(state, table)?;
+  let (mut columns, mut constraints, mut indexes): TableParts = (state.tables[i].columns().clone(), state.tables[i].constraints().clone(), state.tables[i].indexes().clone());
+  f(&mut columns, &mut constraints, &mut indexes)?;
+  state.tables[i] = meta::Table::new(&table.schema, &table.name, columns).with_keys(constraints, indexes);
   Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::database::drivers::conformance;
+  use crate::database::integration::{self, DiffDiagnosis};
+  use crate::database::naming::NamingStrategy;
+  use crate::database::operations;
+  use crate::lang::{ast_builder, internal};
+
+  const MODELS: &[&str] = &[r#"
+app database
+
+namespace db where
+
+struct persists Agent
+
+name:: Agent -> String
+age:: Agent -> Int"#, r#"
+app database
+
+namespace shop where
+
+struct persists Customer
+
+name:: Customer -> String
+rating:: Customer -> Float
+
+struct persists Order
+
+customer:: Order -> Customer
+paid:: Order -> Bool"#];
 
-}
\ No newline at end of file
+  fn no_diffs(ast: &crate::lang::ast::Application, db_config: &meta::DatabaseConfig) -> bool {
+    integration::diagnose_db_diffs(ast, db_config).unwrap().iter().all(|d| d.diff_diagnosis().iter().all(|d| *d == DiffDiagnosis::NoDiff))
+  }
+
+  // diagnose, plan, apply and diagnose again finds nothing left to do, and the reverse plan puts
+  // the database back the way it was
+  fn converges(code: &str, db_config: meta::DatabaseConfig) {
+    let ast = ast_builder::build(code).unwrap();
+    let before = introspect(&db_config).unwrap();
+    let planned = operations::plan(&integration::diagnose_db_diffs(&ast, &db_config).unwrap());
+    let driver = MockDriver;
+    driver.execute_changes(&driver.changes(&planned, &db_config).unwrap(), None, &db_config).unwrap();
+    assert!(no_diffs(&ast, &db_config), "{} did not converge", code);
+    driver.execute_changes(&driver.changes(&operations::reverse(&planned), &db_config).unwrap(), None, &db_config).unwrap();
+    assert_eq!(introspect(&db_config).unwrap().tables(), before.tables());
+  }
+
+  #[test]
+  fn test_converges() {
+    let drifted = || vec!(
+      meta::Table::new("", "db_Agent", vec!(meta::Column::new("name", internal::LeafType::Int), meta::Column::new("nickname", internal::LeafType::String)))
+    , meta::Table::new("public", "Customer", vec!(meta::Column::new("name", internal::LeafType::String)))
+    );
+    for code in MODELS {
+      converges(code, meta::DatabaseConfig::MockDb(meta::MockDbConfig::new(vec!())));
+      converges(code, meta::DatabaseConfig::MockDb(meta::MockDbConfig::new(drifted())));
+      let per_namespace = || meta::MockDbConfig{ schema_mapping: meta::SchemaMapping::PerNamespace, ..meta::MockDbConfig::new(drifted()) };
+      converges(code, meta::DatabaseConfig::MockDb(per_namespace()));
+      let naming = NamingStrategy{ pluralise: true, ..NamingStrategy::default() };
+      converges(code, meta::DatabaseConfig::MockDb(meta::MockDbConfig{ naming, ..per_namespace() }));
+    }
+  }
+
+  // every entity of a namespace reports its schema as missing, it is created once
+  #[test]
+  fn test_schema_created_once() {
+    let db_config = meta::DatabaseConfig::MockDb(meta::MockDbConfig{ schema_mapping: meta::SchemaMapping::PerNamespace, ..meta::MockDbConfig::new(vec!()) });
+    let ast = ast_builder::build(MODELS[1]).unwrap();
+    let planned = operations::plan(&integration::diagnose_db_diffs(&ast, &db_config).unwrap());
+    let schemas = planned.iter().filter(|o| matches!(o, Operation::CreateSchema(_))).map(|o| o.describe()).collect::<Vec<String>>();
+    assert_eq!(schemas, vec!("create schema shop"));
+    MockDriver.execute_changes(&MockDriver.changes(&planned, &db_config).unwrap(), None, &db_config).unwrap();
+    assert!(no_diffs(&ast, &db_config));
+  }
+
+  #[test]
+  fn test_failed_changes() {
+    let db_config = meta::DatabaseConfig::MockDb(meta::MockDbConfig::new(vec!()));
+    let changes = meta::DatabaseChange::MockDb(vec!(
+      Operation::CreateTable(meta::Table::new("", "a", vec!()))
+    , Operation::DropTable(meta::Table::new("", "b", vec!()))
+    ));
+    let error = MockDriver.execute_changes(&changes, None, &db_config).unwrap_err();
+    assert!(error.starts_with("I couldn't run operation 2 because: table b does not exist"));
+    assert!(introspect(&db_config).unwrap().tables().is_empty());
+  }
+
+  // keys, indexes and renames aren't planned from diffs yet, but run and reverse like the rest
+  #[test]
+  fn test_keys() {
+    let table = meta::Table::new("", "a", vec!(meta::Column::new("x", internal::LeafType::Id)));
+    let db_config = meta::DatabaseConfig::MockDb(meta::MockDbConfig::new(vec!(table.clone(), meta::Table::new("", "b", vec!()))));
+    let a = TableRef::of(&table);
+    let planned = vec!(
+      Operation::RenameColumn(a.clone(), "x".to_string(), "b_id".to_string())
+    , Operation::AddForeignKey(a.clone(), meta::Constraint{ name: "a_b".to_string(), kind: meta::ConstraintKind::ForeignKey("b".to_string()), columns: vec!("b_id".to_string()) })
+    , Operation::CreateIndex(a, meta::Index{ name: "a_b_id".to_string(), columns: vec!("b_id".to_string()), unique: true })
+    );
+    MockDriver.execute_changes(&MockDriver.changes(&planned, &db_config).unwrap(), None, &db_config).unwrap();
+    let changed = introspect(&db_config).unwrap();
+    let changed_a = changed.table("", "a").unwrap();
+    assert_eq!(changed_a.columns()[0].name(), "b_id");
+    assert_eq!((changed_a.constraints().len(), changed_a.indexes().len()), (1, 1));
+    MockDriver.execute_changes(&MockDriver.changes(&operations::reverse(&planned), &db_config).unwrap(), None, &db_config).unwrap();
+    assert_eq!(introspect(&db_config).unwrap().table("", "a").unwrap(), &table);
+  }
+
+  // staged changes converge too, the indexes on a retyped column end up on the swapped in shadow
+  #[test]
+  fn test_staged() {
+    let agent = meta::Table::new("", "db_Agent", vec!(meta::Column::new("name", internal::LeafType::Int)))
+      .with_keys(vec!(), vec!(meta::Index{ name: "agent_name".to_string(), columns: vec!("name".to_string()), unique: false }));
+    let db_config = meta::DatabaseConfig::MockDb(meta::MockDbConfig::new(vec!(agent.clone())));
+    let ast = ast_builder::build(MODELS[0]).unwrap();
+    let planned = operations::plan(&integration::diagnose_db_diffs(&ast, &db_config).unwrap());
+    let staged = expand_contract::stage(&planned).unwrap();
+    MockDriver.execute_changes(&MockDriver.changes(&staged, &db_config).unwrap(), None, &db_config).unwrap();
+    assert!(no_diffs(&ast, &db_config));
+    let changed = introspect(&db_config).unwrap();
+    let changed_agent = changed.table("", "db_Agent").unwrap();
+    assert_eq!(changed_agent.columns().iter().map(|c| c.name()).collect::<Vec<String>>(), vec!("name", "age"));
+    assert_eq!(changed_agent.indexes(), agent.indexes());
+    let unstaged = expand_contract::stage(&operations::reverse(&planned)).unwrap();
+    MockDriver.execute_changes(&MockDriver.changes(&unstaged, &db_config).unwrap(), None, &db_config).unwrap();
+    assert_eq!(introspect(&db_config).unwrap().tables(), &vec!(agent));
+  }
+
+  #[test]
+  fn test_conformance() {
+    conformance::check(&MockDriver, &meta::DatabaseConfig::MockDb(meta::MockDbConfig::new(vec!())));
+  }
+
+  #[test]
+  fn test_lock() {
+    let db_config = meta::DatabaseConfig::MockDb(meta::MockDbConfig::new(vec!()));
+    let held = MockDriver.lock("shop", "ann on build-1 (pid 7)", Duration::ZERO, &db_config).unwrap();
+    let error = MockDriver.lock("shop", "bob on build-2 (pid 9)", Duration::ZERO, &db_config).err().unwrap();
+    assert_eq!(error, "I couldn't take the migration lock for shop within 0 seconds because ann on build-1 (pid 7) holds it.");
+    drop(held);
+    assert!(MockDriver.lock("shop", "bob on build-2 (pid 9)", Duration::ZERO, &db_config).is_ok());
+  }
+
+  #[test]
+  fn test_history() {
+    let db_config = meta::DatabaseConfig::MockDb(meta::MockDbConfig::new(vec!()));
+    let history = migrations::HistoryChange::Record{ version: 1, checksum: "abc".to_string(), started: std::time::Instant::now() };
+    MockDriver.execute_changes(&meta::DatabaseChange::MockDb(vec!()), Some(&history), &db_config).unwrap();
+    assert_eq!(MockDriver.applied_migrations(&db_config).unwrap().iter().map(|m| (m.version, m.checksum.clone())).collect::<Vec<(i32, String)>>(),
+               vec!((1, "abc".to_string())));
+    let failing = meta::DatabaseChange::MockDb(vec!(Operation::DropSchema("missing".to_string())));
+    assert!(MockDriver.execute_changes(&failing, Some(&migrations::HistoryChange::Remove(1)), &db_config).is_err());
+    assert_eq!(MockDriver.applied_migrations(&db_config).unwrap().len(), 1);
+    MockDriver.execute_changes(&meta::DatabaseChange::MockDb(vec!()), Some(&migrations::HistoryChange::Remove(1)), &db_config).unwrap();
+    assert!(MockDriver.applied_migrations(&db_config).unwrap().is_empty());
+  }
+}
@@ -1,2 +1,113 @@
+#[cfg(test)]
+mod conformance;
+pub mod duckdb;
 pub mod mock;
-pub mod postgres;
\ No newline at end of file
+pub mod mysql;
+pub mod postgres;
+pub mod sqlite;
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::database::meta;
+use crate::database::migrations;
+use crate::database::operations::Operation;
+use crate::database::seed;
+
+// what the integration layer needs from a backend, each dialect in the configuration has one
+pub trait DatabaseDriver: Sync {
+  // the name configuration and migration headers use for this backend
+  fn dialect(&self) -> &'static str;
+
+  // reads every schema, or only the named ones and the default schema where a flattened model's tables are
+  fn introspect(&self, schemas: Option<&[String]>, db_config: &meta::DatabaseConfig) -> Result<meta::Database, String>;
+
+  // renders the operations in order, or says which one the dialect can't run
+  fn changes(&self, operations: &[Operation], db_config: &meta::DatabaseConfig) -> Result<meta::DatabaseChange, String>;
+
+  // runs the changes, and a migration's history change on the same connection in the transaction of the
+  // last statement where it runs in one, so a migration is never recorded without its changes
+  fn execute_changes(&self, db_changes: &meta::DatabaseChange, history: Option<&migrations::HistoryChange>, db_config: &meta::DatabaseConfig) -> Result<(), String>;
+
+  fn applied_migrations(&self, db_config: &meta::DatabaseConfig) -> Result<Vec<migrations::AppliedMigration>, String>;
+
+  // writes each row over the row with its key or as a new one where there is none, all of them or none
+  fn upsert(&self, rows: &[seed::Row], db_config: &meta::DatabaseConfig) -> Result<(), String>;
+
+  // takes the app's migration lock, waiting up to timeout for whoever holds it to let go
+  fn lock<'a>(&self, app: &str, holder: &str, timeout: Duration, db_config: &'a meta::DatabaseConfig) -> Result<Box<dyn migrations::HeldLock + 'a>, String>;
+}
+
+// how long to wait before trying a lock someone else holds again
+const LOCK_RETRY: Duration = Duration::from_millis(500);
+
+// tries to take a lock until it is taken or the timeout runs out, it is tried at least once
+pub fn wait_for_lock<T>(timeout: Duration, mut try_lock: impl FnMut() -> Result<Option<T>, String>) -> Result<Option<T>, String> {
+  let started = Instant::now();
+  loop {
+    if let Some(lock) = try_lock()? {
+      return Ok(Some(lock));
+    }
+    let waited = started.elapsed();
+    if waited >= timeout {
+      return Ok(None);
+    }
+    thread::sleep(LOCK_RETRY.min(timeout - waited));
+  }
+}
+
+pub fn lock_error(app: &str, timeout: Duration, holder: &str) -> String {
+  format!("I couldn't take the migration lock for {} within {} seconds because {} holds it.", app, timeout.as_secs(), holder)
+}
+
+// a lock kept in a table outlives a migrator that dies holding it, so whoever waits is told how to clear it
+pub fn lock_table_error(app: &str, timeout: Duration, holder: &str, acquired_at: &str) -> String {
+  format!("{} It was taken at {}, if that migration isn't running any more delete its row from {}.", lock_error(app, timeout, holder), acquired_at, migrations::LOCK_TABLE)
+}
+
+// the number of the last statement an earlier run of the migration committed, from the checksum it checkpointed for
+// each statement by number. A statement edited since it ran stops the migration, running the rest of an edited script
+// would leave the database like neither version of it
+pub fn resume_from(migration: &str, commands: &[String], checkpointed: &[(usize, String)]) -> Result<usize, String> {
+  for (n, checksum) in checkpointed {
+    if commands.get(n - 1).map(|c| migrations::checksum(c)).as_ref() != Some(checksum) {
+      return Err(format!("Statement {} of migration {} changed since it ran. Put it back the way it was, or undo what already ran by hand \
+                          and delete the migration's rows from {}.", n, migration, migrations::CHECKPOINT_TABLE));
+    }
+  }
+  Ok(checkpointed.iter().map(|(n, _)| *n).max().unwrap_or(0))
+}
+
+// a new backend registers here, and gets a DatabaseConfig variant naming its dialect
+static DRIVERS: &[&dyn DatabaseDriver] = &[&mock::MockDriver, &postgres::PostgresDriver, &sqlite::SqliteDriver, &mysql::MysqlDriver, &duckdb::DuckdbDriver];
+
+pub fn driver_for(db_config: &meta::DatabaseConfig) -> Result<&'static dyn DatabaseDriver, String> {
+  DRIVERS.iter()
+    .find(|d| d.dialect() == db_config.dialect())
+    .copied()
+    .ok_or_else(|| format!("I couldn't find a driver for {} databases.", db_config.dialect()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_registry() {
+    let mock_db_config = meta::DatabaseConfig::MockDb(meta::MockDbConfig::new(vec!()));
+    assert_eq!(driver_for(&mock_db_config).unwrap().dialect(), "mock");
+    let dialects = DRIVERS.iter().map(|d| d.dialect()).collect::<Vec<&str>>();
+    assert_eq!(dialects, vec!("mock", "postgres", "sqlite", "mysql", "duckdb"));
+  }
+
+  #[test]
+  fn test_resume_from() {
+    let commands = vec!("CREATE TABLE a (x int)".to_string(), "".to_string(), "CREATE TABLE b (y int)".to_string());
+    assert_eq!(resume_from("1", &commands, &[]), Ok(0));
+    let ran = vec!((1, migrations::checksum(&commands[0])), (3, migrations::checksum(&commands[2])));
+    assert_eq!(resume_from("1", &commands, &ran), Ok(3));
+    let edited = vec!("CREATE TABLE a (x bigint)".to_string(), "".to_string(), "CREATE TABLE b (y int)".to_string());
+    assert!(resume_from("1", &edited, &ran).unwrap_err().starts_with("Statement 1 of migration 1 changed"));
+    assert!(resume_from("1", &commands[..1], &ran).unwrap_err().starts_with("Statement 3 of migration 1 changed"));
+  }
+}
@@ -0,0 +1,469 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use mysql::{Conn, OptsBuilder, TxOpts};
+use mysql::prelude::Queryable;
+
+use crate::database::drivers::{self, DatabaseDriver};
+use crate::database::meta;
+use crate::database::migrations;
+use crate::database::operations::{ColumnChange, Operation};
+use crate::database::seed;
+use crate::lang::internal;
+
+pub struct MysqlDriver;
+
+impl DatabaseDriver for MysqlDriver {
+  fn dialect(&self) -> &'static str {
+    "mysql"
+  }
+
+  // every table of the app is in the configured database
+  fn introspect(&self, _schemas: Option<&[String]>, db_config: &meta::DatabaseConfig) -> Result<meta::Database, String> {
+    introspect(db_config)
+  }
+
+  fn changes(&self, operations: &[Operation], _db_config: &meta::DatabaseConfig) -> Result<meta::DatabaseChange, String> {
+    operations.iter().map(operation_ddl).collect::<Result<Vec<String>, String>>().map(meta::DatabaseChange::SqlDb)
+  }
+
+  fn execute_changes(&self, db_changes: &meta::DatabaseChange, history: Option<&migrations::HistoryChange>, db_config: &meta::DatabaseConfig) -> Result<(), String> {
+    execute_changes(db_changes, history, db_config)
+  }
+
+  fn applied_migrations(&self, db_config: &meta::DatabaseConfig) -> Result<Vec<migrations::AppliedMigration>, String> {
+    applied_migrations(db_config)
+  }
+
+  fn upsert(&self, rows: &[seed::Row], db_config: &meta::DatabaseConfig) -> Result<(), String> {
+    upsert(rows, db_config)
+  }
+
+  fn lock<'a>(&self, app: &str, _holder: &str, timeout: Duration, db_config: &'a meta::DatabaseConfig) -> Result<Box<dyn migrations::HeldLock + 'a>, String> {
+    lock(app, timeout, db_config)
+  }
+}
+
+// GET_LOCK belongs to the session that took it, so it is released when the connection closes
+struct NamedLock {
+  _session: Conn
+}
+
+impl migrations::HeldLock for NamedLock {}
+
+fn connect(db_config: &meta::DatabaseConfig) -> Result<Conn, String> {
+  let config = match db_config {
+    meta::DatabaseConfig::Mysql(c) => c
+  , _ => unreachable!()
+  };
+  let opts = OptsBuilder::new()
+    .ip_or_hostname(Some(&config.host))
+    .tcp_port(config.port)
+    .db_name(Some(&config.database))
+    .user(Some(&config.user))
+    .pass(config.password.as_ref().map(|p| p.expose()));
+  Conn::new(opts).map_err(|e| format!("I couldn't connect to {} on {}:{} because: {}", config.database, config.host, config.port, e))
+}
+
+// mysql commits every DDL statement as it runs so a failed migration can't be rolled back, instead each of
+// a migration's statements is checkpointed once it has run, by its number in the migration along with its checksum,
+// so running the migration again skips what already ran and catches a statement edited since. A migration's history change
+// is written in one transaction with the removal of its checkpoint, on the same connection once the last statement has run
+fn execute_changes(db_changes: &meta::DatabaseChange, history: Option<&migrations::HistoryChange>, db_config: &meta::DatabaseConfig) -> Result<(), String> {
+  let mut connection = connect(db_config)?;
+  let commands = db_changes.commands();
+  let key = history.map(|h| h.checkpoint_key());
+  let checkpoint_error = |e: mysql::Error| format!("I couldn't keep track of the migration's progress because: {}", e);
+  let done = match &key {
+    Some(key) => {
+      connection.query_drop(format!("CREATE TABLE IF NOT EXISTS {} (migration varchar(32), statement int, checksum char(64) NOT NULL, PRIMARY KEY (migration, statement))",
+                                    quote_ident(migrations::CHECKPOINT_TABLE)))
+        .map_err(checkpoint_error)?;
+      let checkpointed = connection.exec::<(usize, String), _, _>(format!("SELECT statement, checksum FROM {} WHERE migration = ? ORDER BY statement", quote_ident(migrations::CHECKPOINT_TABLE)), (key,))
+        .map_err(checkpoint_error)?;
+      drivers::resume_from(key, commands, &checkpointed)?
+    }
+  , None => 0
+  };
+  for (i, statement) in commands.iter().enumerate().skip(done).filter(|(_, c)| !c.trim().is_empty()) {
+    let kept = commands[..i].iter().filter(|c| !c.trim().is_empty()).count();
+    connection.query_drop(statement.as_str()).map_err(|e| statement_error(i + 1, statement, &e, kept))?;
+    if let Some(key) = &key {
+      connection.exec_drop(format!("INSERT INTO {} (migration, statement, checksum) VALUES (?, ?, ?)", quote_ident(migrations::CHECKPOINT_TABLE)), (key, i + 1, migrations::checksum(statement)))
+        .map_err(checkpoint_error)?;
+    }
+  }
+  let (history, key) = match (history, key) {
+    (Some(history), Some(key)) => (history, key)
+  , _ => return Ok(())
+  };
+  let mut transaction = connection.start_transaction(TxOpts::default()).map_err(|e| format!("I couldn't start a transaction because: {}", e))?;
+  change_history(&mut transaction, history)?;
+  transaction.exec_drop(format!("DELETE FROM {} WHERE migration = ?", quote_ident(migrations::CHECKPOINT_TABLE)), (&key,)).map_err(checkpoint_error)?;
+  transaction.commit().map_err(|e| format!("I couldn't commit the migration because: {}", e))
+}
+
+fn change_history<Q: Queryable>(connection: &mut Q, history: &migrations::HistoryChange) -> Result<(), String> {
+  match history.applied() {
+    Some(migration) => {
+      let insert = format!("INSERT INTO {} (version, checksum, duration_ms) VALUES (?, ?, ?)", quote_ident(migrations::HISTORY_TABLE));
+      connection.exec_drop(insert, (migration.version, &migration.checksum, migration.duration_ms))
+        .map_err(|e| format!("I couldn't record migration {} because: {}", migration.version, e))
+    }
+  , None => {
+      let delete = format!("DELETE FROM {} WHERE version = ?", quote_ident(migrations::HISTORY_TABLE));
+      connection.exec_drop(delete, (history.version(),))
+        .map_err(|e| format!("I couldn't remove migration {} from the history because: {}", history.version(), e))
+    }
+  }
+}
+
+// unlike DDL, rows are written in a transaction so either all of them are or none
+fn upsert(rows: &[seed::Row], db_config: &meta::DatabaseConfig) -> Result<(), String> {
+  let mut connection = connect(db_config)?;
+  let dialect = seed::SqlDialect{ quote_ident, literal, select_from: " FROM DUAL" };
+  let mut transaction = connection.start_transaction(TxOpts::default()).map_err(|e| format!("I couldn't start a transaction because: {}", e))?;
+  for (i, statement) in rows.iter().flat_map(|r| seed::upsert_statements(r, &quote_ident(&r.table.name), &dialect)).enumerate() {
+    transaction.query_drop(&statement).map_err(|e| format!("I couldn't run statement {} because: {}\n{}\nNothing was changed.", i + 1, e, statement))?;
+  }
+  transaction.commit().map_err(|e| format!("I couldn't commit the rows because: {}", e))
+}
+
+// mysql reads a backslash in a string as the start of an escape
+fn literal(value: &seed::Value) -> String {
+  match value {
+    seed::Value::String(s) => seed::literal(&seed::Value::String(s.replace('\\', "\\\\")))
+  , _ => seed::literal(value)
+  }
+}
+
+fn lock_query_error(e: mysql::Error) -> String {
+  format!("I couldn't take the migration lock because: {}", e)
+}
+
+// lock names are at most 64 characters. MySQL only knows the holder by its connection
+fn lock(app: &str, timeout: Duration, db_config: &meta::DatabaseConfig) -> Result<Box<dyn migrations::HeldLock>, String> {
+  let mut connection = connect(db_config)?;
+  let name: String = format!("gimbal:{}", app).chars().take(64).collect();
+  let taken = drivers::wait_for_lock(timeout, || {
+    connection.exec_first::<Option<i64>, _, _>("SELECT GET_LOCK(?, 0)", (&name,)).map(|r| r.flatten().filter(|taken| *taken == 1)).map_err(lock_query_error)
+  })?;
+  if taken.is_some() {
+    return Ok(Box::new(NamedLock{ _session: connection }));
+  }
+  let holder = connection.exec_first::<(u64, String, String), _, _>("SELECT ID, USER, HOST FROM information_schema.PROCESSLIST WHERE ID = IS_USED_LOCK(?)", (&name,))
+    .map_err(lock_query_error)?
+    .map_or("another connection".to_string(), |(id, user, host)| format!("connection {} of {} from {}", id, user, host));
+  Err(drivers::lock_error(app, timeout, &holder))
+}
+
+fn statement_error(n: usize, statement: &str, error: &mysql::Error, kept: usize) -> String {
+  let reason = match error {
+    mysql::Error::MySqlError(e) => e.message.clone()
+  , e => e.to_string()
+  };
+  let outcome = if kept == 0 {
+    "Nothing was changed.".to_string()
+  } else {
+    format!("MySQL commits each statement as it runs so the {} before it are kept, migrate again once it is fixed and I'll carry on from this statement.", kept)
+  };
+  format!("I couldn't run statement {} because: {}\n{}\n{}", n, reason, statement, outcome)
+}
+
+fn applied_migrations(db_config: &meta::DatabaseConfig) -> Result<Vec<migrations::AppliedMigration>, String> {
+  let mut connection = connect(db_config)?;
+  let create = format!("CREATE TABLE IF NOT EXISTS {} (version int PRIMARY KEY, checksum char(64) NOT NULL, applied_at timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP, duration_ms bigint NOT NULL)",
+                       quote_ident(migrations::HISTORY_TABLE));
+  connection.query_drop(create).map_err(|e| format!("I couldn't create the migration history because: {}", e))?;
+  let select = format!("SELECT version, checksum, CAST(applied_at AS char), duration_ms FROM {} ORDER BY version", quote_ident(migrations::HISTORY_TABLE));
+  connection.query_map(select, |(version, checksum, applied_at, duration_ms)| migrations::AppliedMigration{ version, checksum, applied_at, duration_ms })
+    .map_err(|e| format!("I couldn't read the migration history because: {}", e))
+}
+
+fn introspection_error(e: mysql::Error) -> String {
+  format!("I couldn't read the database structure because: {}", e)
+}
+
+// only the configured database is read, it holds every table of the app. MariaDB writes defaults
+// as SQL expressions where MySQL gives literals unquoted, so those are quoted to match
+fn introspect(db_config: &meta::DatabaseConfig) -> Result<meta::Database, String> {
+  let mut connection = connect(db_config)?;
+  let mariadb = connection.query_first::<String, _>("SELECT VERSION()").map_err(introspection_error)?.unwrap_or_default().contains("MariaDB");
+  let mut columns: BTreeMap<String, Vec<meta::Column>> = BTreeMap::new();
+  connection.query_map(
+    "SELECT TABLE_NAME, COLUMN_NAME, DATA_TYPE, COLUMN_TYPE, IS_NULLABLE, COLUMN_DEFAULT, EXTRA FROM information_schema.COLUMNS
+     WHERE TABLE_SCHEMA = DATABASE() ORDER BY TABLE_NAME, ORDINAL_POSITION",
+    |(table, name, data_type, column_type, nullable, default, extra): (String, String, String, String, String, Option<String>, String)| {
+      let default = match default {
+        Some(d) if mariadb && d == "NULL" => None
+      , Some(d) if !mariadb && !extra.contains("DEFAULT_GENERATED") => Some(format!("'{}'", d.replace('\'', "''")))
+      , d => d
+      };
+      let sql_type = parse_column_type(&data_type, &column_type);
+      (table, meta::Column::introspected(&name, sql_type_to_column_type(&data_type, &sql_type), sql_type, nullable == "YES", default))
+    }).map_err(introspection_error)?
+    .into_iter().for_each(|(table, column)| columns.entry(table).or_default().push(column));
+  let mut constraints: BTreeMap<String, Vec<meta::Constraint>> = BTreeMap::new();
+  connection.query_map(
+    "SELECT c.TABLE_NAME, c.CONSTRAINT_NAME, c.CONSTRAINT_TYPE, k.COLUMN_NAME, k.REFERENCED_TABLE_NAME
+     FROM information_schema.TABLE_CONSTRAINTS c
+     LEFT JOIN information_schema.KEY_COLUMN_USAGE k
+       ON k.CONSTRAINT_SCHEMA = c.CONSTRAINT_SCHEMA AND k.TABLE_NAME = c.TABLE_NAME AND k.CONSTRAINT_NAME = c.CONSTRAINT_NAME
+     WHERE c.CONSTRAINT_SCHEMA = DATABASE() ORDER BY c.TABLE_NAME, c.CONSTRAINT_NAME, k.ORDINAL_POSITION",
+    |row: (String, String, String, Option<String>, Option<String>)| row).map_err(introspection_error)?
+    .into_iter().for_each(|(table, name, constraint_type, column, referenced)| {
+      let table_constraints = constraints.entry(table).or_default();
+      if !table_constraints.iter().any(|c| c.name == name) {
+        let kind = match (constraint_type.as_str(), &referenced) {
+          ("PRIMARY KEY", _) => meta::ConstraintKind::PrimaryKey
+        , ("UNIQUE", _) => meta::ConstraintKind::Unique
+        , ("FOREIGN KEY", Some(referenced)) => meta::ConstraintKind::ForeignKey(referenced.clone())
+        , ("CHECK", _) => meta::ConstraintKind::Check
+        , _ => meta::ConstraintKind::Other
+        };
+        table_constraints.push(meta::Constraint{ name: name.clone(), kind, columns: vec!() });
+      }
+      let constraint = table_constraints.iter_mut().find(|c| c.name == name).unwrap();
+      constraint.columns.extend(column);
+    });
+  let mut indexes: BTreeMap<String, Vec<meta::Index>> = BTreeMap::new();
+  connection.query_map(
+    "SELECT TABLE_NAME, INDEX_NAME, NON_UNIQUE, COLUMN_NAME FROM information_schema.STATISTICS
+     WHERE TABLE_SCHEMA = DATABASE() ORDER BY TABLE_NAME, INDEX_NAME, SEQ_IN_INDEX",
+    |row: (String, String, i64, String)| row).map_err(introspection_error)?
+    .into_iter().for_each(|(table, name, non_unique, column)| {
+      let table_indexes = indexes.entry(table).or_default();
+      match table_indexes.iter_mut().find(|i| i.name == name) {
+        Some(index) => index.columns.push(column)
+      , None => table_indexes.push(meta::Index{ name, columns: vec!(column), unique: non_unique == 0 })
+      }
+    });
+  let tables = connection.exec_map(
+    "SELECT TABLE_NAME FROM information_schema.TABLES WHERE TABLE_SCHEMA = DATABASE() AND TABLE_TYPE = 'BASE TABLE' AND TABLE_NAME <> ? ORDER BY TABLE_NAME",
+    (migrations::CHECKPOINT_TABLE,),
+    |name: String| {
+      meta::Table::new("", &name, columns.remove(&name).unwrap_or_default())
+        .with_keys(constraints.remove(&name).unwrap_or_default(), indexes.remove(&name).unwrap_or_default())
+    }).map_err(introspection_error)?;
+  Ok(meta::Database::new(tables).with_schemas(vec!(), ""))
+}
+
+// the column type is the full declaration, int(11) unsigned or decimal(10,2). Display widths of
+// integers are dropped except tinyint(1), which is how mysql spells boolean
+fn parse_column_type(data_type: &str, column_type: &str) -> meta::SqlType {
+  let numbers: Vec<i32> = column_type.find('(')
+    .and_then(|start| column_type[start + 1..].find(')').map(|end| &column_type[start + 1..start + 1 + end]))
+    .map(|arguments| arguments.split(',').filter_map(|a| a.trim().parse().ok()).collect())
+    .unwrap_or_default();
+  let unsigned = column_type.contains("unsigned");
+  let name = if unsigned { format!("{} unsigned", data_type) } else { data_type.to_string() };
+  match data_type {
+    "char" | "varchar" | "binary" | "varbinary" => meta::SqlType{ name, length: numbers.first().copied(), precision: None, scale: None }
+  , "tinyint" if numbers.first() == Some(&1) => meta::SqlType{ name, length: Some(1), precision: None, scale: None }
+  , "decimal" | "numeric" if !unsigned => meta::SqlType{ name, length: None, precision: numbers.first().copied(), scale: numbers.get(1).copied() }
+  , "tinyint" | "smallint" | "mediumint" | "int" | "bigint" | "float" | "double" | "text" | "tinytext" | "mediumtext" | "longtext" => {
+      meta::SqlType{ name, length: None, precision: None, scale: None }
+    }
+  , _ => meta::SqlType{ name: column_type.to_string(), length: None, precision: None, scale: None }
+  }
+}
+
+// the types gimbal creates map back to their leaf types, along with the close relatives
+// someone may have used by hand. Anything else is kept as its declaration
+fn sql_type_to_column_type(data_type: &str, sql_type: &meta::SqlType) -> meta::ColumnType {
+  match data_type {
+    "tinyint" if sql_type.length == Some(1) => meta::ColumnType::Known(internal::LeafType::Bool)
+  , "char" if sql_type.length == Some(36) => meta::ColumnType::Known(internal::LeafType::Id)
+  , "char" | "varchar" | "text" | "tinytext" | "mediumtext" | "longtext" => meta::ColumnType::Known(internal::LeafType::String)
+  , "tinyint" | "smallint" | "mediumint" | "int" | "bigint" => meta::ColumnType::Known(internal::LeafType::Int)
+  , "float" | "double" | "decimal" => meta::ColumnType::Known(internal::LeafType::Float)
+  , _ => meta::ColumnType::Unknown(sql_type.declaration())
+  }
+}
+
+// namespaces are always flattened so there are no schemas to create or tables to move
+fn operation_ddl(operation: &Operation) -> Result<String, String> {
+  match operation {
+    Operation::CreateSchema(_) | Operation::DropSchema(_) | Operation::MoveTable(_, _) => {
+      Err(format!("mysql namespaces are flattened into one database, so I can't {}.", operation.describe()))
+    }
+  , Operation::Staged(_) => Err(format!("I can only stage changes to run without downtime on postgres, so I can't {} on mysql.", operation.describe()))
+  , Operation::CreateTable(table) => Ok(table_ddl(table))
+  , Operation::DropTable(table) => Ok(format!("DROP TABLE {}", quote_ident(&table.name())))
+  , Operation::AddColumn(table, column) => Ok(format!("ALTER TABLE {} ADD COLUMN {}", quote_ident(&table.name), column_ddl(column)))
+  , Operation::DropColumn(table, column) => Ok(format!("ALTER TABLE {} DROP COLUMN {}", quote_ident(&table.name), quote_ident(&column.name())))
+  , Operation::AlterColumnType(change) => Ok(modify_ddl(change))
+  , Operation::RenameColumn(table, from, to) => Ok(format!("ALTER TABLE {} RENAME COLUMN {} TO {}", quote_ident(&table.name), quote_ident(from), quote_ident(to)))
+  , Operation::AddForeignKey(table, constraint) => {
+      let referenced = match &constraint.kind {
+        meta::ConstraintKind::ForeignKey(referenced) => referenced
+      , _ => unreachable!()
+      };
+      Ok(format!("ALTER TABLE {} ADD CONSTRAINT {} FOREIGN KEY ({}) REFERENCES {}", quote_ident(&table.name), quote_ident(&constraint.name), column_list(&constraint.columns), quote_ident(referenced)))
+    }
+  , Operation::DropForeignKey(table, constraint) => Ok(format!("ALTER TABLE {} DROP FOREIGN KEY {}", quote_ident(&table.name), quote_ident(&constraint.name)))
+  , Operation::CreateIndex(table, index) => {
+      Ok(format!("CREATE {}INDEX {} ON {} ({})", if index.unique { "UNIQUE " } else { "" }, quote_ident(&index.name), quote_ident(&table.name), column_list(&index.columns)))
+    }
+  , Operation::DropIndex(table, index) => Ok(format!("DROP INDEX {} ON {}", quote_ident(&index.name), quote_ident(&table.name)))
+  }
+}
+
+// MODIFY COLUMN replaces the whole definition, so nullability and the default are declared again
+fn modify_ddl(change: &ColumnChange) -> String {
+  let database_column = change.database_column();
+  let declaration = change.declaration(column_type_ddl);
+  let not_null = database_column.filter(|c| !c.nullable()).map_or("", |_| " NOT NULL");
+  let default = database_column.and_then(|c| c.default()).map_or("".to_string(), |d| default_ddl(&declaration, d));
+  format!("ALTER TABLE {} MODIFY COLUMN {} {}{}{}", quote_ident(&change.table.name), quote_ident(&change.column), declaration, not_null, default)
+}
+
+// text, blob, json and geometry columns can't have a literal default, only an expression in parentheses
+fn default_ddl(declaration: &str, default: &str) -> String {
+  let base = declaration.split(['(', ' ']).next().unwrap_or("").to_lowercase();
+  if base.ends_with("text") || base.ends_with("blob") || base == "json" || base == "geometry" {
+    format!(" DEFAULT ({})", default)
+  } else {
+    format!(" DEFAULT {}", default)
+  }
+}
+
+fn column_list(columns: &[String]) -> String {
+  columns.iter().map(|c| quote_ident(c)).collect::<Vec<String>>().join(", ")
+}
+
+fn table_ddl(table: &meta::Table) -> String {
+  let columns = table.columns().iter().map(column_ddl).collect::<Vec<String>>().join(", ");
+  format!("CREATE TABLE {} ({})", quote_ident(&table.name()), columns)
+}
+
+fn column_ddl(column: &meta::Column) -> String {
+  format!("{} {}", quote_ident(&column.name()), column_type_ddl(&column.data_type()))
+}
+
+fn column_type_ddl(data_type: &meta::ColumnType) -> String {
+  match data_type {
+    meta::ColumnType::Known(internal::LeafType::String) => "varchar(255)".to_string()
+  , meta::ColumnType::Known(internal::LeafType::Int) => "int".to_string()
+  , meta::ColumnType::Known(internal::LeafType::Float) => "double".to_string()
+  , meta::ColumnType::Known(internal::LeafType::Bool) => "tinyint(1)".to_string()
+  , meta::ColumnType::Known(internal::LeafType::Id) => "char(36)".to_string()
+  , meta::ColumnType::Unknown(declaration) => declaration.clone()
+  }
+}
+
+fn quote_ident(ident: &str) -> String {
+  format!("`{}`", ident.replace('`', "``"))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::database::drivers::conformance;
+  use crate::database::integration::{self, DiffDiagnosis};
+  use crate::database::naming::NamingStrategy;
+  use crate::database::operations;
+  use crate::lang::ast_builder;
+
+  const GOLDEN_MODEL: &str = r#"
+app database
+
+namespace db where
+
+struct persists Agent
+
+name:: Agent -> String
+age:: Agent -> Int
+active:: Agent -> Bool
+
+struct persists Team
+
+title:: Team -> String
+rating:: Team -> Float"#;
+
+  fn test_config() -> meta::DatabaseConfig {
+    meta::DatabaseConfig::Mysql(meta::MysqlConfig{
+      host: "localhost".to_string(), port: 3306, database: "gimbal".to_string(), user: "root".to_string(), password: None, naming: NamingStrategy::default()
+    })
+  }
+
+  fn script(change: &meta::DatabaseChange) -> String {
+    change.commands().iter().filter(|c| !c.is_empty()).map(|c| format!("{};\n", c)).collect()
+  }
+
+  // the database has an Agent table with age as text and nothing else, the plan against it
+  // is kept in golden files next to this one
+  #[test]
+  fn test_golden_sql() {
+    let ast = ast_builder::build(GOLDEN_MODEL).unwrap();
+    let db_config = test_config();
+    let agent = meta::Table::new("", "db_Agent", vec!(
+      meta::Column::introspected("name", meta::ColumnType::Known(internal::LeafType::String), parse_column_type("varchar", "varchar(255)"), true, None)
+    , meta::Column::introspected("age", meta::ColumnType::Known(internal::LeafType::String), parse_column_type("text", "text"), false, Some("'0'".to_string()))
+    ));
+    let database = meta::Database::new(vec!(agent)).with_schemas(vec!(), "");
+    let diffs = integration::diagnose_snapshot(&ast, &db_config, &database);
+    let driver = MysqlDriver;
+    let planned = operations::plan(&diffs);
+    assert_eq!(script(&driver.changes(&planned, &db_config).unwrap()), include_str!("golden/mysql_up.sql"));
+    assert_eq!(script(&driver.changes(&operations::reverse(&planned), &db_config).unwrap()), include_str!("golden/mysql_down.sql"));
+  }
+
+  #[test]
+  fn test_sql_types() {
+    let column_type = |data_type: &str, column_type: &str| sql_type_to_column_type(data_type, &parse_column_type(data_type, column_type));
+    assert_eq!(column_type("varchar", "varchar(40)"), meta::ColumnType::Known(internal::LeafType::String));
+    assert_eq!(column_type("int", "int(11)"), meta::ColumnType::Known(internal::LeafType::Int));
+    assert_eq!(column_type("tinyint", "tinyint(1)"), meta::ColumnType::Known(internal::LeafType::Bool));
+    assert_eq!(column_type("tinyint", "tinyint(4)"), meta::ColumnType::Known(internal::LeafType::Int));
+    assert_eq!(column_type("char", "char(36)"), meta::ColumnType::Known(internal::LeafType::Id));
+    assert_eq!(column_type("json", "json"), meta::ColumnType::Unknown("json".to_string()));
+    assert_eq!(column_type("enum", "enum('a','b')"), meta::ColumnType::Unknown("enum('a','b')".to_string()));
+    assert_eq!(parse_column_type("decimal", "decimal(10,2) unsigned").declaration(), "decimal(10,2) unsigned");
+    assert_eq!(parse_column_type("int", "int(10) unsigned").declaration(), "int unsigned");
+  }
+
+  #[test]
+  fn test_quoted_ddl() {
+    let columns = vec!(meta::Column::new("order", internal::LeafType::Int), meta::Column::new("say `hi`", internal::LeafType::String));
+    let table = meta::Table::new("", "User", columns);
+    assert_eq!(table_ddl(&table), "CREATE TABLE `User` (`order` int, `say ``hi``` varchar(255))");
+  }
+
+  // runs only when GIMBAL_TEST_MYSQL names a database on a local server, as host:port/database
+  // with user and password in MYSQL_USER and MYSQL_PASSWORD
+  #[test]
+  fn test_live_database() {
+    let target = match std::env::var("GIMBAL_TEST_MYSQL") {
+      Ok(t) => t
+    , Err(_) => return
+    };
+    let (address, database) = target.split_once('/').expect("GIMBAL_TEST_MYSQL is host:port/database");
+    let (host, port) = address.split_once(':').unwrap_or((address, "3306"));
+    let db_config = meta::DatabaseConfig::Mysql(meta::MysqlConfig{
+      host: host.to_string(), port: port.parse().unwrap(), database: database.to_string()
+    , user: std::env::var("MYSQL_USER").unwrap_or_else(|_| "root".to_string())
+    , password: std::env::var("MYSQL_PASSWORD").ok().map(|p| meta::Secret::new(&p))
+    , naming: NamingStrategy::default()
+    });
+    let driver = MysqlDriver;
+    let ast = ast_builder::build(GOLDEN_MODEL).unwrap();
+    driver.execute_changes(&meta::DatabaseChange::SqlDb(vec!("DROP TABLE IF EXISTS `db_Agent`, `db_Team`, `gimbal_migrations`".to_string())), None, &db_config).unwrap();
+    conformance::check(&driver, &db_config);
+    let diffs = integration::diagnose_db_diffs(&ast, &db_config).unwrap();
+    driver.execute_changes(&driver.changes(&operations::plan(&diffs), &db_config).unwrap(), None, &db_config).unwrap();
+    let diffs = integration::diagnose_db_diffs(&ast, &db_config).unwrap();
+    assert!(diffs.iter().all(|d| d.diff_diagnosis().iter().all(|d| *d == DiffDiagnosis::NoDiff)));
+
+    let failing = meta::DatabaseChange::SqlDb(vec!("ALTER TABLE `db_Agent` ADD COLUMN `extra` int".to_string(), "ALTER TABLE `missing` ADD COLUMN `x` int".to_string()));
+    let history = migrations::HistoryChange::Record{ version: 2, checksum: "failing".to_string(), started: std::time::Instant::now() };
+    let error = driver.execute_changes(&failing, Some(&history), &db_config).unwrap_err();
+    assert!(error.contains("the 1 before it are kept"));
+    let edited = meta::DatabaseChange::SqlDb(vec!("ALTER TABLE `db_Agent` ADD COLUMN `extra` bigint".to_string(), "ALTER TABLE `missing` ADD COLUMN `x` int".to_string()));
+    assert!(driver.execute_changes(&edited, Some(&history), &db_config).unwrap_err().starts_with("Statement 1 of migration 2 changed"));
+    driver.execute_changes(&meta::DatabaseChange::SqlDb(vec!("CREATE TABLE `missing` (`y` int)".to_string())), None, &db_config).unwrap();
+    driver.execute_changes(&failing, Some(&history), &db_config).unwrap();
+    assert_eq!(driver.applied_migrations(&db_config).unwrap().iter().map(|m| m.version).collect::<Vec<i32>>(), vec!(2));
+    driver.execute_changes(&meta::DatabaseChange::SqlDb(vec!("DROP TABLE `missing`, `db_Agent`, `db_Team`, `gimbal_migrations`".to_string())), None, &db_config).unwrap();
+  }
+}
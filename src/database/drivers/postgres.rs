@@ -1,71 +1,546 @@
 
-use postgres::{Client, NoTls, error::DbError, Error};
+use std::collections::BTreeMap;
+use std::time::Duration;
 
+use postgres::{Client, NoTls, Error};
+
+use crate::database::drivers::{self, DatabaseDriver};
+use crate::database::expand_contract::{self, Step};
 use crate::database::meta;
-use crate::database::integration::{DbDiff, DiffDiagnosis};
+use crate::database::migrations;
+use crate::database::operations::{ColumnChange, Operation, TableRef};
+use crate::database::seed;
 use crate::lang::internal;
 
-pub fn execute_changes(db_changes: &meta::DatabaseChange, db_config: &meta::DatabaseConfig) -> Result<(), String> {
-  let (_, errors): (Vec<_>, Vec<_>) = db_changes.commands().iter().map(|c| execute_ddl(&c)).partition(Result::is_ok);
-  if errors.len() > 0 {
-    Err(errors.iter().map(|e| e.as_ref().unwrap_err().to_string()).collect::<Vec<String>>().join("\n"))
-  } else {
-    Ok(())
+// rows a staged backfill copies in each transaction
+const BACKFILL_BATCH: usize = 1000;
+
+pub struct PostgresDriver;
+
+impl DatabaseDriver for PostgresDriver {
+  fn dialect(&self) -> &'static str {
+    "postgres"
+  }
+
+  fn introspect(&self, schemas: Option<&[String]>, db_config: &meta::DatabaseConfig) -> Result<meta::Database, String> {
+    introspect(schemas, db_config)
+  }
+
+  fn changes(&self, operations: &[Operation], _db_config: &meta::DatabaseConfig) -> Result<meta::DatabaseChange, String> {
+    Ok(meta::DatabaseChange::SqlDb(commands(operations)))
+  }
+
+  fn execute_changes(&self, db_changes: &meta::DatabaseChange, history: Option<&migrations::HistoryChange>, db_config: &meta::DatabaseConfig) -> Result<(), String> {
+    execute_changes(db_changes, history, db_config)
+  }
+
+  fn applied_migrations(&self, db_config: &meta::DatabaseConfig) -> Result<Vec<migrations::AppliedMigration>, String> {
+    applied_migrations(db_config)
+  }
+
+  fn upsert(&self, rows: &[seed::Row], db_config: &meta::DatabaseConfig) -> Result<(), String> {
+    execute_changes(&meta::DatabaseChange::SqlDb(upsert_statements(rows)), None, db_config)
+  }
+
+  fn lock<'a>(&self, app: &str, holder: &str, timeout: Duration, db_config: &'a meta::DatabaseConfig) -> Result<Box<dyn migrations::HeldLock + 'a>, String> {
+    lock(app, holder, timeout, db_config)
   }
 }
 
-fn execute_ddl(command: &str) -> Result<(), Error> {
-  let mut client = 
-    Client::connect("host=localhost user=postgres dbname=david user=david password=password", NoTls).unwrap();
-  client.batch_execute(command)
+// an advisory lock belongs to the session that took it, so it is released when the connection closes
+struct AdvisoryLock {
+  _session: Client
 }
 
-pub fn db_table_for_ast_table(db_config: &meta::DatabaseConfig, ast_table: &meta::Table) -> Option<meta::Table> {
-  let mut client = 
-    Client::connect("host=localhost user=postgres dbname=david user=david password=password", NoTls).unwrap();
-  let result = client.query("SELECT table_name FROM information_schema.tables WHERE table_name = $1",
-               &[&ast_table.name().to_lowercase()]).unwrap();
-  match result.len() {
-    0 => None
-  , 1 => Some(meta::Table::new("", result[0].get(0), db_columns_for_table(&db_config, result[0].get(0))))
-  , _ => unreachable!()
+impl migrations::HeldLock for AdvisoryLock {}
+
+// statements run in order on one connection, consecutive ones share a transaction
+// and those marked as unable to run in a transaction run on their own between them.
+// A phase of a staged migration starts a new transaction
+#[derive(Debug, PartialEq)]
+enum Segment<'a> {
+  Transaction(Vec<(usize, &'a str)>)
+, Alone(usize, &'a str)
+}
+
+// a migration that runs as more than one segment is checkpointed after each, in the same transaction
+// where there is one, so running it again after a failure skips the segments already committed. Each statement
+// is checkpointed by its number in the migration along with its checksum, so one edited since it ran is caught.
+// A migration's history change goes with the last segment
+fn execute_changes(db_changes: &meta::DatabaseChange, history: Option<&migrations::HistoryChange>, db_config: &meta::DatabaseConfig) -> Result<(), String> {
+  let mut client = connect(db_config)?;
+  let commands = db_changes.commands();
+  let segments = segments(commands);
+  let key = history.filter(|_| segments.len() > 1).map(|h| h.checkpoint_key());
+  let done = match &key {
+    Some(key) => drivers::resume_from(key, commands, &checkpoint(&mut client, key)?)?
+  , None => 0
+  };
+  let last = segments.last().map_or(0, |s| s.last());
+  let mut kept = 0;
+  for segment in segments.into_iter().filter(|s| s.last() > done) {
+    let history = history.filter(|_| segment.last() == last);
+    match segment {
+      Segment::Transaction(statements) => {
+        let mut transaction = client.transaction().map_err(|e| format!("I couldn't start a transaction because: {}", e))?;
+        for (n, statement) in &statements {
+          transaction.batch_execute(statement).map_err(|e| statement_error(*n, statement, &e, kept))?;
+        }
+        if let Some(key) = &key {
+          record_checkpoint(&mut transaction, key, &statements)?;
+        }
+        if let Some(history) = history {
+          change_history(&mut transaction, history)?;
+        }
+        transaction.commit().map_err(|e| format!("I couldn't commit the migration because: {}", e))?;
+        kept += statements.len();
+      }
+    , Segment::Alone(n, statement) => {
+        client.batch_execute(statement).map_err(|e| statement_error(n, statement, &e, kept))?;
+        if let Some(key) = &key {
+          record_checkpoint(&mut client, key, &[(n, statement)])?;
+        }
+        if let Some(history) = history {
+          change_history(&mut client, history)?;
+        }
+        kept += 1;
+      }
+    }
+  }
+  // a migration without statements still changes the history
+  if let (0, Some(history)) = (last, history) {
+    change_history(&mut client, history)?;
   }
+  if let Some(key) = &key {
+    let delete = format!("DELETE FROM {} WHERE migration = $1", quote_ident(migrations::CHECKPOINT_TABLE));
+    client.execute(delete.as_str(), &[key]).map_err(checkpoint_error)?;
+  }
+  Ok(())
 }
 
-fn db_columns_for_table(db_config: &meta::DatabaseConfig, table_name: &str) -> Vec<meta::Column> {
-  let mut client = 
-    Client::connect("host=localhost user=postgres dbname=david user=david password=password", NoTls).unwrap();
-  let result = client.query("SELECT column_name, udt_name from information_schema.columns where table_name = $1",
-    &[&table_name.to_lowercase()]).unwrap();
-  result.iter().map(|r| meta::Column::new(r.get(0), data_type_to_leaf_type(r.get(1)))).collect()
-  
+fn checkpoint_error(e: Error) -> String {
+  format!("I couldn't keep track of the migration's progress because: {}", e)
 }
 
-fn data_type_to_leaf_type(data_type: &str) -> internal::LeafType {
-  match data_type {
-    "varchar" => internal::LeafType::String
+// the statements an earlier run of the same migration committed, by number with their checksums
+fn checkpoint(client: &mut Client, key: &str) -> Result<Vec<(usize, String)>, String> {
+  let create = format!("CREATE TABLE IF NOT EXISTS {} (migration varchar(32), statement integer, checksum char(64) NOT NULL, PRIMARY KEY (migration, statement))",
+                       quote_ident(migrations::CHECKPOINT_TABLE));
+  client.batch_execute(&create).map_err(checkpoint_error)?;
+  let select = format!("SELECT statement, checksum FROM {} WHERE migration = $1 ORDER BY statement", quote_ident(migrations::CHECKPOINT_TABLE));
+  let rows = client.query(select.as_str(), &[&key]).map_err(checkpoint_error)?;
+  Ok(rows.iter().map(|r| (r.get::<_, i32>(0) as usize, r.get(1))).collect())
+}
+
+fn record_checkpoint<C: postgres::GenericClient>(client: &mut C, key: &str, statements: &[(usize, &str)]) -> Result<(), String> {
+  let insert = format!("INSERT INTO {} (migration, statement, checksum) VALUES ($1, $2, $3)", quote_ident(migrations::CHECKPOINT_TABLE));
+  for (n, statement) in statements {
+    client.execute(insert.as_str(), &[&key, &(*n as i32), &migrations::checksum(statement)]).map_err(checkpoint_error)?;
+  }
+  Ok(())
+}
+
+fn lock_query_error(e: Error) -> String {
+  format!("I couldn't take the migration lock because: {}", e)
+}
+
+// the holder names the connection so whoever waits can be told who has the lock
+fn lock(app: &str, holder: &str, timeout: Duration, db_config: &meta::DatabaseConfig) -> Result<Box<dyn migrations::HeldLock>, String> {
+  let mut client = connect(db_config)?;
+  let key = lock_key(app);
+  client.execute("SELECT set_config('application_name', $1, false)", &[&holder]).map_err(lock_query_error)?;
+  let taken = drivers::wait_for_lock(timeout, || {
+    client.query_one("SELECT pg_try_advisory_lock($1)", &[&key]).map(|r| if r.get(0) { Some(()) } else { None }).map_err(lock_query_error)
+  })?;
+  if taken.is_some() {
+    return Ok(Box::new(AdvisoryLock{ _session: client }));
+  }
+  // a bigint key is split across classid and objid
+  let holders = client.query(
+    "SELECT a.application_name, coalesce(a.client_addr::text, 'a local socket'), a.backend_start::text FROM pg_locks l JOIN pg_stat_activity a ON a.pid = l.pid
+     WHERE l.locktype = 'advisory' AND l.granted AND l.objsubid = 1 AND (l.classid::bigint << 32) | l.objid::bigint = $1", &[&key]).map_err(lock_query_error)?;
+  let holder = holders.first().map_or("another connection".to_string(), |r| {
+    format!("{} connected from {} since {}", r.get::<_, String>(0), r.get::<_, String>(1), r.get::<_, String>(2))
+  });
+  Err(drivers::lock_error(app, timeout, &holder))
+}
+
+// advisory locks are keyed by a number, the app's is taken from the hash of its name
+fn lock_key(app: &str) -> i64 {
+  i64::from_str_radix(&migrations::checksum(&format!("gimbal:{}", app))[..15], 16).unwrap_or_default()
+}
+
+fn applied_migrations(db_config: &meta::DatabaseConfig) -> Result<Vec<migrations::AppliedMigration>, String> {
+  let mut client = connect(db_config)?;
+  let create = format!("CREATE TABLE IF NOT EXISTS {} (version integer PRIMARY KEY, checksum text NOT NULL, applied_at timestamptz NOT NULL DEFAULT now(), duration_ms bigint NOT NULL)",
+                       quote_ident(migrations::HISTORY_TABLE));
+  client.batch_execute(&create).map_err(|e| format!("I couldn't create the migration history because: {}", e))?;
+  let select = format!("SELECT version, checksum, applied_at::text, duration_ms FROM {} ORDER BY version", quote_ident(migrations::HISTORY_TABLE));
+  let result = client.query(select.as_str(), &[]).map_err(|e| format!("I couldn't read the migration history because: {}", e))?;
+  Ok(result.iter().map(|r| migrations::AppliedMigration{ version: r.get(0), checksum: r.get(1), applied_at: r.get(2), duration_ms: r.get(3) }).collect())
+}
+
+fn change_history<C: postgres::GenericClient>(client: &mut C, history: &migrations::HistoryChange) -> Result<(), String> {
+  match history.applied() {
+    Some(migration) => {
+      let insert = format!("INSERT INTO {} (version, checksum, duration_ms) VALUES ($1, $2, $3)", quote_ident(migrations::HISTORY_TABLE));
+      client.execute(insert.as_str(), &[&migration.version, &migration.checksum, &migration.duration_ms])
+        .map(|_| ())
+        .map_err(|e| format!("I couldn't record migration {} because: {}", migration.version, e))
+    }
+  , None => {
+      let delete = format!("DELETE FROM {} WHERE version = $1", quote_ident(migrations::HISTORY_TABLE));
+      client.execute(delete.as_str(), &[&history.version()])
+        .map(|_| ())
+        .map_err(|e| format!("I couldn't remove migration {} from the history because: {}", history.version(), e))
+    }
+  }
+}
+
+impl Segment<'_> {
+  // the number of its last statement
+  fn last(&self) -> usize {
+    match self {
+      Segment::Transaction(statements) => statements.last().map_or(0, |(n, _)| *n)
+    , Segment::Alone(n, _) => *n
+    }
+  }
+}
+
+fn segments(commands: &[String]) -> Vec<Segment<'_>> {
+  let mut segments: Vec<Segment> = Vec::new();
+  commands.iter().enumerate().filter(|(_, c)| !c.trim().is_empty()).for_each(|(i, c)| {
+    let comments = leading_comments(c);
+    let phase = comments.iter().any(|comment| comment.starts_with(meta::PHASE_MARKER));
+    if comments.contains(&meta::NO_TRANSACTION_MARKER) {
+      segments.push(Segment::Alone(i + 1, c));
+    } else if let (false, Some(Segment::Transaction(statements))) = (phase, segments.last_mut()) {
+      statements.push((i + 1, c));
+    } else {
+      segments.push(Segment::Transaction(vec!((i + 1, c))));
+    }
+  });
+  segments
+}
+
+// the comments a statement starts with, in whatever order and spacing they were written
+fn leading_comments(statement: &str) -> Vec<&str> {
+  let mut comments = Vec::new();
+  let mut rest = statement.trim_start();
+  loop {
+    let end = if rest.starts_with("/*") {
+      match rest.find("*/") {
+        Some(end) => end + 2
+      , None => break
+      }
+    } else if rest.starts_with("--") {
+      rest.find('\n').unwrap_or(rest.len())
+    } else {
+      break
+    };
+    comments.push(&rest[..end]);
+    rest = rest[end..].trim_start();
+  }
+  comments
+}
+
+fn statement_error(n: usize, statement: &str, error: &Error, kept: usize) -> String {
+  let reason = match error.as_db_error() {
+    Some(db_error) => db_error.message().to_string()
+  , None => error.to_string()
+  };
+  let outcome = if kept == 0 {
+    "Nothing was changed.".to_string()
+  } else {
+    format!("The {} statements before it were already committed because they ran in an earlier transaction, everything since then was rolled back. \
+             Migrate again once it is fixed and I'll carry on from there.", kept)
+  };
+  format!("I couldn't run statement {} because: {}\n{}\n{}", n, reason, statement, outcome)
+}
+
+fn connect(db_config: &meta::DatabaseConfig) -> Result<Client, String> {
+  let config = match db_config {
+    meta::DatabaseConfig::Postgres(c) => c
   , _ => unreachable!()
+  };
+  let mut pg_config = postgres::Config::new();
+  pg_config.host(&config.host).port(config.port).dbname(&config.database).user(&config.user);
+  if let Some(password) = &config.password {
+    pg_config.password(password.expose());
+  }
+  pg_config.connect(NoTls).map_err(|e| format!("I couldn't connect to {} on {}:{} because: {}", config.database, config.host, config.port, e))
+}
+
+// streams the rows of a table as CSV with \N for null, every value as postgres writes it as text
+pub fn copy_out(db_config: &meta::DatabaseConfig, table: &meta::Table, sink: &mut dyn std::io::Write) -> Result<u64, String> {
+  let mut client = connect(db_config)?;
+  let columns = table.columns().iter().map(|c| format!("{}::text", quote_ident(&c.name()))).collect::<Vec<String>>().join(", ");
+  let query = format!("COPY (SELECT {} FROM {}) TO STDOUT (FORMAT csv, NULL '\\N')", columns, qualified_table_name(&TableRef::of(table)));
+  let mut reader = client.copy_out(query.as_str()).map_err(|e| format!("I couldn't read {} because: {}", table.name(), e))?;
+  std::io::copy(&mut reader, sink).map_err(|e| format!("I couldn't copy {} because: {}", table.name(), e))
+}
+
+fn introspection_error(e: Error) -> String {
+  format!("I couldn't read the database structure because: {}", e)
+}
+
+// every schema but the catalogs and temporary ones, narrowed to the app's schemas bound as $1 when there
+// are some. The current schema is always read, a flattened model's tables and the ones moved out of it are there
+const USER_SCHEMAS: &str = "n.nspname NOT IN ('pg_catalog', 'information_schema', 'pg_toast') AND n.nspname NOT LIKE 'pg_temp_%' AND n.nspname NOT LIKE 'pg_toast_temp_%'
+                            AND ($1::text[] IS NULL OR n.nspname = ANY($1::text[]) OR n.nspname = current_schema())";
+
+type TableKey = (String, String);
+
+// one query each for schemas, tables, columns, constraints and indexes on a single connection
+fn introspect(schemas: Option<&[String]>, db_config: &meta::DatabaseConfig) -> Result<meta::Database, String> {
+  let mut client = connect(db_config)?;
+  let app_schemas = schemas.map(|s| s.to_vec());
+  let default_schema: Option<String> = client.query_one("SELECT current_schema()::text", &[]).map_err(introspection_error)?.get(0);
+  let schemas = client.query(format!("SELECT n.nspname::text FROM pg_namespace n WHERE {} ORDER BY 1", USER_SCHEMAS).as_str(), &[&app_schemas])
+    .map_err(introspection_error)?
+    .iter().map(|r| r.get(0)).collect();
+  let mut columns = db_columns(&mut client, &app_schemas)?;
+  let mut constraints = db_constraints(&mut client, &app_schemas)?;
+  let mut indexes = db_indexes(&mut client, &app_schemas)?;
+  let tables = client.query(format!(
+    "SELECT n.nspname::text, c.relname::text FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace
+     WHERE c.relkind IN ('r', 'p') AND {} ORDER BY 1, 2", USER_SCHEMAS).as_str(), &[&app_schemas])
+    .map_err(introspection_error)?
+    .iter().map(|r| {
+      let key: TableKey = (r.get(0), r.get(1));
+      meta::Table::new(&key.0, &key.1, columns.remove(&key).unwrap_or_default())
+        .with_keys(constraints.remove(&key).unwrap_or_default(), indexes.remove(&key).unwrap_or_default())
+    }).collect();
+  Ok(meta::Database::new(tables).with_schemas(schemas, &default_schema.unwrap_or_default()))
+}
+
+// read from pg_catalog rather than information_schema, which leaves out columns of types
+// the connecting role doesn't own. Lengths and precisions are decoded from the type modifier
+fn db_columns(client: &mut Client, app_schemas: &Option<Vec<String>>) -> Result<BTreeMap<TableKey, Vec<meta::Column>>, String> {
+  let result = client.query(format!(
+    "SELECT n.nspname::text, c.relname::text, a.attname::text, t.typname::text, NOT a.attnotnull, pg_get_expr(d.adbin, d.adrelid),
+            CASE WHEN t.typname IN ('varchar', 'bpchar') AND a.atttypmod > 4 THEN a.atttypmod - 4 END,
+            CASE WHEN t.typname = 'numeric' AND a.atttypmod > 4 THEN ((a.atttypmod - 4) >> 16) & 65535 END,
+            CASE WHEN t.typname = 'numeric' AND a.atttypmod > 4 THEN (a.atttypmod - 4) & 65535 END
+     FROM pg_attribute a
+     JOIN pg_class c ON c.oid = a.attrelid
+     JOIN pg_namespace n ON n.oid = c.relnamespace
+     JOIN pg_type t ON t.oid = a.atttypid
+     LEFT JOIN pg_attrdef d ON d.adrelid = a.attrelid AND d.adnum = a.attnum
+     WHERE c.relkind IN ('r', 'p') AND {} AND a.attnum > 0 AND NOT a.attisdropped
+     ORDER BY 1, 2, a.attnum", USER_SCHEMAS).as_str(), &[app_schemas]).map_err(introspection_error)?;
+  let mut columns: BTreeMap<TableKey, Vec<meta::Column>> = BTreeMap::new();
+  result.iter().for_each(|r| {
+    let sql_type = meta::SqlType{ name: r.get(3), length: r.get(6), precision: r.get(7), scale: r.get(8) };
+    let column = meta::Column::introspected(r.get(2), sql_type_to_column_type(&sql_type), sql_type, r.get(4), r.get(5));
+    columns.entry((r.get(0), r.get(1))).or_default().push(column);
+  });
+  Ok(columns)
+}
+
+fn db_constraints(client: &mut Client, app_schemas: &Option<Vec<String>>) -> Result<BTreeMap<TableKey, Vec<meta::Constraint>>, String> {
+  let result = client.query(format!(
+    "SELECT n.nspname::text, c.relname::text, k.conname::text, k.contype::text,
+            ARRAY(SELECT a.attname::text FROM unnest(k.conkey) WITH ORDINALITY o(attnum, i)
+                  JOIN pg_attribute a ON a.attrelid = k.conrelid AND a.attnum = o.attnum ORDER BY o.i),
+            CASE WHEN k.contype = 'f' THEN k.confrelid::regclass::text END
+     FROM pg_constraint k
+     JOIN pg_class c ON c.oid = k.conrelid
+     JOIN pg_namespace n ON n.oid = c.relnamespace
+     WHERE {}
+     ORDER BY 1, 2, 3", USER_SCHEMAS).as_str(), &[app_schemas]).map_err(introspection_error)?;
+  let mut constraints: BTreeMap<TableKey, Vec<meta::Constraint>> = BTreeMap::new();
+  result.iter().for_each(|r| {
+    let kind = match (r.get::<_, &str>(3), r.get::<_, Option<String>>(5)) {
+      ("p", _) => meta::ConstraintKind::PrimaryKey
+    , ("u", _) => meta::ConstraintKind::Unique
+    , ("f", Some(referenced)) => meta::ConstraintKind::ForeignKey(referenced)
+    , ("c", _) => meta::ConstraintKind::Check
+    , _ => meta::ConstraintKind::Other
+    };
+    constraints.entry((r.get(0), r.get(1))).or_default().push(meta::Constraint{ name: r.get(2), kind, columns: r.get(4) });
+  });
+  Ok(constraints)
+}
+
+fn db_indexes(client: &mut Client, app_schemas: &Option<Vec<String>>) -> Result<BTreeMap<TableKey, Vec<meta::Index>>, String> {
+  let result = client.query(format!(
+    "SELECT n.nspname::text, c.relname::text, i.relname::text, x.indisunique,
+            ARRAY(SELECT a.attname::text FROM unnest(x.indkey::int2[]) WITH ORDINALITY o(attnum, k)
+                  JOIN pg_attribute a ON a.attrelid = x.indrelid AND a.attnum = o.attnum ORDER BY o.k)
+     FROM pg_index x
+     JOIN pg_class i ON i.oid = x.indexrelid
+     JOIN pg_class c ON c.oid = x.indrelid
+     JOIN pg_namespace n ON n.oid = c.relnamespace
+     WHERE {}
+     ORDER BY 1, 2, 3", USER_SCHEMAS).as_str(), &[app_schemas]).map_err(introspection_error)?;
+  let mut indexes: BTreeMap<TableKey, Vec<meta::Index>> = BTreeMap::new();
+  result.iter().for_each(|r| {
+    indexes.entry((r.get(0), r.get(1))).or_default().push(meta::Index{ name: r.get(2), unique: r.get(3), columns: r.get(4) });
+  });
+  Ok(indexes)
+}
+
+// the types gimbal creates map back to their leaf types, along with the close relatives
+// someone may have used by hand. Anything else is kept as its declaration
+fn sql_type_to_column_type(sql_type: &meta::SqlType) -> meta::ColumnType {
+  match sql_type.name.as_str() {
+    "varchar" | "text" | "bpchar" | "citext" | "name" => meta::ColumnType::Known(internal::LeafType::String)
+  , "int2" | "int4" | "int8" => meta::ColumnType::Known(internal::LeafType::Int)
+  , "float4" | "float8" | "numeric" => meta::ColumnType::Known(internal::LeafType::Float)
+  , "bool" => meta::ColumnType::Known(internal::LeafType::Bool)
+  , "uuid" => meta::ColumnType::Known(internal::LeafType::Id)
+  , _ => meta::ColumnType::Unknown(sql_type.declaration())
   }
 }
 
-pub fn diffs_to_changes(db_diffs: &Vec<DbDiff>, db_config: &meta::DatabaseConfig) -> meta::DatabaseChange {
-  meta::DatabaseChange::SqlDb(db_diffs.iter().map(|d| diff_to_command(&d.db_table(), d)).flatten().collect())
+fn operation_ddl(operation: &Operation) -> String {
+  match operation {
+    Operation::CreateSchema(schema) => format!("CREATE SCHEMA IF NOT EXISTS {}", quote_ident(schema))
+  , Operation::DropSchema(schema) => format!("DROP SCHEMA {}", quote_ident(schema))
+  , Operation::CreateTable(table) => table_ddl(table)
+  , Operation::DropTable(table) => format!("DROP TABLE {}", qualified_table_name(&TableRef::of(table)))
+  , Operation::MoveTable(table, to) => format!("ALTER TABLE {} SET SCHEMA {}", qualified_table_name(table), quote_ident(to))
+  , Operation::AddColumn(table, column) => format!("ALTER TABLE {} ADD COLUMN {}", qualified_table_name(table), column_ddl(column))
+  , Operation::DropColumn(table, column) => format!("ALTER TABLE {} DROP COLUMN {}", qualified_table_name(table), quote_ident(&column.name()))
+  , Operation::AlterColumnType(change) => alter_type_ddl(change)
+  , Operation::RenameColumn(table, from, to) => {
+      format!("ALTER TABLE {} RENAME COLUMN {} TO {}", qualified_table_name(table), quote_ident(from), quote_ident(to))
+    }
+  , Operation::AddForeignKey(table, constraint) => {
+      // introspection keeps the referenced table as postgres writes it, already quoted where it has to be
+      let referenced = match &constraint.kind {
+        meta::ConstraintKind::ForeignKey(referenced) => referenced
+      , _ => unreachable!()
+      };
+      format!("ALTER TABLE {} ADD CONSTRAINT {} FOREIGN KEY ({}) REFERENCES {}", qualified_table_name(table), quote_ident(&constraint.name), column_list(&constraint.columns), referenced)
+    }
+  , Operation::DropForeignKey(table, constraint) => format!("ALTER TABLE {} DROP CONSTRAINT {}", qualified_table_name(table), quote_ident(&constraint.name))
+  , Operation::CreateIndex(table, index) => {
+      format!("CREATE {}INDEX {} ON {} ({})", if index.unique { "UNIQUE " } else { "" }, quote_ident(&index.name), qualified_table_name(table), column_list(&index.columns))
+    }
+  , Operation::DropIndex(table, index) => format!("DROP INDEX {}", qualified_table_name(&TableRef::new(&table.schema, &index.name)))
+  , Operation::Staged(step) => step_ddl(step).join(";\n")
+  }
 }
 
-fn diff_to_command(table: &meta::Table, db_diff: &DbDiff) -> Vec<String> {
-  db_diff.diff_diagnosis().iter().map(|d| diagnosis_to_ddl(table, d)).collect()
+// a staged migration marks where each of its phases starts so each runs in its own transaction
+fn commands(operations: &[Operation]) -> Vec<String> {
+  let staged = operations.iter().any(|o| matches!(o, Operation::Staged(_)));
+  let mut phase = None;
+  operations.iter().map(|o| {
+    let ddl = operation_ddl(o);
+    let starts = staged && phase != Some(expand_contract::phase(o));
+    phase = Some(expand_contract::phase(o));
+    if starts { format!("{}{} */ {}", meta::PHASE_MARKER, expand_contract::phase(o).name(), ddl) } else { ddl }
+  }).collect()
 }
 
-fn diagnosis_to_ddl(table: &meta::Table, diagnosis: &DiffDiagnosis) -> String {
-  match diagnosis {
-    DiffDiagnosis::NoDiff => "".to_string()
-  , DiffDiagnosis::TableMissing => table_ddl(table)
-  , _ => "".to_string()
+// rows are backfilled in batches each committed on its own, so the backfill runs outside a transaction
+// and one that stopped carries on with the rows it hadn't reached. Writes are copied by a trigger
+// which casts the way the backfill does
+fn step_ddl(step: &Step) -> Vec<String> {
+  match step {
+    Step::SyncColumn(change) => {
+      let function = qualified_table_name(&TableRef::new(&change.table.schema, &sync_name(change)));
+      let mut statements = vec!(
+        format!("CREATE OR REPLACE FUNCTION {}() RETURNS trigger LANGUAGE plpgsql AS $$ BEGIN NEW.{} := NEW.{}::{}; RETURN NEW; END $$",
+                function, quote_ident(&expand_contract::shadow_name(change)), quote_ident(&change.column), column_type_ddl(&change.to))
+      , format!("CREATE TRIGGER {} BEFORE INSERT OR UPDATE ON {} FOR EACH ROW EXECUTE FUNCTION {}()", quote_ident(&sync_name(change)), qualified_table_name(&change.table), function)
+      );
+      if let Some(check) = expand_contract::not_null_check(change) {
+        statements.push(format!("ALTER TABLE {} ADD CONSTRAINT {} CHECK ({} IS NOT NULL) NOT VALID",
+                                qualified_table_name(&change.table), quote_ident(&check), quote_ident(&expand_contract::shadow_name(change))));
+      }
+      statements
+    }
+  , Step::UnsyncColumn(change) => vec!(
+      format!("DROP TRIGGER {} ON {}", quote_ident(&sync_name(change)), qualified_table_name(&change.table))
+    , format!("DROP FUNCTION {}()", qualified_table_name(&TableRef::new(&change.table.schema, &sync_name(change))))
+    )
+  , Step::Backfill(change) => {
+      let table = qualified_table_name(&change.table);
+      let (shadow, column) = (quote_ident(&expand_contract::shadow_name(change)), quote_ident(&change.column));
+      let mut statements = vec!(format!(
+        "{} DO $$ DECLARE copied integer; BEGIN LOOP UPDATE {} SET {} = {}::{} WHERE ctid = ANY(ARRAY(SELECT ctid FROM {} WHERE {} IS NULL AND {} IS NOT NULL LIMIT {})); \
+         GET DIAGNOSTICS copied = ROW_COUNT; COMMIT; EXIT WHEN copied = 0; END LOOP; END $$",
+        meta::NO_TRANSACTION_MARKER, table, shadow, column, column_type_ddl(&change.to), table, shadow, column, BACKFILL_BATCH));
+      if let Some(check) = expand_contract::not_null_check(change) {
+        statements.push(format!("ALTER TABLE {} VALIDATE CONSTRAINT {}", table, quote_ident(&check)));
+      }
+      statements
+    }
+  , Step::SwapColumn(change) => {
+      let table = qualified_table_name(&change.table);
+      let column = quote_ident(&change.column);
+      let mut statements = vec!(
+        format!("ALTER TABLE {} DROP COLUMN {}", table, column)
+      , format!("ALTER TABLE {} RENAME COLUMN {} TO {}", table, quote_ident(&expand_contract::shadow_name(change)), column)
+      );
+      if let Some(check) = expand_contract::not_null_check(change) {
+        statements.push(format!("ALTER TABLE {} ALTER COLUMN {} SET NOT NULL", table, column));
+        statements.push(format!("ALTER TABLE {} DROP CONSTRAINT {}", table, quote_ident(&check)));
+      }
+      statements.extend(expand_contract::shadow_indexes(change).into_iter().map(|(index, shadow)| {
+        format!("ALTER INDEX {} RENAME TO {}", qualified_table_name(&TableRef::new(&change.table.schema, &shadow.name)), quote_ident(&index.name))
+      }));
+      statements
+    }
+  , Step::CreateIndex(table, index) => vec!(format!("{} CREATE {}INDEX CONCURRENTLY {} ON {} ({})",
+      meta::NO_TRANSACTION_MARKER, if index.unique { "UNIQUE " } else { "" }, quote_ident(&index.name), qualified_table_name(table), column_list(&index.columns)))
+  , Step::DropIndex(table, index) => {
+      vec!(format!("{} DROP INDEX CONCURRENTLY {}", meta::NO_TRANSACTION_MARKER, qualified_table_name(&TableRef::new(&table.schema, &index.name))))
+    }
+  , Step::AddForeignKey(table, constraint) => vec!(format!("{} NOT VALID", operation_ddl(&Operation::AddForeignKey(table.clone(), constraint.clone()))))
+  , Step::ValidateForeignKey(table, constraint) => {
+      vec!(format!("ALTER TABLE {} VALIDATE CONSTRAINT {}", qualified_table_name(table), quote_ident(&constraint.name)))
+    }
   }
 }
 
+// names both the trigger copying writes into a shadow column and its function
+fn sync_name(change: &ColumnChange) -> String {
+  format!("{}_{}_gimbal_sync", change.table.name, change.column)
+}
+
+fn alter_type_ddl(change: &ColumnChange) -> String {
+  let type_ddl = change.declaration(column_type_ddl);
+  let column = quote_ident(&change.column);
+  format!("ALTER TABLE {} ALTER COLUMN {} TYPE {} USING {}::{}", qualified_table_name(&change.table), column, type_ddl, column, type_ddl)
+}
+
 fn table_ddl(table: &meta::Table) -> String {
-  format!("CREATE TABLE {} {}", table.name(), columns_for_create_ddl(table.columns()))
+  format!("CREATE TABLE {} {}", qualified_table_name(&TableRef::of(table)), columns_for_create_ddl(table.columns()))
+}
+
+fn upsert_statements(rows: &[seed::Row]) -> Vec<String> {
+  let dialect = seed::SqlDialect{ quote_ident, literal: typed_literal, select_from: "" };
+  rows.iter().flat_map(|r| seed::upsert_statements(r, &qualified_table_name(&r.table), &dialect)).collect()
+}
+
+// a value selected to be inserted takes its type from how it is written, so ids and nulls say theirs
+fn typed_literal(value: &seed::Value) -> String {
+  match value {
+    seed::Value::Id(_) | seed::Value::Null(_) => format!("CAST({} AS {})", seed::literal(value), data_type_ddl(value.leaf_type()))
+  , _ => seed::literal(value)
+  }
+}
+
+fn qualified_table_name(table: &TableRef) -> String {
+  if table.schema.is_empty() {
+    quote_ident(&table.name)
+  } else {
+    format!("{}.{}", quote_ident(&table.schema), quote_ident(&table.name))
+  }
+}
+
+fn column_list(columns: &[String]) -> String {
+  columns.iter().map(|c| quote_ident(c)).collect::<Vec<String>>().join(", ")
+}
+
+// every identifier is quoted so postgres keeps its case and reserved words can be used as names
+fn quote_ident(ident: &str) -> String {
+  format!("\"{}\"", ident.replace("\"", "\"\""))
 }
 
 fn columns_for_create_ddl(columns: &Vec<meta::Column>) -> String {
@@ -73,7 +548,14 @@ fn columns_for_create_ddl(columns: &Vec<meta::Column>) -> String {
 }
 
 fn column_ddl(column: &meta::Column) -> String {
-  format!("{} {}", column.name(), data_type_ddl(column.data_type()))
+  format!("{} {}", quote_ident(&column.name()), column_type_ddl(&column.data_type()))
+}
+
+fn column_type_ddl(data_type: &meta::ColumnType) -> String {
+  match data_type {
+    meta::ColumnType::Known(leaf_type) => data_type_ddl(leaf_type.clone())
+  , meta::ColumnType::Unknown(declaration) => declaration.clone()
+  }
 }
 
 fn data_type_ddl(data_type: internal::LeafType) -> String {
@@ -87,3 +569,174 @@ fn data_type_ddl(data_type: internal::LeafType) -> String {
 }
 
 
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::database::drivers::conformance;
+  use crate::database::naming::NamingStrategy;
+  use crate::database::sql_script;
+
+  #[test]
+  fn test_reverse_ddl() {
+    let table = TableRef::new("db", "User");
+    let added = Operation::AddColumn(table.clone(), meta::Column::new("age", internal::LeafType::Int));
+    let retyped = |from: meta::ColumnType| Operation::AlterColumnType(ColumnChange{
+      table: table.clone(), column: "age".to_string(), from, to: meta::ColumnType::Known(internal::LeafType::Int), database_table: None
+    });
+    assert_eq!(operation_ddl(&added), r#"ALTER TABLE "db"."User" ADD COLUMN "age" integer"#);
+    assert_eq!(operation_ddl(&added.reverse()), r#"ALTER TABLE "db"."User" DROP COLUMN "age""#);
+    assert_eq!(operation_ddl(&retyped(meta::ColumnType::Known(internal::LeafType::String)).reverse()), r#"ALTER TABLE "db"."User" ALTER COLUMN "age" TYPE varchar(255) USING "age"::varchar(255)"#);
+    assert_eq!(operation_ddl(&retyped(meta::ColumnType::Unknown("numeric(10,2)".to_string())).reverse()), r#"ALTER TABLE "db"."User" ALTER COLUMN "age" TYPE numeric(10,2) USING "age"::numeric(10,2)"#);
+  }
+
+  #[test]
+  fn test_key_ddl() {
+    let table = TableRef::new("db", "Order");
+    let foreign_key = meta::Constraint{ name: "order_customer".to_string(), kind: meta::ConstraintKind::ForeignKey(r#"db."Customer""#.to_string()), columns: vec!("customer".to_string()) };
+    let index = meta::Index{ name: "order_customer_idx".to_string(), columns: vec!("customer".to_string()), unique: false };
+    assert_eq!(operation_ddl(&Operation::AddForeignKey(table.clone(), foreign_key.clone())),
+               r#"ALTER TABLE "db"."Order" ADD CONSTRAINT "order_customer" FOREIGN KEY ("customer") REFERENCES db."Customer""#);
+    assert_eq!(operation_ddl(&Operation::DropForeignKey(table.clone(), foreign_key)), r#"ALTER TABLE "db"."Order" DROP CONSTRAINT "order_customer""#);
+    assert_eq!(operation_ddl(&Operation::CreateIndex(table.clone(), index.clone())), r#"CREATE INDEX "order_customer_idx" ON "db"."Order" ("customer")"#);
+    assert_eq!(operation_ddl(&Operation::DropIndex(table.clone(), index)), r#"DROP INDEX "db"."order_customer_idx""#);
+    assert_eq!(operation_ddl(&Operation::RenameColumn(table, "customer".to_string(), "buyer".to_string())), r#"ALTER TABLE "db"."Order" RENAME COLUMN "customer" TO "buyer""#);
+  }
+
+  #[test]
+  fn test_staged_ddl() {
+    let sql_type = meta::SqlType{ name: "varchar".to_string(), length: Some(10), precision: None, scale: None };
+    let database_table = meta::Table::new("db", "User", vec!(meta::Column::introspected("age", meta::ColumnType::Known(internal::LeafType::String), sql_type, false, None)))
+      .with_keys(vec!(), vec!(meta::Index{ name: "user_age".to_string(), columns: vec!("age".to_string()), unique: false }));
+    let retyped = Operation::AlterColumnType(ColumnChange{
+      table: TableRef::of(&database_table), column: "age".to_string(), database_table: Some(database_table)
+    , from: meta::ColumnType::Known(internal::LeafType::String), to: meta::ColumnType::Known(internal::LeafType::Int)
+    });
+    let commands = commands(&expand_contract::stage(&[retyped]).unwrap());
+    assert_eq!(commands, vec!(
+      r#"/* gimbal:phase=expand */ ALTER TABLE "db"."User" ADD COLUMN "age_gimbal_new" integer"#.to_string()
+    , [r#"CREATE OR REPLACE FUNCTION "db"."User_age_gimbal_sync"() RETURNS trigger LANGUAGE plpgsql AS $$ BEGIN NEW."age_gimbal_new" := NEW."age"::integer; RETURN NEW; END $$"#
+      , r#"CREATE TRIGGER "User_age_gimbal_sync" BEFORE INSERT OR UPDATE ON "db"."User" FOR EACH ROW EXECUTE FUNCTION "db"."User_age_gimbal_sync"()"#
+      , r#"ALTER TABLE "db"."User" ADD CONSTRAINT "age_gimbal_new_not_null" CHECK ("age_gimbal_new" IS NOT NULL) NOT VALID"#].join(";\n")
+    , format!("/* gimbal:phase=backfill */ {} DO $$ DECLARE copied integer; BEGIN LOOP UPDATE \"db\".\"User\" SET \"age_gimbal_new\" = \"age\"::integer \
+               WHERE ctid = ANY(ARRAY(SELECT ctid FROM \"db\".\"User\" WHERE \"age_gimbal_new\" IS NULL AND \"age\" IS NOT NULL LIMIT 1000)); \
+               GET DIAGNOSTICS copied = ROW_COUNT; COMMIT; EXIT WHEN copied = 0; END LOOP; END $$;\n\
+               ALTER TABLE \"db\".\"User\" VALIDATE CONSTRAINT \"age_gimbal_new_not_null\"", meta::NO_TRANSACTION_MARKER)
+    , format!(r#"{} CREATE INDEX CONCURRENTLY "user_age_gimbal_new" ON "db"."User" ("age_gimbal_new")"#, meta::NO_TRANSACTION_MARKER)
+    , [r#"/* gimbal:phase=contract */ DROP TRIGGER "User_age_gimbal_sync" ON "db"."User""#, r#"DROP FUNCTION "db"."User_age_gimbal_sync"()"#].join(";\n")
+    , [r#"ALTER TABLE "db"."User" DROP COLUMN "age""#
+      , r#"ALTER TABLE "db"."User" RENAME COLUMN "age_gimbal_new" TO "age""#
+      , r#"ALTER TABLE "db"."User" ALTER COLUMN "age" SET NOT NULL"#
+      , r#"ALTER TABLE "db"."User" DROP CONSTRAINT "age_gimbal_new_not_null""#
+      , r#"ALTER INDEX "db"."user_age_gimbal_new" RENAME TO "user_age""#].join(";\n")
+    ));
+    // the file splits them into statements, each phase starting a transaction of its own
+    let statements = sql_script::split_statements(&commands.iter().map(|c| format!("{};\n", c)).collect::<String>());
+    let kinds = segments(&statements).iter().map(|s| match s {
+      Segment::Transaction(statements) => statements.len()
+    , Segment::Alone(_, _) => 0
+    }).collect::<Vec<usize>>();
+    assert_eq!(kinds, vec!(4, 0, 1, 0, 7));
+    let index = meta::Index{ name: "user_age_gimbal_new".to_string(), columns: vec!("age_gimbal_new".to_string()), unique: false };
+    assert_eq!(operation_ddl(&Operation::Staged(Step::CreateIndex(TableRef::new("db", "User"), index)).reverse()),
+               format!(r#"{} DROP INDEX CONCURRENTLY "db"."user_age_gimbal_new""#, meta::NO_TRANSACTION_MARKER));
+  }
+
+  #[test]
+  fn test_lock_key() {
+    assert_eq!(lock_key("shop"), lock_key("shop"));
+    assert_ne!(lock_key("shop"), lock_key("blog"));
+    assert!(lock_key("shop") > 0);
+  }
+
+  #[test]
+  fn test_sql_types() {
+    let sql_type = |name: &str, length: Option<i32>, precision: Option<i32>, scale: Option<i32>| {
+      meta::SqlType{ name: name.to_string(), length, precision, scale }
+    };
+    assert_eq!(sql_type_to_column_type(&sql_type("varchar", Some(40), None, None)), meta::ColumnType::Known(internal::LeafType::String));
+    assert_eq!(sql_type_to_column_type(&sql_type("int4", None, None, None)), meta::ColumnType::Known(internal::LeafType::Int));
+    assert_eq!(sql_type_to_column_type(&sql_type("uuid", None, None, None)), meta::ColumnType::Known(internal::LeafType::Id));
+    assert_eq!(sql_type_to_column_type(&sql_type("bool", None, None, None)), meta::ColumnType::Known(internal::LeafType::Bool));
+    assert_eq!(sql_type_to_column_type(&sql_type("jsonb", None, None, None)), meta::ColumnType::Unknown("jsonb".to_string()));
+    assert_eq!(sql_type("numeric", None, Some(10), Some(2)).declaration(), "numeric(10,2)");
+  }
+
+  #[test]
+  fn test_segments() {
+    let commands = vec!(
+      "CREATE TABLE a (x integer)".to_string()
+    , "".to_string()
+    , "ALTER TABLE a ADD COLUMN y integer".to_string()
+    , format!("{} CREATE INDEX CONCURRENTLY a_x ON a (x)", meta::NO_TRANSACTION_MARKER)
+    , "CREATE TABLE b (x integer)".to_string()
+    );
+    assert_eq!(segments(&commands), vec!(
+      Segment::Transaction(vec!((1, commands[0].as_str()), (3, commands[2].as_str())))
+    , Segment::Alone(4, commands[3].as_str())
+    , Segment::Transaction(vec!((5, commands[4].as_str())))
+    ));
+  }
+
+  #[test]
+  fn test_phase_segments() {
+    let commands = vec!(
+      "/* gimbal:phase=expand */ ALTER TABLE a ADD COLUMN y integer".to_string()
+    , "CREATE TABLE b (x integer)".to_string()
+    , format!("/* gimbal:phase=backfill */ {} DO $$ BEGIN END $$", meta::NO_TRANSACTION_MARKER)
+    , "/* gimbal:phase=contract */ ALTER TABLE a DROP COLUMN x".to_string()
+    );
+    assert_eq!(segments(&commands), vec!(
+      Segment::Transaction(vec!((1, commands[0].as_str()), (2, commands[1].as_str())))
+    , Segment::Alone(3, commands[2].as_str())
+    , Segment::Transaction(vec!((4, commands[3].as_str())))
+    ));
+    assert_eq!(segments(&commands).iter().map(|s| s.last()).collect::<Vec<usize>>(), vec!(2, 3, 4));
+    // the markers are found among a statement's leading comments however they are laid out
+    let commands = vec!(
+      "CREATE TABLE b (x integer)".to_string()
+    , format!("\n  {}\n/* gimbal:phase=backfill */ DO $$ BEGIN END $$", meta::NO_TRANSACTION_MARKER)
+    , format!("-- the index\n{} CREATE INDEX CONCURRENTLY b_x ON b (x)", meta::NO_TRANSACTION_MARKER)
+    , "  /* gimbal:phase=contract */ ALTER TABLE b DROP COLUMN x".to_string()
+    );
+    assert_eq!(segments(&commands).iter().map(|s| s.last()).collect::<Vec<usize>>(), vec!(1, 2, 3, 4));
+  }
+
+  #[test]
+  fn test_quoted_ddl() {
+    let columns = vec!(meta::Column::new("order", internal::LeafType::Int), meta::Column::new("say \"hi\"", internal::LeafType::String));
+    let table = meta::Table::new("db", "User", columns);
+    assert_eq!(table_ddl(&table), r#"CREATE TABLE "db"."User" ("order" integer, "say ""hi""" varchar(255))"#);
+  }
+
+  #[test]
+  fn test_upsert_statements() {
+    let row = seed::Row{
+      table: TableRef::new("sales", "Customer")
+    , key: vec!(("name".to_string(), seed::Value::String("Acme".to_string())))
+    , values: vec!(("country".to_string(), seed::Value::Id("1e80deb6-affa-5766-8ec5-f59a7350a5a1".to_string())), ("vip".to_string(), seed::Value::Null(internal::LeafType::Bool)))
+    };
+    assert_eq!(upsert_statements(&[row])[1], r#"INSERT INTO "sales"."Customer" ("name", "country", "vip") SELECT 'Acme', CAST('1e80deb6-affa-5766-8ec5-f59a7350a5a1' AS uuid), CAST(NULL AS boolean) WHERE NOT EXISTS (SELECT 1 FROM "sales"."Customer" WHERE "name" = 'Acme')"#);
+  }
+
+  // runs only when GIMBAL_TEST_POSTGRES names a database on a local server, as host:port/database
+  // with user and password in PGUSER and PGPASSWORD
+  #[test]
+  fn test_conformance() {
+    let target = match std::env::var("GIMBAL_TEST_POSTGRES") {
+      Ok(t) => t
+    , Err(_) => return
+    };
+    let (address, database) = target.split_once('/').expect("GIMBAL_TEST_POSTGRES is host:port/database");
+    let (host, port) = address.split_once(':').unwrap_or((address, "5432"));
+    let db_config = meta::DatabaseConfig::Postgres(meta::PostgresConfig{
+      host: host.to_string(), port: port.parse().unwrap(), database: database.to_string()
+    , user: std::env::var("PGUSER").unwrap_or_else(|_| "postgres".to_string())
+    , password: std::env::var("PGPASSWORD").ok().map(|p| meta::Secret::new(&p))
+    , schema_mapping: meta::SchemaMapping::Flatten, naming: NamingStrategy::default()
+    });
+    let driver = PostgresDriver;
+    driver.execute_changes(&meta::DatabaseChange::SqlDb(vec!("DROP TABLE IF EXISTS \"db_Agent\", \"gimbal_migrations\"".to_string())), None, &db_config).unwrap();
+    conformance::check(&driver, &db_config);
+  }
+}
@@ -0,0 +1,562 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use rusqlite::{Connection, Row};
+
+use crate::database::drivers::{self, DatabaseDriver};
+use crate::database::meta;
+use crate::database::migrations;
+use crate::database::operations::{ColumnChange, Operation, TableRef};
+use crate::database::seed;
+use crate::lang::internal;
+
+// a type change is made by copying the table into one of this name and renaming it back
+const REBUILD_PREFIX: &str = "_gimbal_rebuild_";
+
+pub struct SqliteDriver;
+
+impl DatabaseDriver for SqliteDriver {
+  fn dialect(&self) -> &'static str {
+    "sqlite"
+  }
+
+  // the database file has the one unnamed schema
+  fn introspect(&self, _schemas: Option<&[String]>, db_config: &meta::DatabaseConfig) -> Result<meta::Database, String> {
+    introspect(db_config)
+  }
+
+  fn changes(&self, operations: &[Operation], _db_config: &meta::DatabaseConfig) -> Result<meta::DatabaseChange, String> {
+    operations_ddl(operations).map(meta::DatabaseChange::SqlDb)
+  }
+
+  fn execute_changes(&self, db_changes: &meta::DatabaseChange, history: Option<&migrations::HistoryChange>, db_config: &meta::DatabaseConfig) -> Result<(), String> {
+    execute_changes(db_changes, history, db_config)
+  }
+
+  fn applied_migrations(&self, db_config: &meta::DatabaseConfig) -> Result<Vec<migrations::AppliedMigration>, String> {
+    applied_migrations(db_config)
+  }
+
+  fn upsert(&self, rows: &[seed::Row], db_config: &meta::DatabaseConfig) -> Result<(), String> {
+    let dialect = seed::SqlDialect{ quote_ident, literal: seed::literal, select_from: "" };
+    let statements = rows.iter().flat_map(|r| seed::upsert_statements(r, &quote_ident(&r.table.name), &dialect)).collect();
+    execute_changes(&meta::DatabaseChange::SqlDb(statements), None, db_config)
+  }
+
+  fn lock<'a>(&self, app: &str, holder: &str, timeout: Duration, db_config: &'a meta::DatabaseConfig) -> Result<Box<dyn migrations::HeldLock + 'a>, String> {
+    lock(app, holder, timeout, db_config)
+  }
+}
+
+// sqlite has no advisory locks, the lock is a row of the lock table that is deleted when it is dropped.
+// Writing the row waits for whoever is writing to the file, for as long as the lock is waited for
+struct TableLock {
+  connection: Connection
+, app: String
+, holder: String
+}
+
+impl migrations::HeldLock for TableLock {}
+
+impl Drop for TableLock {
+  fn drop(&mut self) {
+    let delete = format!("DELETE FROM {} WHERE app = ?1 AND holder = ?2", quote_ident(migrations::LOCK_TABLE));
+    let _ = self.connection.execute(&delete, [&self.app, &self.holder]);
+  }
+}
+
+fn connect(db_config: &meta::DatabaseConfig) -> Result<Connection, String> {
+  let config = match db_config {
+    meta::DatabaseConfig::Sqlite(c) => c
+  , _ => unreachable!()
+  };
+  Connection::open(&config.path).map_err(|e| format!("I couldn't open the sqlite database {} because: {}", config.path, e))
+}
+
+// sqlite runs DDL inside transactions, so every statement shares one, and so does a migration's history change
+fn execute_changes(db_changes: &meta::DatabaseChange, history: Option<&migrations::HistoryChange>, db_config: &meta::DatabaseConfig) -> Result<(), String> {
+  let mut connection = connect(db_config)?;
+  let transaction = connection.transaction().map_err(|e| format!("I couldn't start a transaction because: {}", e))?;
+  for (i, statement) in db_changes.commands().iter().enumerate().filter(|(_, c)| !c.trim().is_empty()) {
+    transaction.execute_batch(statement)
+      .map_err(|e| format!("I couldn't run statement {} because: {}\n{}\nNothing was changed.", i + 1, e, statement))?;
+  }
+  if let Some(history) = history {
+    change_history(&transaction, history)?;
+  }
+  transaction.commit().map_err(|e| format!("I couldn't commit the migration because: {}", e))
+}
+
+fn change_history(connection: &Connection, history: &migrations::HistoryChange) -> Result<(), String> {
+  match history.applied() {
+    Some(migration) => {
+      let insert = format!("INSERT INTO {} (version, checksum, duration_ms) VALUES (?1, ?2, ?3)", quote_ident(migrations::HISTORY_TABLE));
+      connection.execute(&insert, rusqlite::params![migration.version, migration.checksum, migration.duration_ms])
+        .map(|_| ())
+        .map_err(|e| format!("I couldn't record migration {} because: {}", migration.version, e))
+    }
+  , None => {
+      let delete = format!("DELETE FROM {} WHERE version = ?1", quote_ident(migrations::HISTORY_TABLE));
+      connection.execute(&delete, [history.version()])
+        .map(|_| ())
+        .map_err(|e| format!("I couldn't remove migration {} from the history because: {}", history.version(), e))
+    }
+  }
+}
+
+fn lock_query_error(e: rusqlite::Error) -> String {
+  format!("I couldn't take the migration lock because: {}", e)
+}
+
+fn lock(app: &str, holder: &str, timeout: Duration, db_config: &meta::DatabaseConfig) -> Result<Box<dyn migrations::HeldLock>, String> {
+  let connection = connect(db_config)?;
+  connection.busy_timeout(timeout).map_err(lock_query_error)?;
+  let create = format!("CREATE TABLE IF NOT EXISTS {} (app text PRIMARY KEY, holder text NOT NULL, acquired_at text NOT NULL DEFAULT CURRENT_TIMESTAMP)",
+                       quote_ident(migrations::LOCK_TABLE));
+  connection.execute_batch(&create).map_err(lock_query_error)?;
+  let insert = format!("INSERT INTO {} (app, holder) VALUES (?1, ?2) ON CONFLICT (app) DO NOTHING", quote_ident(migrations::LOCK_TABLE));
+  let taken = drivers::wait_for_lock(timeout, || {
+    connection.execute(&insert, [app, holder]).map(|inserted| if inserted == 1 { Some(()) } else { None }).map_err(lock_query_error)
+  })?;
+  if taken.is_some() {
+    return Ok(Box::new(TableLock{ connection, app: app.to_string(), holder: holder.to_string() }));
+  }
+  let select = format!("SELECT holder, acquired_at FROM {} WHERE app = ?1", quote_ident(migrations::LOCK_TABLE));
+  let (other, acquired_at): (String, String) = connection.query_row(&select, [app], |r| Ok((r.get(0)?, r.get(1)?))).map_err(lock_query_error)?;
+  Err(drivers::lock_table_error(app, timeout, &other, &acquired_at))
+}
+
+fn applied_migrations(db_config: &meta::DatabaseConfig) -> Result<Vec<migrations::AppliedMigration>, String> {
+  let connection = connect(db_config)?;
+  let create = format!("CREATE TABLE IF NOT EXISTS {} (version integer PRIMARY KEY, checksum text NOT NULL, applied_at text NOT NULL DEFAULT CURRENT_TIMESTAMP, duration_ms integer NOT NULL)",
+                       quote_ident(migrations::HISTORY_TABLE));
+  connection.execute_batch(&create).map_err(|e| format!("I couldn't create the migration history because: {}", e))?;
+  let select = format!("SELECT version, checksum, applied_at, duration_ms FROM {} ORDER BY version", quote_ident(migrations::HISTORY_TABLE));
+  query(&connection, &select, |r| Ok(migrations::AppliedMigration{ version: r.get(0)?, checksum: r.get(1)?, applied_at: r.get(2)?, duration_ms: r.get(3)? }))
+    .map_err(|e| format!("I couldn't read the migration history because: {}", e))
+}
+
+fn query<T, F: FnMut(&Row) -> rusqlite::Result<T>>(connection: &Connection, sql: &str, f: F) -> rusqlite::Result<Vec<T>> {
+  let mut statement = connection.prepare(sql)?;
+  let rows = statement.query_map([], f)?;
+  rows.collect()
+}
+
+fn introspection_error(e: rusqlite::Error) -> String {
+  format!("I couldn't read the database structure because: {}", e)
+}
+
+// the catalog is read through the pragma table functions, one query each for tables, columns,
+// foreign keys and indexes. Every table is in the unnamed main schema
+fn introspect(db_config: &meta::DatabaseConfig) -> Result<meta::Database, String> {
+  let connection = connect(db_config)?;
+  let user_tables = "m.type = 'table' AND m.name NOT LIKE 'sqlite_%'";
+  let mut columns: BTreeMap<String, Vec<meta::Column>> = BTreeMap::new();
+  let mut primary_keys: BTreeMap<String, Vec<(i32, String)>> = BTreeMap::new();
+  query(&connection, &format!(
+    "SELECT m.name, p.name, p.type, p.\"notnull\", p.dflt_value, p.pk FROM sqlite_master m JOIN pragma_table_info(m.name) p
+     WHERE {} ORDER BY m.name, p.cid", user_tables),
+    |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?, r.get::<_, String>(2)?, r.get::<_, bool>(3)?, r.get::<_, Option<String>>(4)?, r.get::<_, i32>(5)?)))
+    .map_err(introspection_error)?
+    .into_iter().for_each(|(table, name, declared, not_null, default, pk)| {
+      let sql_type = parse_declared_type(&declared);
+      columns.entry(table.clone()).or_default().push(meta::Column::introspected(&name, sql_type_to_column_type(&sql_type), sql_type, !not_null, default));
+      if pk > 0 {
+        primary_keys.entry(table).or_default().push((pk, name));
+      }
+    });
+  let mut constraints: BTreeMap<String, Vec<meta::Constraint>> = BTreeMap::new();
+  primary_keys.into_iter().for_each(|(table, mut pk)| {
+    pk.sort();
+    let constraint = meta::Constraint{ name: format!("{}_pkey", table), kind: meta::ConstraintKind::PrimaryKey, columns: pk.into_iter().map(|(_, c)| c).collect() };
+    constraints.entry(table).or_default().push(constraint);
+  });
+  let mut foreign_keys: BTreeMap<(String, i32), (String, Vec<String>)> = BTreeMap::new();
+  query(&connection, &format!(
+    "SELECT m.name, f.id, f.\"table\", f.\"from\" FROM sqlite_master m JOIN pragma_foreign_key_list(m.name) f
+     WHERE {} ORDER BY m.name, f.id, f.seq", user_tables),
+    |r| Ok((r.get::<_, String>(0)?, r.get::<_, i32>(1)?, r.get::<_, String>(2)?, r.get::<_, String>(3)?)))
+    .map_err(introspection_error)?
+    .into_iter().for_each(|(table, id, referenced, column)| {
+      foreign_keys.entry((table, id)).or_insert_with(|| (referenced, vec!())).1.push(column);
+    });
+  foreign_keys.into_iter().for_each(|((table, id), (referenced, columns))| {
+    let constraint = meta::Constraint{ name: format!("{}_fkey{}", table, id), kind: meta::ConstraintKind::ForeignKey(referenced), columns };
+    constraints.entry(table).or_default().push(constraint);
+  });
+  let mut indexes: BTreeMap<String, Vec<meta::Index>> = BTreeMap::new();
+  query(&connection, &format!(
+    "SELECT m.name, l.name, l.\"unique\", l.origin, i.name FROM sqlite_master m JOIN pragma_index_list(m.name) l JOIN pragma_index_info(l.name) i
+     WHERE {} ORDER BY m.name, l.name, i.seqno", user_tables),
+    |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?, r.get::<_, bool>(2)?, r.get::<_, String>(3)?, r.get::<_, String>(4)?)))
+    .map_err(introspection_error)?
+    .into_iter().for_each(|(table, name, unique, origin, column)| {
+      let table_indexes = indexes.entry(table.clone()).or_default();
+      match table_indexes.iter_mut().find(|i| i.name == name) {
+        Some(index) => index.columns.push(column)
+      , None => table_indexes.push(meta::Index{ name: name.clone(), columns: vec!(column), unique })
+      }
+      // a unique constraint is only visible as the index made for it
+      if origin == "u" {
+        let table_constraints = constraints.entry(table).or_default();
+        match table_constraints.iter_mut().find(|c| c.name == name) {
+          Some(constraint) => constraint.columns = table_indexes.iter().find(|i| i.name == name).unwrap().columns.clone()
+        , None => table_constraints.push(meta::Constraint{ name, kind: meta::ConstraintKind::Unique, columns: table_indexes.last().unwrap().columns.clone() })
+        }
+      }
+    });
+  let tables = query(&connection, &format!("SELECT m.name FROM sqlite_master m WHERE {} ORDER BY m.name", user_tables), |r| r.get::<_, String>(0))
+    .map_err(introspection_error)?
+    .into_iter().map(|name| {
+      meta::Table::new("", &name, columns.remove(&name).unwrap_or_default())
+        .with_keys(constraints.remove(&name).unwrap_or_default(), indexes.remove(&name).unwrap_or_default())
+    }).collect();
+  Ok(meta::Database::new(tables).with_schemas(vec!(), ""))
+}
+
+// a declared type is any words with optional numbers in brackets, varchar(40) or numeric(10, 2)
+fn parse_declared_type(declared: &str) -> meta::SqlType {
+  let (name, arguments) = match declared.find('(') {
+    Some(i) => (&declared[..i], declared[i + 1..].trim_end_matches(')'))
+  , None => (declared, "")
+  };
+  let numbers: Vec<i32> = arguments.split(',').filter_map(|a| a.trim().parse().ok()).collect();
+  let name = name.trim().to_lowercase();
+  if name.contains("char") {
+    meta::SqlType{ name, length: numbers.first().copied(), precision: None, scale: None }
+  } else {
+    meta::SqlType{ name, length: None, precision: numbers.first().copied(), scale: numbers.get(1).copied() }
+  }
+}
+
+// sqlite decides how to store a column from words in its declared type, the same rules decide the leaf type
+fn sql_type_to_column_type(sql_type: &meta::SqlType) -> meta::ColumnType {
+  let name = sql_type.name.to_uppercase();
+  if name.contains("INT") {
+    meta::ColumnType::Known(internal::LeafType::Int)
+  } else if name.contains("CHAR") || name.contains("CLOB") || name.contains("TEXT") {
+    meta::ColumnType::Known(internal::LeafType::String)
+  } else if name.contains("REAL") || name.contains("FLOA") || name.contains("DOUB") {
+    meta::ColumnType::Known(internal::LeafType::Float)
+  } else if name.contains("BOOL") {
+    meta::ColumnType::Known(internal::LeafType::Bool)
+  } else if name == "UUID" {
+    meta::ColumnType::Known(internal::LeafType::Id)
+  } else {
+    meta::ColumnType::Unknown(sql_type.declaration())
+  }
+}
+
+// sqlite can't change a column's type in place, so every column added, dropped or retyped on a table
+// whose types change is folded into one rebuild of it, made where the first of them would have run
+fn operations_ddl(operations: &[Operation]) -> Result<Vec<String>, String> {
+  let retyped: Vec<&TableRef> = operations.iter().filter_map(|o| match o {
+    Operation::AlterColumnType(change) => Some(&change.table)
+  , _ => None
+  }).collect();
+  let mut rebuilt: Vec<&TableRef> = Vec::new();
+  let mut commands: Vec<String> = Vec::new();
+  for operation in operations {
+    match column_table(operation).filter(|t| retyped.contains(t)) {
+      Some(table) if rebuilt.contains(&table) => {}
+    , Some(table) => {
+        commands.extend(rebuild(table, operations)?);
+        rebuilt.push(table);
+      }
+    , None => commands.extend(operation_ddl(operation)?)
+    }
+  }
+  Ok(commands)
+}
+
+fn column_table(operation: &Operation) -> Option<&TableRef> {
+  match operation {
+    Operation::AddColumn(table, _) | Operation::DropColumn(table, _) => Some(table)
+  , Operation::AlterColumnType(change) => Some(&change.table)
+  , _ => None
+  }
+}
+
+fn operation_ddl(operation: &Operation) -> Result<Vec<String>, String> {
+  match operation {
+    Operation::CreateSchema(_) | Operation::DropSchema(_) | Operation::MoveTable(_, _) => {
+      Err(format!("sqlite has no schemas, so I can't {}.", operation.describe()))
+    }
+  , Operation::AddForeignKey(_, _) | Operation::DropForeignKey(_, _) => {
+      Err(format!("sqlite only declares foreign keys when it creates a table, so I can't {}.", operation.describe()))
+    }
+  , Operation::Staged(_) => Err(format!("I can only stage changes to run without downtime on postgres, so I can't {} on sqlite.", operation.describe()))
+  , Operation::CreateTable(table) => Ok(vec!(table_ddl(table)))
+  , Operation::DropTable(table) => Ok(vec!(format!("DROP TABLE {}", quote_ident(&table.name()))))
+  , Operation::AddColumn(table, column) => Ok(vec!(format!("ALTER TABLE {} ADD COLUMN {}", quote_ident(&table.name), column_ddl(column))))
+  , Operation::DropColumn(table, column) => Ok(vec!(format!("ALTER TABLE {} DROP COLUMN {}", quote_ident(&table.name), quote_ident(&column.name()))))
+  , Operation::AlterColumnType(change) => rebuild(&change.table, std::slice::from_ref(operation))
+  , Operation::RenameColumn(table, from, to) => Ok(vec!(format!("ALTER TABLE {} RENAME COLUMN {} TO {}", quote_ident(&table.name), quote_ident(from), quote_ident(to))))
+  , Operation::CreateIndex(table, index) => Ok(vec!(index_ddl(&table.name, index)))
+  , Operation::DropIndex(_, index) => Ok(vec!(format!("DROP INDEX {}", quote_ident(&index.name))))
+  }
+}
+
+// the table as the database had it before the migration, with the table's column operations applied.
+// Going back to the old types uses the old declarations, so a rebuild is undone by rebuilding again
+fn rebuild(table: &TableRef, operations: &[Operation]) -> Result<Vec<String>, String> {
+  let changes: Vec<&ColumnChange> = operations.iter().filter_map(|o| match o {
+    Operation::AlterColumnType(change) if &change.table == table => Some(change)
+  , _ => None
+  }).collect();
+  let database_table = changes.iter().find_map(|c| c.database_table.as_ref())
+    .ok_or_else(|| format!("I can't change the type of a column in {} without knowing how the table is declared.", table))?;
+  let dropped: Vec<String> = operations.iter().filter_map(|o| match o {
+    Operation::DropColumn(t, column) if t == table => Some(column.name())
+  , _ => None
+  }).collect();
+  let added: Vec<&meta::Column> = operations.iter().filter_map(|o| match o {
+    Operation::AddColumn(t, column) if t == table && !database_table.columns().iter().any(|c| c.name() == column.name()) => Some(column)
+  , _ => None
+  }).collect();
+  let kept: Vec<&meta::Column> = database_table.columns().iter().filter(|c| !dropped.contains(&c.name())).collect();
+  let new_type = |c: &meta::Column| changes.iter().rev().find(|change| change.column == c.name()).map(|change| change.declaration(column_type_ddl));
+  let mut definitions: Vec<String> = kept.iter().map(|c| existing_column_ddl(c, new_type(c).unwrap_or_else(|| declared_type(c)))).collect();
+  definitions.extend(added.iter().map(|c| column_ddl(c)));
+  let copied: Vec<(String, Option<String>)> = kept.iter().map(|c| (c.name(), new_type(c))).collect();
+  Ok(rebuild_ddl(database_table, &definitions, &copied))
+}
+
+// sqlite can't change a column's type in place, so the table is copied into a new one and renamed over
+// the old one. Keys are declared again and indexes created again, check constraints are not kept.
+// Each statement is a command of its own
+fn rebuild_ddl(database_table: &meta::Table, definitions: &[String], copied: &[(String, Option<String>)]) -> Vec<String> {
+  let name = database_table.name();
+  let rebuilt = format!("{}{}", REBUILD_PREFIX, name);
+  let mut elements: Vec<String> = definitions.to_vec();
+  elements.extend(database_table.constraints().iter().filter_map(|c| {
+    let columns = c.columns.iter().map(|c| quote_ident(c)).collect::<Vec<String>>().join(", ");
+    match &c.kind {
+      meta::ConstraintKind::PrimaryKey => Some(format!("PRIMARY KEY ({})", columns))
+    , meta::ConstraintKind::Unique => Some(format!("UNIQUE ({})", columns))
+    , meta::ConstraintKind::ForeignKey(referenced) => Some(format!("FOREIGN KEY ({}) REFERENCES {}", columns, quote_ident(referenced)))
+    , meta::ConstraintKind::Check | meta::ConstraintKind::Other => None
+    }
+  }));
+  let columns = copied.iter().map(|(c, _)| quote_ident(c)).collect::<Vec<String>>().join(", ");
+  let values = copied.iter().map(|(c, cast)| match cast {
+    Some(t) => format!("CAST({} AS {})", quote_ident(c), t)
+  , None => quote_ident(c)
+  }).collect::<Vec<String>>().join(", ");
+  let mut statements = vec!(
+    format!("CREATE TABLE {} ({})", quote_ident(&rebuilt), elements.join(", "))
+  , format!("INSERT INTO {} ({}) SELECT {} FROM {}", quote_ident(&rebuilt), columns, values, quote_ident(&name))
+  , format!("DROP TABLE {}", quote_ident(&name))
+  , format!("ALTER TABLE {} RENAME TO {}", quote_ident(&rebuilt), quote_ident(&name))
+  );
+  statements.extend(database_table.indexes().iter().filter(|i| !i.name.starts_with("sqlite_autoindex_")).map(|i| index_ddl(&name, i)));
+  statements
+}
+
+fn index_ddl(table_name: &str, index: &meta::Index) -> String {
+  let columns = index.columns.iter().map(|c| quote_ident(c)).collect::<Vec<String>>().join(", ");
+  format!("CREATE {}INDEX {} ON {} ({})", if index.unique { "UNIQUE " } else { "" }, quote_ident(&index.name), quote_ident(table_name), columns)
+}
+
+fn table_ddl(table: &meta::Table) -> String {
+  let columns = table.columns().iter().map(column_ddl).collect::<Vec<String>>().join(", ");
+  format!("CREATE TABLE {} ({})", quote_ident(&table.name()), columns)
+}
+
+fn column_ddl(column: &meta::Column) -> String {
+  format!("{} {}", quote_ident(&column.name()), column_type_ddl(&column.data_type()))
+}
+
+fn existing_column_ddl(column: &meta::Column, declared: String) -> String {
+  let not_null = if column.nullable() { "" } else { " NOT NULL" };
+  let default = column.default().map_or("".to_string(), |d| format!(" DEFAULT {}", d));
+  format!("{} {}{}{}", quote_ident(&column.name()), declared, not_null, default)
+}
+
+fn declared_type(column: &meta::Column) -> String {
+  column.sql_type().map_or_else(|| column_type_ddl(&column.data_type()), |t| t.declaration())
+}
+
+fn column_type_ddl(data_type: &meta::ColumnType) -> String {
+  match data_type {
+    meta::ColumnType::Known(internal::LeafType::String) => "text".to_string()
+  , meta::ColumnType::Known(internal::LeafType::Int) => "integer".to_string()
+  , meta::ColumnType::Known(internal::LeafType::Float) => "real".to_string()
+  , meta::ColumnType::Known(internal::LeafType::Bool) => "boolean".to_string()
+  , meta::ColumnType::Known(internal::LeafType::Id) => "uuid".to_string()
+  , meta::ColumnType::Unknown(declaration) => declaration.clone()
+  }
+}
+
+fn quote_ident(ident: &str) -> String {
+  format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::database::drivers::conformance;
+  use crate::database::integration::{self, DiffDiagnosis};
+  use crate::database::naming::NamingStrategy;
+  use crate::database::operations;
+  use crate::lang::ast_builder;
+  use std::time::Instant;
+
+  fn test_config(name: &str) -> meta::DatabaseConfig {
+    let path = std::env::temp_dir().join(format!("gimbal_{}_{}.db", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    meta::DatabaseConfig::Sqlite(meta::SqliteConfig{ path: path.to_string_lossy().to_string(), naming: NamingStrategy::default() })
+  }
+
+  fn remove(db_config: &meta::DatabaseConfig) {
+    if let meta::DatabaseConfig::Sqlite(c) = db_config {
+      std::fs::remove_file(&c.path).unwrap();
+    }
+  }
+
+  #[test]
+  fn test_reverse_ddl() {
+    let table = meta::Table::new("", "User", vec!(meta::Column::new("age", internal::LeafType::Int)));
+    let database_table = meta::Table::new("", "User", vec!(
+      meta::Column::introspected("age", meta::ColumnType::Known(internal::LeafType::String), parse_declared_type("varchar(10)"), false, None)
+    )).with_keys(vec!(), vec!(meta::Index{ name: "user_age".to_string(), columns: vec!("age".to_string()), unique: false }));
+    let retyped = Operation::AlterColumnType(ColumnChange{
+      table: TableRef::of(&table), column: "age".to_string(), database_table: Some(database_table)
+    , from: meta::ColumnType::Known(internal::LeafType::String), to: meta::ColumnType::Known(internal::LeafType::Int)
+    });
+    let up = operations_ddl(std::slice::from_ref(&retyped)).unwrap();
+    assert_eq!(up, vec!(
+      r#"CREATE TABLE "_gimbal_rebuild_User" ("age" integer NOT NULL)"#
+    , r#"INSERT INTO "_gimbal_rebuild_User" ("age") SELECT CAST("age" AS integer) FROM "User""#
+    , r#"DROP TABLE "User""#
+    , r#"ALTER TABLE "_gimbal_rebuild_User" RENAME TO "User""#
+    , r#"CREATE INDEX "user_age" ON "User" ("age")"#
+    ));
+    let down = operation_ddl(&retyped.reverse()).unwrap();
+    assert_eq!(down[0], r#"CREATE TABLE "_gimbal_rebuild_User" ("age" varchar(10) NOT NULL)"#);
+    assert_eq!(down[1], r#"INSERT INTO "_gimbal_rebuild_User" ("age") SELECT CAST("age" AS varchar(10)) FROM "User""#);
+    assert_eq!(table_ddl(&table), r#"CREATE TABLE "User" ("age" integer)"#);
+  }
+
+  #[test]
+  fn test_unsupported() {
+    let foreign_key = meta::Constraint{ name: "user_team".to_string(), kind: meta::ConstraintKind::ForeignKey("Team".to_string()), columns: vec!("team".to_string()) };
+    let error = operations_ddl(&[Operation::AddForeignKey(TableRef::new("", "User"), foreign_key)]).unwrap_err();
+    assert_eq!(error, "sqlite only declares foreign keys when it creates a table, so I can't add foreign key user_team on User.");
+  }
+
+  #[test]
+  fn test_quoted_ddl() {
+    let columns = vec!(meta::Column::new("order", internal::LeafType::Int), meta::Column::new("say \"hi\"", internal::LeafType::String));
+    let table = meta::Table::new("", "User", columns);
+    assert_eq!(table_ddl(&table), r#"CREATE TABLE "User" ("order" integer, "say ""hi""" text)"#);
+  }
+
+  #[test]
+  fn test_sql_types() {
+    assert_eq!(sql_type_to_column_type(&parse_declared_type("VARCHAR(40)")), meta::ColumnType::Known(internal::LeafType::String));
+    assert_eq!(sql_type_to_column_type(&parse_declared_type("bigint")), meta::ColumnType::Known(internal::LeafType::Int));
+    assert_eq!(sql_type_to_column_type(&parse_declared_type("double precision")), meta::ColumnType::Known(internal::LeafType::Float));
+    assert_eq!(sql_type_to_column_type(&parse_declared_type("boolean")), meta::ColumnType::Known(internal::LeafType::Bool));
+    assert_eq!(sql_type_to_column_type(&parse_declared_type("uuid")), meta::ColumnType::Known(internal::LeafType::Id));
+    assert_eq!(sql_type_to_column_type(&parse_declared_type("numeric(10, 2)")), meta::ColumnType::Unknown("numeric(10,2)".to_string()));
+    assert_eq!(parse_declared_type("varchar(40)").length, Some(40));
+  }
+
+  #[test]
+  fn test_pipeline() {
+    let code = r#"
+app database
+
+namespace db where
+
+struct persists Agent
+
+name:: Agent -> String
+age:: Agent -> Int"#;
+
+    let ast = ast_builder::build(code).unwrap();
+    let db_config = test_config("pipeline");
+    let driver = SqliteDriver;
+    driver.execute_changes(&meta::DatabaseChange::SqlDb(vec!(
+      r#"CREATE TABLE "db_Agent" ("name" integer NOT NULL DEFAULT 0, "id" integer PRIMARY KEY)"#.to_string()
+    , r#"CREATE INDEX "agent_name" ON "db_Agent" ("name")"#.to_string()
+    , r#"INSERT INTO "db_Agent" ("name") VALUES (42)"#.to_string()
+    )), None, &db_config).unwrap();
+
+    let diffs = integration::diagnose_db_diffs(&ast, &db_config).unwrap();
+    assert_eq!(diffs[0].diff_diagnosis(), &vec!(DiffDiagnosis::ColumnMissing("age".to_string()), DiffDiagnosis::ColumnTypeMismatch(
+      "name".to_string(), meta::ColumnType::Known(internal::LeafType::String), meta::ColumnType::Known(internal::LeafType::Int))));
+    let planned = operations::plan(&diffs);
+    let up = driver.changes(&planned, &db_config).unwrap();
+    let down = driver.changes(&operations::reverse(&planned), &db_config).unwrap();
+    assert_eq!(up.commands().len(), 5);
+    driver.execute_changes(&up, None, &db_config).unwrap();
+
+    let database = driver.introspect(None, &db_config).unwrap();
+    let agent = database.table("", "db_Agent").unwrap();
+    let names = agent.columns().iter().map(|c| (c.name(), c.data_type().name(), c.nullable())).collect::<Vec<(String, String, bool)>>();
+    assert_eq!(names, vec!(("name".to_string(), "String".to_string(), false), ("id".to_string(), "Int".to_string(), true), ("age".to_string(), "Int".to_string(), true)));
+    assert_eq!(agent.indexes().iter().map(|i| i.name.clone()).collect::<Vec<String>>(), vec!("agent_name"));
+    assert_eq!(agent.constraints()[0].kind, meta::ConstraintKind::PrimaryKey);
+    assert!(integration::diagnose_db_diffs(&ast, &db_config).unwrap()[0].diff_diagnosis().iter().all(|d| *d == DiffDiagnosis::NoDiff));
+
+    driver.execute_changes(&down, None, &db_config).unwrap();
+    let restored = driver.introspect(None, &db_config).unwrap();
+    let columns = restored.table("", "db_Agent").unwrap().columns().iter().map(|c| c.name()).collect::<Vec<String>>();
+    assert_eq!(columns, vec!("name", "id"));
+    remove(&db_config);
+  }
+
+  #[test]
+  fn test_conformance() {
+    let db_config = test_config("conformance");
+    conformance::check(&SqliteDriver, &db_config);
+    remove(&db_config);
+  }
+
+  #[test]
+  fn test_history() {
+    let db_config = test_config("history");
+    let driver = SqliteDriver;
+    assert!(driver.applied_migrations(&db_config).unwrap().is_empty());
+    let record = |version: i32| migrations::HistoryChange::Record{ version, checksum: "abc".to_string(), started: Instant::now() };
+    let create = meta::DatabaseChange::SqlDb(vec!(r#"CREATE TABLE "db_Agent" ("name" text)"#.to_string()));
+    driver.execute_changes(&create, Some(&record(1)), &db_config).unwrap();
+    assert_eq!(driver.applied_migrations(&db_config).unwrap()[0].checksum, "abc");
+
+    // a migration that fails leaves no history row behind
+    let failing = meta::DatabaseChange::SqlDb(vec!(r#"CREATE TABLE "db_Team" ("name" text)"#.to_string(), "DROP TABLE missing".to_string()));
+    assert!(driver.execute_changes(&failing, Some(&record(2)), &db_config).is_err());
+    assert_eq!(driver.applied_migrations(&db_config).unwrap().iter().map(|m| m.version).collect::<Vec<i32>>(), vec!(1));
+
+    let drop = meta::DatabaseChange::SqlDb(vec!(r#"DROP TABLE "db_Agent""#.to_string()));
+    driver.execute_changes(&drop, Some(&migrations::HistoryChange::Remove(1)), &db_config).unwrap();
+    assert!(driver.applied_migrations(&db_config).unwrap().is_empty());
+    assert!(driver.introspect(None, &db_config).unwrap().table("", "db_Agent").is_none());
+    assert!(driver.introspect(None, &db_config).unwrap().table("", migrations::HISTORY_TABLE).is_some());
+    remove(&db_config);
+  }
+
+  #[test]
+  fn test_lock() {
+    let db_config = test_config("lock");
+    let held = SqliteDriver.lock("shop", "ann on build-1 (pid 7)", Duration::ZERO, &db_config).unwrap();
+    let error = SqliteDriver.lock("shop", "bob on build-2 (pid 9)", Duration::ZERO, &db_config).err().unwrap();
+    assert!(error.starts_with("I couldn't take the migration lock for shop within 0 seconds because ann on build-1 (pid 7) holds it. It was taken at "), "{}", error);
+    assert!(SqliteDriver.lock("blog", "bob on build-2 (pid 9)", Duration::ZERO, &db_config).is_ok());
+    drop(held);
+    assert!(SqliteDriver.lock("shop", "bob on build-2 (pid 9)", Duration::ZERO, &db_config).is_ok());
+
+    // someone else writing to the file is waited for rather than taken for an error
+    let writer = connect(&db_config).unwrap();
+    writer.execute_batch("BEGIN IMMEDIATE").unwrap();
+    let released = std::thread::spawn(move || {
+      std::thread::sleep(Duration::from_millis(200));
+      writer.execute_batch("COMMIT").unwrap();
+    });
+    assert!(SqliteDriver.lock("shop", "bob on build-2 (pid 9)", Duration::from_secs(5), &db_config).is_ok());
+    released.join().unwrap();
+    remove(&db_config);
+  }
+}
@@ -0,0 +1,217 @@
+use crate::database::meta;
+use crate::database::operations::{ColumnChange, Operation, TableRef};
+
+// added to a column's name for the column of its new type while both exist, and to the names
+// of the indexes built on it
+pub const SHADOW_SUFFIX: &str = "_gimbal_new";
+
+// the parts of a change planned to run while the application keeps using the database. Expand
+// only adds, backfill fills in and checks what expand added without holding locks that block
+// writes, and contract takes away what nothing uses any more. Each phase runs on its own so a
+// migration that stops part way is carried on from the phase it stopped in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Phase {
+  Expand
+, Backfill
+, Contract
+}
+
+impl Phase {
+  pub fn name(&self) -> &'static str {
+    match self {
+      Phase::Expand => "expand"
+    , Phase::Backfill => "backfill"
+    , Phase::Contract => "contract"
+    }
+  }
+}
+
+// a column changes type by way of a shadow column of the new type: writes to the column are
+// copied into it, existing rows are copied in batches and then the shadow takes the column's place.
+// Indexes are built and foreign keys checked without blocking writes
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+  SyncColumn(ColumnChange)
+, UnsyncColumn(ColumnChange)
+, Backfill(ColumnChange)
+, SwapColumn(ColumnChange)
+, CreateIndex(TableRef, meta::Index)
+, DropIndex(TableRef, meta::Index)
+, AddForeignKey(TableRef, meta::Constraint)
+, ValidateForeignKey(TableRef, meta::Constraint)
+}
+
+impl Step {
+  // a staged migration is undone by staging the reverse of the operations it was staged from, a step on
+  // its own reverses to what puts its effect back. A backfill reverses to itself, running it again changes
+  // nothing, and once swapped in the column only goes back to its old type by changing it in place
+  pub fn reverse(&self) -> Operation {
+    match self {
+      Step::SyncColumn(change) => Operation::Staged(Step::UnsyncColumn(change.clone()))
+    , Step::UnsyncColumn(change) => Operation::Staged(Step::SyncColumn(change.clone()))
+    , Step::Backfill(_) | Step::ValidateForeignKey(_, _) => Operation::Staged(self.clone())
+    , Step::SwapColumn(change) => Operation::AlterColumnType(change.reverse())
+    , Step::CreateIndex(t, i) => Operation::Staged(Step::DropIndex(t.clone(), i.clone()))
+    , Step::DropIndex(t, i) => Operation::Staged(Step::CreateIndex(t.clone(), i.clone()))
+    , Step::AddForeignKey(t, k) => Operation::DropForeignKey(t.clone(), k.clone())
+    }
+  }
+
+  pub fn describe(&self) -> String {
+    match self {
+      Step::SyncColumn(change) => format!("copy writes to {}.{} into {}", change.table, change.column, shadow_name(change))
+    , Step::UnsyncColumn(change) => format!("stop copying writes to {}.{} into {}", change.table, change.column, shadow_name(change))
+    , Step::Backfill(change) => format!("backfill {}.{} from {}", change.table, shadow_name(change), change.column)
+    , Step::SwapColumn(change) => {
+        format!("swap column {}.{} from {} to {}", change.table, change.column, change.from.name(), change.to.name())
+      }
+    , Step::CreateIndex(t, i) => format!("create index {} on {} concurrently", i.name, t)
+    , Step::DropIndex(t, i) => format!("drop index {} on {} concurrently", i.name, t)
+    , Step::AddForeignKey(t, k) => format!("add foreign key {} on {} without checking existing rows", k.name, t)
+    , Step::ValidateForeignKey(t, k) => format!("validate foreign key {} on {}", k.name, t)
+    }
+  }
+
+  pub fn phase(&self) -> Phase {
+    match self {
+      Step::SyncColumn(_) | Step::AddForeignKey(_, _) => Phase::Expand
+    , Step::Backfill(_) | Step::CreateIndex(_, _) | Step::ValidateForeignKey(_, _) => Phase::Backfill
+    , Step::UnsyncColumn(_) | Step::SwapColumn(_) | Step::DropIndex(_, _) => Phase::Contract
+    }
+  }
+}
+
+pub fn shadow_name(change: &ColumnChange) -> String {
+  format!("{}{}", change.column, SHADOW_SUFFIX)
+}
+
+// the indexes the database has on the column, each with the index that replaces it on the shadow
+pub fn shadow_indexes(change: &ColumnChange) -> Vec<(meta::Index, meta::Index)> {
+  let indexes = change.database_table.as_ref().map(|t| t.indexes().clone()).unwrap_or_default();
+  indexes.into_iter().filter(|i| i.columns.contains(&change.column)).map(|i| {
+    let columns = i.columns.iter().map(|c| if *c == change.column { shadow_name(change) } else { c.clone() }).collect();
+    let shadow = meta::Index{ name: format!("{}{}", i.name, SHADOW_SUFFIX), columns, unique: i.unique };
+    (i, shadow)
+  }).collect()
+}
+
+// a shadow starts out nullable, a column that wasn't is checked instead and made not null once it is swapped in
+pub fn not_null_check(change: &ColumnChange) -> Option<String> {
+  change.database_column().filter(|c| !c.nullable()).map(|_| format!("{}_not_null", shadow_name(change)))
+}
+
+pub fn phase(operation: &Operation) -> Phase {
+  match operation {
+    Operation::Staged(step) => step.phase()
+  , Operation::CreateSchema(_) | Operation::CreateTable(_) | Operation::AddColumn(_, _) => Phase::Expand
+  , _ => Phase::Contract
+  }
+}
+
+// the planned operations rewritten to run without downtime and sorted into their phases, the sort
+// is stable so the planned order is kept within a phase
+pub fn stage(operations: &[Operation]) -> Result<Vec<Operation>, String> {
+  let mut staged: Vec<Operation> = Vec::new();
+  for operation in operations {
+    match operation {
+      Operation::AlterColumnType(change) => staged.extend(stage_column_change(change)?)
+    , Operation::CreateIndex(t, i) => staged.push(Operation::Staged(Step::CreateIndex(t.clone(), i.clone())))
+    , Operation::DropIndex(t, i) => staged.push(Operation::Staged(Step::DropIndex(t.clone(), i.clone())))
+    , Operation::AddForeignKey(t, k) => {
+        staged.push(Operation::Staged(Step::AddForeignKey(t.clone(), k.clone())));
+        staged.push(Operation::Staged(Step::ValidateForeignKey(t.clone(), k.clone())));
+      }
+    , _ => staged.push(operation.clone())
+    }
+  }
+  staged.sort_by_key(phase);
+  Ok(staged)
+}
+
+// keys depend on the column itself rather than its name, so a column one uses can't be swapped for another
+fn stage_column_change(change: &ColumnChange) -> Result<Vec<Operation>, String> {
+  let constraints = change.database_table.as_ref().map(|t| t.constraints().clone()).unwrap_or_default();
+  if let Some(k) = constraints.iter().find(|k| k.columns.contains(&change.column)) {
+    return Err(format!("I can't change column {}.{} without downtime because constraint {} uses it, plan it without --zero-downtime.", change.table, change.column, k.name));
+  }
+  let mut operations = vec!(
+    Operation::AddColumn(change.table.clone(), meta::Column::typed(&shadow_name(change), change.to.clone()))
+  , Operation::Staged(Step::SyncColumn(change.clone()))
+  , Operation::Staged(Step::Backfill(change.clone()))
+  );
+  operations.extend(shadow_indexes(change).into_iter().map(|(_, shadow)| Operation::Staged(Step::CreateIndex(change.table.clone(), shadow))));
+  operations.push(Operation::Staged(Step::UnsyncColumn(change.clone())));
+  operations.push(Operation::Staged(Step::SwapColumn(change.clone())));
+  Ok(operations)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::database::operations::Risk;
+  use crate::lang::internal;
+
+  fn retyped(nullable: bool, constraints: Vec<meta::Constraint>) -> ColumnChange {
+    let sql_type = meta::SqlType{ name: "varchar".to_string(), length: Some(10), precision: None, scale: None };
+    let database_table = meta::Table::new("db", "Agent", vec!(meta::Column::introspected("age", meta::ColumnType::Known(internal::LeafType::String), sql_type, nullable, None)))
+      .with_keys(constraints, vec!(meta::Index{ name: "agent_age".to_string(), columns: vec!("age".to_string()), unique: false }));
+    ColumnChange{
+      table: TableRef::of(&database_table), column: "age".to_string(), database_table: Some(database_table)
+    , from: meta::ColumnType::Known(internal::LeafType::String), to: meta::ColumnType::Known(internal::LeafType::Int)
+    }
+  }
+
+  #[test]
+  fn test_stage() {
+    let table = TableRef::new("db", "Agent");
+    let operations = vec!(
+      Operation::AlterColumnType(retyped(false, vec!()))
+    , Operation::AddColumn(table.clone(), meta::Column::new("name", internal::LeafType::String))
+    , Operation::DropIndex(table.clone(), meta::Index{ name: "agent_name".to_string(), columns: vec!("name".to_string()), unique: false })
+    );
+    let staged = stage(&operations).unwrap();
+    let described = staged.iter().map(|o| (phase(o).name(), o.describe())).collect::<Vec<(&str, String)>>();
+    assert_eq!(described, vec!(
+      ("expand", "add column db.Agent.age_gimbal_new".to_string())
+    , ("expand", "copy writes to db.Agent.age into age_gimbal_new".to_string())
+    , ("expand", "add column db.Agent.name".to_string())
+    , ("backfill", "backfill db.Agent.age_gimbal_new from age".to_string())
+    , ("backfill", "create index agent_age_gimbal_new on db.Agent concurrently".to_string())
+    , ("contract", "stop copying writes to db.Agent.age into age_gimbal_new".to_string())
+    , ("contract", "swap column db.Agent.age from String to Int".to_string())
+    , ("contract", "drop index agent_name on db.Agent concurrently".to_string())
+    ));
+    let risks = staged.iter().map(|o| o.risk()).collect::<Vec<Risk>>();
+    assert_eq!(risks, vec!(Risk::Safe, Risk::Locking, Risk::Safe, Risk::DataRewriting, Risk::Safe, Risk::Locking, Risk::Destructive, Risk::Safe));
+    assert_eq!(not_null_check(&retyped(false, vec!())), Some("age_gimbal_new_not_null".to_string()));
+    assert_eq!(not_null_check(&retyped(true, vec!())), None);
+  }
+
+  #[test]
+  fn test_reverse() {
+    let change = retyped(true, vec!());
+    let reversed = stage(&[Operation::AlterColumnType(change.clone())]).unwrap().iter().map(|o| o.reverse().describe()).collect::<Vec<String>>();
+    assert_eq!(reversed, vec!(
+      "drop column db.Agent.age_gimbal_new"
+    , "stop copying writes to db.Agent.age into age_gimbal_new"
+    , "backfill db.Agent.age_gimbal_new from age"
+    , "drop index agent_age_gimbal_new on db.Agent concurrently"
+    , "copy writes to db.Agent.age into age_gimbal_new"
+    , "change column db.Agent.age from Int to String"
+    ));
+    assert_eq!(Step::SwapColumn(change.clone()).reverse(), Operation::AlterColumnType(change.reverse()));
+    assert!(Operation::Staged(Step::SwapColumn(change.reverse())).reverse().is_destructive());
+  }
+
+  #[test]
+  fn test_stage_keys() {
+    let table = TableRef::new("db", "Order");
+    let foreign_key = meta::Constraint{ name: "order_customer".to_string(), kind: meta::ConstraintKind::ForeignKey("db.\"Customer\"".to_string()), columns: vec!("customer".to_string()) };
+    let staged = stage(&[Operation::AddForeignKey(table, foreign_key.clone())]).unwrap();
+    let described = staged.iter().map(|o| o.describe()).collect::<Vec<String>>();
+    assert_eq!(described, vec!("add foreign key order_customer on db.Order without checking existing rows", "validate foreign key order_customer on db.Order"));
+    let unique = meta::Constraint{ name: "agent_age_key".to_string(), kind: meta::ConstraintKind::Unique, columns: vec!("age".to_string()) };
+    assert_eq!(stage(&[Operation::AlterColumnType(retyped(true, vec!(unique)))]).unwrap_err(),
+               "I can't change column db.Agent.age without downtime because constraint agent_age_key uses it, plan it without --zero-downtime.");
+  }
+}
@@ -0,0 +1,706 @@
+use crate::database::meta;
+use crate::database::sql_script::{self, Span};
+use crate::lang::internal;
+
+// words that end a column's type or the clause before them
+const COLUMN_KEYWORDS: [&str; 13] = [
+  "constraint", "not", "null", "default", "primary", "unique", "references", "check", "generated", "collate", "auto_increment", "autoincrement", "comment"
+];
+
+// reads the tables a SQL DDL file creates into the database it would make, so a design kept as a dump
+// becomes a model the way a live database does. Anything other than schemas, tables, columns, keys and
+// indexes is left out and reported
+pub fn read_ddl(text: &str, db_config: &meta::DatabaseConfig) -> (meta::Database, Vec<String>) {
+  let mut design = Design{ dialect: Dialect::of(db_config), schemas: Vec::new(), tables: Vec::new(), left_out: Vec::new() };
+  sql_script::split_statements(text).iter().for_each(|s| design.statement(s));
+  design.finish()
+}
+
+// what the file's database does with names it isn't given in full
+struct Dialect {
+  default_schema: &'static str
+, folds_case: bool
+, mysql: bool
+}
+
+impl Dialect {
+  fn of(db_config: &meta::DatabaseConfig) -> Dialect {
+    match db_config.dialect() {
+      "postgres" => Dialect{ default_schema: "public", folds_case: true, mysql: false }
+    , "duckdb" => Dialect{ default_schema: "main", folds_case: false, mysql: false }
+    , "mysql" => Dialect{ default_schema: "", folds_case: false, mysql: true }
+    , _ => Dialect{ default_schema: "", folds_case: false, mysql: false }
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+  Word(String)
+, Quoted(String)
+, Literal
+, Symbol(char)
+}
+
+// a token and where it sits in its statement
+#[derive(Debug)]
+struct Lexeme {
+  token: Token
+, start: usize
+, end: usize
+}
+
+// comments and quoting are read the way migration scripts are split into statements
+fn lex(text: &str) -> Vec<Lexeme> {
+  let chars: Vec<char> = text.chars().collect();
+  let offsets: Vec<usize> = text.char_indices().map(|(o, _)| o).chain(std::iter::once(text.len())).collect();
+  let mut lexemes: Vec<Lexeme> = Vec::new();
+  let mut i = 0;
+  while i < chars.len() {
+    let c = chars[i];
+    let (end, token) = match sql_script::span(&chars, i) {
+      (end, Span::Comment) => (end, None)
+    , (end, Span::Quoted(quote)) if quote == '"' || quote == '`' => {
+        let unquoted = text[offsets[i + 1]..offsets[end - 1].max(offsets[i + 1])].replace(&format!("{}{}", quote, quote), &quote.to_string());
+        (end, Some(Token::Quoted(unquoted)))
+      }
+    , (end, Span::Quoted(_)) => (end, Some(Token::Literal))
+    , _ if c.is_whitespace() => (i + 1, None)
+    , _ if c.is_ascii_digit() => {
+        ((i..chars.len()).find(|&j| !(chars[j].is_ascii_alphanumeric() || chars[j] == '.')).unwrap_or(chars.len()), Some(Token::Literal))
+      }
+    , _ if c.is_alphabetic() || c == '_' => {
+        let end = (i..chars.len()).find(|&j| !(chars[j].is_alphanumeric() || chars[j] == '_' || chars[j] == '$')).unwrap_or(chars.len());
+        (end, Some(Token::Word(text[offsets[i]..offsets[end]].to_string())))
+      }
+    , _ => (i + 1, Some(Token::Symbol(c)))
+    };
+    if let Some(token) = token {
+      lexemes.push(Lexeme{ token, start: offsets[i], end: offsets[end] });
+    }
+    i = end;
+  }
+  lexemes
+}
+
+// a statement or a part of one, read a lexeme at a time
+struct Tokens<'a> {
+  text: &'a str
+, lexemes: &'a [Lexeme]
+, at: usize
+}
+
+impl<'a> Tokens<'a> {
+  fn peek(&self) -> Option<&'a Token> {
+    self.lexemes.get(self.at).map(|l| &l.token)
+  }
+
+  fn is_done(&self) -> bool {
+    self.at >= self.lexemes.len()
+  }
+
+  fn is_word(&self, word: &str) -> bool {
+    matches!(self.peek(), Some(Token::Word(w)) if w.eq_ignore_ascii_case(word))
+  }
+
+  fn is_symbol(&self, symbol: char) -> bool {
+    self.peek() == Some(&Token::Symbol(symbol))
+  }
+
+  fn eat_word(&mut self, word: &str) -> bool {
+    let is_word = self.is_word(word);
+    if is_word {
+      self.at += 1;
+    }
+    is_word
+  }
+
+  // all of the words in turn or none of them
+  fn eat_words(&mut self, words: &[&str]) -> bool {
+    let at = self.at;
+    let eaten = words.iter().all(|w| self.eat_word(w));
+    if !eaten {
+      self.at = at;
+    }
+    eaten
+  }
+
+  fn eat_symbol(&mut self, symbol: char) -> bool {
+    let is_symbol = self.is_symbol(symbol);
+    if is_symbol {
+      self.at += 1;
+    }
+    is_symbol
+  }
+
+  // the next lexeme, or all of a parenthesised group
+  fn skip(&mut self) {
+    if self.group().is_none() {
+      self.at += 1;
+    }
+  }
+
+  fn name(&mut self, dialect: &Dialect) -> Option<String> {
+    let name = match self.peek()? {
+      Token::Word(w) if dialect.folds_case => w.to_lowercase()
+    , Token::Word(w) | Token::Quoted(w) => w.clone()
+    , _ => return None
+    };
+    self.at += 1;
+    Some(name)
+  }
+
+  // a table's schema, when it is given, and name. A database in front of them is dropped
+  fn qualified_name(&mut self, dialect: &Dialect) -> Option<(Option<String>, String)> {
+    let mut parts = vec!(self.name(dialect)?);
+    while self.eat_symbol('.') {
+      parts.push(self.name(dialect)?);
+    }
+    let name = parts.pop()?;
+    Some((parts.pop(), name))
+  }
+
+  // the parts of the parenthesised list that starts here
+  fn group(&mut self) -> Option<Vec<Tokens<'a>>> {
+    if !self.is_symbol('(') {
+      return None;
+    }
+    let mut depth = 0;
+    let close = (self.at..self.lexemes.len()).find(|&i| {
+      match self.lexemes[i].token {
+        Token::Symbol('(') => depth += 1
+      , Token::Symbol(')') => depth -= 1
+      , _ => {}
+      }
+      depth == 0
+    }).unwrap_or(self.lexemes.len());
+    let mut inside = self.slice(self.at + 1, close);
+    self.at = (close + 1).min(self.lexemes.len());
+    Some(inside.parts())
+  }
+
+  // what is left split at its top level commas
+  fn parts(&mut self) -> Vec<Tokens<'a>> {
+    let mut parts: Vec<Tokens<'a>> = Vec::new();
+    let mut start = self.at;
+    let mut depth = 0;
+    while let Some(lexeme) = self.lexemes.get(self.at) {
+      match lexeme.token {
+        Token::Symbol('(') => depth += 1
+      , Token::Symbol(')') => depth -= 1
+      , Token::Symbol(',') if depth == 0 => {
+          parts.push(self.slice(start, self.at));
+          start = self.at + 1;
+        }
+      , _ => {}
+      }
+      self.at += 1;
+    }
+    if start < self.at {
+      parts.push(self.slice(start, self.at));
+    }
+    parts
+  }
+
+  fn slice(&self, from: usize, to: usize) -> Tokens<'a> {
+    Tokens{ text: self.text, lexemes: &self.lexemes[from..to], at: 0 }
+  }
+
+  fn text(&self, from: usize, to: usize) -> String {
+    if from >= to { String::new() } else { self.text[self.lexemes[from].start..self.lexemes[to - 1].end].to_string() }
+  }
+
+  fn rest(&mut self) -> String {
+    let rest = self.text(self.at, self.lexemes.len());
+    self.at = self.lexemes.len();
+    rest
+  }
+
+  // a clause of a column running up to the next one, at least one lexeme long
+  fn clause(&mut self) -> String {
+    let start = self.at;
+    self.skip();
+    while !self.is_done() && !COLUMN_KEYWORDS.iter().any(|k| self.is_word(k)) {
+      self.skip();
+    }
+    self.text(start, self.at)
+  }
+}
+
+struct DraftTable {
+  schema: String
+, name: String
+, columns: Vec<DraftColumn>
+, constraints: Vec<meta::Constraint>
+, indexes: Vec<meta::Index>
+}
+
+impl DraftTable {
+  fn display(&self) -> String {
+    if self.schema.is_empty() { self.name.clone() } else { format!("{}.{}", self.schema, self.name) }
+  }
+
+  // a key the file didn't name is given the name postgres would have given it
+  fn add_constraint(&mut self, name: Option<String>, kind: meta::ConstraintKind, columns: Vec<String>) {
+    let suffix = match kind {
+      meta::ConstraintKind::PrimaryKey => "pkey"
+    , meta::ConstraintKind::Unique => "key"
+    , meta::ConstraintKind::ForeignKey(_) => "fkey"
+    , meta::ConstraintKind::Check | meta::ConstraintKind::Other => "check"
+    };
+    let name = name.unwrap_or_else(|| match kind {
+      meta::ConstraintKind::PrimaryKey => format!("{}_pkey", self.name)
+    , _ => [vec!(self.name.clone()), columns.clone(), vec!(suffix.to_string())].concat().join("_")
+    });
+    self.constraints.push(meta::Constraint{ name, kind, columns });
+  }
+
+  // key columns can't be null
+  fn into_table(mut self, dialect: &Dialect) -> meta::Table {
+    let keyed: Vec<String> = self.constraints.iter().filter(|k| k.kind == meta::ConstraintKind::PrimaryKey).flat_map(|k| k.columns.clone()).collect();
+    self.columns.iter_mut().filter(|c| keyed.contains(&c.name)).for_each(|c| c.nullable = false);
+    let columns = self.columns.into_iter().map(|c| {
+      meta::Column::introspected(&c.name, column_type(&c.sql_type, dialect), c.sql_type, c.nullable, c.default)
+    }).collect();
+    meta::Table::new(&self.schema, &self.name, columns).with_keys(self.constraints, self.indexes)
+  }
+}
+
+struct DraftColumn {
+  name: String
+, sql_type: meta::SqlType
+, nullable: bool
+, default: Option<String>
+}
+
+// the leaf type of a type as a DDL file spells it, which needn't be how the database reports it
+fn column_type(sql_type: &meta::SqlType, dialect: &Dialect) -> meta::ColumnType {
+  match sql_type.name.as_str() {
+    "tinyint" if dialect.mysql && sql_type.length == Some(1) => meta::ColumnType::Known(internal::LeafType::Bool)
+  , "char" if dialect.mysql && sql_type.length == Some(36) => meta::ColumnType::Known(internal::LeafType::Id)
+  , "varchar" | "character varying" | "char" | "character" | "bpchar" | "nchar" | "nvarchar" | "text" | "citext" | "name"
+  | "tinytext" | "mediumtext" | "longtext" | "clob" | "string" => meta::ColumnType::Known(internal::LeafType::String)
+  , "smallint" | "integer" | "int" | "bigint" | "int2" | "int4" | "int8" | "tinyint" | "mediumint" | "hugeint"
+  | "smallserial" | "serial" | "bigserial" | "serial2" | "serial4" | "serial8" => meta::ColumnType::Known(internal::LeafType::Int)
+  , "real" | "float" | "float4" | "float8" | "double" | "double precision" | "numeric" | "decimal" => meta::ColumnType::Known(internal::LeafType::Float)
+  , "boolean" | "bool" => meta::ColumnType::Known(internal::LeafType::Bool)
+  , "uuid" => meta::ColumnType::Known(internal::LeafType::Id)
+  , _ => meta::ColumnType::Unknown(sql_type.declaration())
+  }
+}
+
+struct Design {
+  dialect: Dialect
+, schemas: Vec<String>
+, tables: Vec<DraftTable>
+, left_out: Vec<String>
+}
+
+impl Design {
+  fn statement(&mut self, statement: &str) {
+    let lexemes = lex(statement);
+    let mut tokens = Tokens{ text: statement, lexemes: &lexemes, at: 0 };
+    let read = if tokens.eat_word("create") {
+      tokens.eat_words(&["or", "replace"]);
+      if tokens.eat_word("table") {
+        self.create_table(&mut tokens)
+      } else if tokens.eat_word("schema") {
+        self.create_schema(&mut tokens)
+      } else {
+        let unique = tokens.eat_word("unique");
+        tokens.eat_word("index") && self.create_index(&mut tokens, unique)
+      }
+    } else if tokens.eat_words(&["alter", "table"]) {
+      self.alter_table(&mut tokens)
+    } else {
+      // settings a dump starts with
+      ["set", "reset", "begin", "commit", "start"].iter().any(|w| tokens.is_word(w)) || (tokens.is_word("select") && statement.contains("set_config("))
+    };
+    if !read {
+      let first_line = statement[lexemes.first().map_or(0, |l| l.start)..].lines().next().unwrap_or("").trim();
+      self.left_out.push(format!("left out {}", first_line.trim_end_matches(['(', ' '])));
+    }
+  }
+
+  fn schema(&self, schema: Option<String>) -> String {
+    schema.unwrap_or_else(|| self.dialect.default_schema.to_string())
+  }
+
+  // how a foreign key names the table it references, the way postgres prints it
+  fn reference(&self, schema: Option<String>, name: &str) -> String {
+    let quoted = |n: &str| {
+      let plain = n.starts_with(|c: char| c.is_ascii_lowercase() || c == '_') && n.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+      if plain { n.to_string() } else { format!("\"{}\"", n.replace('"', "\"\"")) }
+    };
+    match schema.filter(|s| s != self.dialect.default_schema) {
+      Some(s) => format!("{}.{}", quoted(&s), quoted(name))
+    , None => quoted(name)
+    }
+  }
+
+  fn create_schema(&mut self, tokens: &mut Tokens) -> bool {
+    tokens.eat_words(&["if", "not", "exists"]);
+    match tokens.name(&self.dialect) {
+      None => false
+    , Some(schema) => {
+        self.schemas.push(schema);
+        true
+      }
+    }
+  }
+
+  // CREATE TABLE ... AS isn't read, it has no columns to read
+  fn create_table(&mut self, tokens: &mut Tokens) -> bool {
+    tokens.eat_words(&["if", "not", "exists"]);
+    let (schema, name) = match tokens.qualified_name(&self.dialect) {
+      None => return false
+    , Some(n) => n
+    };
+    let elements = match tokens.group() {
+      None => return false
+    , Some(e) => e
+    };
+    let mut table = DraftTable{ schema: self.schema(schema), name, columns: vec!(), constraints: vec!(), indexes: vec!() };
+    for mut element in elements {
+      let constraint = if element.eat_word("constraint") { Some(element.name(&self.dialect)) } else { None };
+      if !self.table_key(&mut table, &mut element, constraint.clone().flatten()) {
+        match constraint {
+          Some(_) => self.left_out.push(format!("left out {} on {}", element.text(0, element.lexemes.len()), table.display()))
+        , None => self.column(&mut table, &mut element)
+        }
+      }
+    }
+    if !tokens.is_done() {
+      self.left_out.push(format!("left out {} on {}", tokens.rest(), table.display()));
+    }
+    self.tables.push(table);
+    true
+  }
+
+  // a key of the table, or for mysql an index, false when it is neither
+  fn table_key(&mut self, table: &mut DraftTable, tokens: &mut Tokens, name: Option<String>) -> bool {
+    if tokens.eat_words(&["primary", "key"]) {
+      let columns = self.names(tokens);
+      table.add_constraint(name, meta::ConstraintKind::PrimaryKey, columns);
+    } else if tokens.eat_word("unique") {
+      let _ = tokens.eat_word("key") || tokens.eat_word("index");
+      let index_name = if tokens.is_symbol('(') { None } else { tokens.name(&self.dialect) };
+      let columns = self.names(tokens);
+      table.add_constraint(name.or(index_name), meta::ConstraintKind::Unique, columns);
+    } else if tokens.eat_words(&["foreign", "key"]) {
+      let columns = self.names(tokens);
+      let referenced = if tokens.eat_word("references") { tokens.qualified_name(&self.dialect) } else { None };
+      let (schema, referenced_name) = match referenced {
+        None => return false
+      , Some(r) => r
+      };
+      tokens.group();
+      let reference = self.reference(schema, &referenced_name);
+      self.foreign_key_options(table, tokens, &reference);
+      table.add_constraint(name, meta::ConstraintKind::ForeignKey(reference), columns);
+    } else if tokens.eat_word("check") {
+      tokens.group();
+      table.add_constraint(name, meta::ConstraintKind::Check, vec!());
+    } else if self.dialect.mysql && (tokens.eat_word("key") || tokens.eat_word("index")) {
+      let index_name = if tokens.is_symbol('(') { None } else { tokens.name(&self.dialect) };
+      let columns = self.names(tokens);
+      let index_name = index_name.unwrap_or_else(|| [vec!(table.name.clone()), columns.clone(), vec!("idx".to_string())].concat().join("_"));
+      table.indexes.push(meta::Index{ name: index_name, columns, unique: false });
+    } else {
+      return false;
+    }
+    if !tokens.is_done() {
+      self.left_out.push(format!("left out {} on {}", tokens.rest(), table.display()));
+    }
+    true
+  }
+
+  // what happens to rows whose referenced row goes away is up to the database
+  fn foreign_key_options(&mut self, table: &DraftTable, tokens: &mut Tokens, reference: &str) {
+    let start = tokens.at;
+    while !tokens.is_done() && !COLUMN_KEYWORDS.iter().any(|k| tokens.is_word(k)) {
+      tokens.skip();
+    }
+    if tokens.at > start {
+      self.left_out.push(format!("left out {} on {}'s reference to {}", tokens.text(start, tokens.at), table.display(), reference));
+    }
+  }
+
+  // the names in the parenthesised list that starts here, an expression stands for itself
+  fn names(&self, tokens: &mut Tokens) -> Vec<String> {
+    tokens.group().into_iter().flatten().map(|mut part| {
+      match part.name(&self.dialect) {
+        Some(name) if part.is_done() => name
+      , _ => part.text(0, part.lexemes.len())
+      }
+    }).collect()
+  }
+
+  fn column(&mut self, table: &mut DraftTable, tokens: &mut Tokens) {
+    let name = match tokens.name(&self.dialect) {
+      None => {
+        self.left_out.push(format!("left out {} on {}", tokens.rest(), table.display()));
+        return;
+      }
+    , Some(n) => n
+    };
+    let sql_type = sql_type(tokens);
+    let mut column = DraftColumn{ name, sql_type, nullable: true, default: None };
+    while !tokens.is_done() {
+      let constraint = if tokens.eat_word("constraint") { tokens.name(&self.dialect) } else { None };
+      let columns = vec!(column.name.clone());
+      if tokens.eat_words(&["not", "null"]) {
+        column.nullable = false;
+      } else if tokens.eat_word("null") {
+        column.nullable = true;
+      } else if tokens.eat_word("default") {
+        column.default = Some(tokens.clause());
+      } else if tokens.eat_words(&["primary", "key"]) {
+        table.add_constraint(constraint, meta::ConstraintKind::PrimaryKey, columns);
+      } else if tokens.eat_word("unique") {
+        tokens.eat_word("key");
+        table.add_constraint(constraint, meta::ConstraintKind::Unique, columns);
+      } else if tokens.eat_word("references") {
+        let (schema, referenced_name) = match tokens.qualified_name(&self.dialect) {
+          None => {
+            self.left_out.push(format!("left out {} on {}.{}", tokens.rest(), table.display(), column.name));
+            break;
+          }
+        , Some(r) => r
+        };
+        tokens.group();
+        let reference = self.reference(schema, &referenced_name);
+        self.foreign_key_options(table, tokens, &reference);
+        table.add_constraint(constraint, meta::ConstraintKind::ForeignKey(reference), columns);
+      } else if tokens.eat_word("check") {
+        tokens.group();
+        table.add_constraint(constraint, meta::ConstraintKind::Check, columns);
+      } else {
+        let clause = tokens.clause();
+        self.left_out.push(format!("left out {} on {}.{}", clause, table.display(), column.name));
+      }
+    }
+    table.columns.push(column);
+  }
+
+  fn create_index(&mut self, tokens: &mut Tokens, unique: bool) -> bool {
+    tokens.eat_word("concurrently");
+    tokens.eat_words(&["if", "not", "exists"]);
+    let name = if tokens.is_word("on") { None } else { tokens.name(&self.dialect) };
+    if !tokens.eat_word("on") {
+      return false;
+    }
+    tokens.eat_word("only");
+    let (schema, table_name) = match tokens.qualified_name(&self.dialect) {
+      None => return false
+    , Some(n) => n
+    };
+    if tokens.eat_word("using") {
+      tokens.skip();
+    }
+    let columns = self.names(tokens);
+    let schema = self.schema(schema);
+    let i = match self.tables.iter().position(|t| t.schema == schema && t.name == table_name) {
+      None => return false
+    , Some(i) => i
+    };
+    let name = name.unwrap_or_else(|| [vec!(table_name), columns.clone(), vec!("idx".to_string())].concat().join("_"));
+    if !tokens.is_done() {
+      let rest = tokens.rest();
+      self.left_out.push(format!("left out {} on index {}", rest, name));
+    }
+    self.tables[i].indexes.push(meta::Index{ name, columns, unique });
+    true
+  }
+
+  // dumps add keys and defaults to the tables they created, and set who owns them
+  fn alter_table(&mut self, tokens: &mut Tokens) -> bool {
+    tokens.eat_words(&["if", "exists"]);
+    tokens.eat_word("only");
+    let (schema, name) = match tokens.qualified_name(&self.dialect) {
+      None => return false
+    , Some(n) => n
+    };
+    let schema = self.schema(schema);
+    let i = match self.tables.iter().position(|t| t.schema == schema && t.name == name) {
+      None => return false
+    , Some(i) => i
+    };
+    let mut table = self.tables.remove(i);
+    for mut action in tokens.parts() {
+      self.alter_action(&mut table, &mut action);
+    }
+    self.tables.insert(i, table);
+    true
+  }
+
+  fn alter_action(&mut self, table: &mut DraftTable, tokens: &mut Tokens) {
+    let start = tokens.at;
+    let read = if tokens.eat_word("add") {
+      let constraint = if tokens.eat_word("constraint") { Some(tokens.name(&self.dialect)) } else { None };
+      let key = self.table_key(table, tokens, constraint.clone().flatten());
+      if !key && constraint.is_none() {
+        tokens.eat_word("column");
+        tokens.eat_words(&["if", "not", "exists"]);
+        self.column(table, tokens);
+      }
+      key || constraint.is_none()
+    } else if tokens.eat_word("alter") {
+      tokens.eat_word("column");
+      let name = tokens.name(&self.dialect);
+      match table.columns.iter_mut().find(|c| Some(&c.name) == name.as_ref()) {
+        None => false
+      , Some(column) => {
+          if tokens.eat_words(&["set", "default"]) {
+            column.default = Some(tokens.rest());
+          } else if tokens.eat_words(&["drop", "default"]) {
+            column.default = None;
+          } else if tokens.eat_words(&["set", "not", "null"]) {
+            column.nullable = false;
+          } else if tokens.eat_words(&["drop", "not", "null"]) {
+            column.nullable = true;
+          }
+          tokens.is_done()
+        }
+      }
+    } else {
+      tokens.eat_words(&["owner", "to"])
+    };
+    if !read {
+      tokens.at = start;
+      self.left_out.push(format!("left out {} on {}", tokens.rest(), table.display()));
+    }
+  }
+
+  fn finish(self) -> (meta::Database, Vec<String>) {
+    let Design{ dialect, mut schemas, tables, left_out } = self;
+    let tables: Vec<meta::Table> = tables.into_iter().map(|t| t.into_table(&dialect)).collect();
+    schemas.extend(tables.iter().map(|t| t.schema()).filter(|s| !s.is_empty()));
+    schemas.sort();
+    schemas.dedup();
+    (meta::Database::new(tables).with_schemas(schemas, dialect.default_schema), left_out)
+  }
+}
+
+// the words of a column's type with the numbers it takes, a schema in front of it is dropped
+fn sql_type(tokens: &mut Tokens) -> meta::SqlType {
+  let mut words: Vec<String> = Vec::new();
+  let mut arguments: Vec<i32> = Vec::new();
+  let mut array = false;
+  loop {
+    if tokens.is_symbol('(') && !words.is_empty() {
+      arguments = tokens.group().into_iter().flatten().filter_map(|mut p| p.rest().trim().parse().ok()).collect();
+    } else if tokens.eat_symbol('[') {
+      while !tokens.is_done() && !tokens.eat_symbol(']') {
+        tokens.skip();
+      }
+      array = true;
+    } else if tokens.eat_symbol('.') {
+      words.clear();
+    } else {
+      match tokens.peek() {
+        Some(Token::Word(w)) if !COLUMN_KEYWORDS.iter().any(|k| w.eq_ignore_ascii_case(k)) => {
+          words.push(w.to_lowercase());
+          tokens.at += 1;
+        }
+      , _ => break
+      }
+    }
+  }
+  let name = words.join(" ") + if array { "[]" } else { "" };
+  let (length, precision, scale) = match arguments.as_slice() {
+    [p] if name == "numeric" || name == "decimal" => (None, Some(*p), None)
+  , [l] => (Some(*l), None, None)
+  , [p, s] => (None, Some(*p), Some(*s))
+  , _ => (None, None, None)
+  };
+  meta::SqlType{ name, length, precision, scale }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::database::naming::NamingStrategy;
+  use crate::database::reverse;
+  use crate::lang::printer;
+
+  const DUMP: &str = r#"
+SET statement_timeout = 0;
+SELECT pg_catalog.set_config('search_path', '', false);
+CREATE SCHEMA shop;
+-- customers sign up once
+CREATE TABLE shop.customers (
+    id uuid PRIMARY KEY,
+    name character varying(80) NOT NULL,
+    points integer,
+    "Joined" timestamp(3) with time zone DEFAULT now(),
+    CONSTRAINT customers_name_key UNIQUE (name)
+);
+CREATE TABLE shop.orders (
+    customer uuid REFERENCES shop.customers (id) ON DELETE CASCADE,
+    quantity int NOT NULL CHECK (quantity > 0),
+    unit_price numeric(10,2),
+    tags text[]
+) PARTITION BY HASH (customer);
+CREATE INDEX orders_customer ON shop.orders USING btree (customer);
+CREATE VIEW shop.big_spenders AS SELECT * FROM shop.customers;
+ALTER TABLE ONLY shop.customers ALTER COLUMN points SET DEFAULT 0;
+ALTER TABLE shop.customers OWNER TO admin;
+"#;
+
+  fn shop_config() -> meta::DatabaseConfig {
+    let naming = NamingStrategy{ snake_case: true, pluralise: true, ..NamingStrategy::default() };
+    meta::DatabaseConfig::MockDb(meta::MockDbConfig{ schema_mapping: meta::SchemaMapping::PerNamespace, naming, ..meta::MockDbConfig::new(vec!()) })
+  }
+
+  #[test]
+  fn test_read_ddl() {
+    let (database, left_out) = read_ddl(DUMP, &shop_config());
+    let customers = database.table("shop", "customers").unwrap();
+    let described = customers.columns().iter().map(|c| {
+      format!("{} {} {} {}", c.name(), c.sql_type().unwrap().declaration(), c.nullable(), c.default().unwrap_or("-"))
+    }).collect::<Vec<String>>();
+    assert_eq!(described, vec!("id uuid false -", "name character varying(80) false -", "points integer true 0", "Joined timestamp with time zone(3) true now()"));
+    assert_eq!(customers.constraints().iter().map(|k| k.name.as_str()).collect::<Vec<&str>>(), vec!("customers_pkey", "customers_name_key"));
+    let orders = database.table("shop", "orders").unwrap();
+    assert_eq!(orders.constraints()[0], meta::Constraint{
+      name: "orders_customer_fkey".to_string(), kind: meta::ConstraintKind::ForeignKey("shop.customers".to_string()), columns: vec!("customer".to_string())
+    });
+    assert_eq!(orders.constraints()[1].name, "orders_quantity_check");
+    assert_eq!(orders.indexes()[0].columns, vec!("customer"));
+    assert_eq!(orders.columns()[2].sql_type().unwrap().declaration(), "numeric(10,2)");
+    assert_eq!(orders.columns()[3].data_type(), meta::ColumnType::Unknown("text[]".to_string()));
+    assert_eq!(left_out, vec!(
+      "left out ON DELETE CASCADE on shop.orders's reference to shop.customers"
+    , "left out PARTITION BY HASH (customer) on shop.orders"
+    , "left out CREATE VIEW shop.big_spenders AS SELECT * FROM shop.customers"
+    ));
+  }
+
+  #[test]
+  fn test_ddl_model() {
+    let db_config = shop_config();
+    let (database, _) = read_ddl(DUMP, &db_config);
+    let (ast, notes) = reverse::reverse("shop", &database, &db_config, None);
+    let source = printer::namespace_sources(&ast, &notes).remove("shop").unwrap();
+    assert!(source.contains("struct persists Customer\n"));
+    assert!(source.contains("customer:: Order -> Customer\n"));
+    assert!(source.contains("unit_price:: Order -> Float\n"));
+    let noted = printer::notes_source(&ast, &notes);
+    assert!(noted.contains("Customer.name: declared character varying(80) not null\n"));
+    assert!(noted.contains("Order.unit_price: declared numeric(10,2)\n"));
+  }
+
+  #[test]
+  fn test_mysql_keys() {
+    let db_config = meta::DatabaseConfig::Mysql(meta::MysqlConfig{
+      host: "localhost".to_string(), port: 3306, database: "shop".to_string(), user: "root".to_string(), password: None, naming: NamingStrategy::default()
+    });
+    let (database, left_out) = read_ddl("CREATE TABLE `shop_Customer` (`id` char(36) NOT NULL, `vip` tinyint(1), KEY `by_vip` (`vip`), PRIMARY KEY (`id`)) ENGINE=InnoDB", &db_config);
+    let table = database.table("", "shop_Customer").unwrap();
+    assert_eq!(table.columns().iter().map(|c| c.data_type().name()).collect::<Vec<String>>(), vec!("Id", "Bool"));
+    assert_eq!(table.indexes()[0].name, "by_vip");
+    assert_eq!(table.constraints()[0].name, "shop_Customer_pkey");
+    assert_eq!(left_out, vec!("left out ENGINE=InnoDB on shop_Customer"));
+  }
+}
@@ -0,0 +1,198 @@
+use std::collections::{HashMap, HashSet};
+
+use yaml_rust::{Yaml, YamlLoader};
+
+use crate::lang::{ast, ast_builder, internal};
+use crate::lang::printer::{self, Notes};
+
+// what a property or object type says that the model keeps in some other way
+const READ_KEYS: [&str; 7] = ["type", "$ref", "properties", "required", "title", "definitions", "$defs"];
+
+// reads the object types a JSON Schema document defines into entities of one namespace, each property
+// becoming a function of its entity and a $ref to another object type a function onto that entity.
+// Whatever else a schema says is noted, properties with no gimbal type are left out and so is a property
+// named like one an earlier object type already has, since a namespace names each function once
+pub fn read_json_schema(app_name: &str, namespace: &str, text: &str) -> Result<(ast::Application, Notes), String> {
+  if !printer::is_namespace(namespace) {
+    return Err(format!("{} can't be a namespace, name one with --namespace.", namespace));
+  }
+  // JSON is YAML too, and as JSON strings can't hold a tab every tab is whitespace YAML won't take
+  let document = YamlLoader::load_from_str(&text.replace('\t', " "))
+    .map_err(|e| format!("I couldn't read the JSON Schema: {}", e))?
+    .into_iter().next()
+    .ok_or_else(|| "The JSON Schema is empty.".to_string())?;
+  let mut notes = Notes::default();
+  let mut object_types: Vec<(String, ast::QualifiedName, &Yaml)> = Vec::new();
+  for (reference, key, schema) in schemas(&document) {
+    match entity_name(&key).filter(|_| !schema["properties"].is_badvalue()) {
+      None => notes.note_namespace(namespace, &format!("left out {}, only object types with properties can be entities", reference))
+    , Some(name) => object_types.push((reference, ast::QualifiedName::new(namespace, &name, None), schema))
+    };
+  }
+  let references: HashMap<String, ast::QualifiedName> = object_types.iter().map(|(r, qn, _)| (r.clone(), qn.clone())).collect();
+  let mut atypes: HashMap<ast::QualifiedName, ast::AType> = HashMap::new();
+  let mut function_names: HashSet<String> = HashSet::new();
+  for (reference, e_qn, schema) in object_types {
+    if atypes.contains_key(&e_qn) {
+      notes.note_namespace(namespace, &format!("left out {}, its name is taken by another object type", reference));
+      continue;
+    }
+    atypes.insert(e_qn.clone(), ast::AType::EntityType(ast::EntityType::new(e_qn.clone())));
+    other_keys(schema).iter().for_each(|n| notes.note(&e_qn, n));
+    let required: Vec<&str> = schema["required"].as_vec().into_iter().flatten().filter_map(|r| r.as_str()).collect();
+    for (property, property_schema) in schema["properties"].as_hash().into_iter().flatten() {
+      let name = property.as_str().unwrap_or("");
+      let codom = if !printer::is_function_name(name) {
+        Err("it can't be a function name".to_string())
+      } else if function_names.contains(name) {
+        Err("another object type has a property of that name".to_string())
+      } else {
+        codomain(property_schema, &references)
+      };
+      match codom {
+        Err(reason) => notes.note(&e_qn, &format!("left out property {}, {}", name, reason))
+      , Ok(codom) => {
+          function_names.insert(name.to_string());
+          let f_qn = ast::QualifiedName::new(namespace, name, Some((namespace, &e_qn.name())));
+          if required.contains(&name) {
+            notes.note(&f_qn, "required");
+          }
+          other_keys(property_schema).iter().for_each(|n| notes.note(&f_qn, n));
+          atypes.insert(f_qn.clone(), ast::AType::FunctionType(ast::FunctionType::new(f_qn, e_qn.clone(), codom)));
+        }
+      }
+    }
+  }
+  Ok((ast_builder::application(app_name, atypes), notes))
+}
+
+// the document itself and each schema it defines, with the reference that names it and the key it is defined under.
+// The document's own schema goes by its title
+fn schemas(document: &Yaml) -> Vec<(String, String, &Yaml)> {
+  let mut schemas: Vec<(String, String, &Yaml)> = Vec::new();
+  if !document["properties"].is_badvalue() {
+    schemas.push(("#".to_string(), document["title"].as_str().unwrap_or("").to_string(), document));
+  }
+  for section in ["definitions", "$defs"] {
+    document[section].as_hash().into_iter().flatten().for_each(|(key, schema)| {
+      let key = key.as_str().unwrap_or("").to_string();
+      schemas.push((format!("#/{}/{}", section, key), key, schema));
+    });
+  }
+  schemas
+}
+
+// the key capitalised, so order becomes Order. A key of more than one word gives no type name
+fn entity_name(key: &str) -> Option<String> {
+  let mut chars = key.chars();
+  let name = chars.next().map_or(String::new(), |c| c.to_ascii_uppercase().to_string() + chars.as_str());
+  Some(name).filter(|n| printer::is_type_name(n))
+}
+
+fn codomain(schema: &Yaml, references: &HashMap<String, ast::QualifiedName>) -> Result<ast::QualifiedName, String> {
+  if let Some(reference) = schema["$ref"].as_str() {
+    return references.get(reference).cloned().ok_or_else(|| format!("it refers to {}, which isn't an entity", reference));
+  }
+  // a type that may also be null is that type
+  let types: Vec<&str> = match &schema["type"] {
+    Yaml::String(t) => vec!(t.as_str())
+  , Yaml::Array(ts) => ts.iter().filter_map(|t| t.as_str()).filter(|t| *t != "null").collect()
+  , _ if schema["enum"].as_vec().is_some_and(|values| values.iter().all(|v| v.as_str().is_some())) => vec!("string")
+  , _ => vec!()
+  };
+  let leaf_type = match types.as_slice() {
+    ["string"] => internal::LeafType::String
+  , ["integer"] => internal::LeafType::Int
+  , ["number"] => internal::LeafType::Float
+  , ["boolean"] => internal::LeafType::Bool
+  , ["array"] => return Err("arrays have no gimbal type".to_string())
+  , ["object"] => return Err("objects inside objects have no gimbal type, define it as an object type of its own and refer to it".to_string())
+  , [] => return Err("it has no type".to_string())
+  , _ => return Err(format!("it can be any of {}", types.join(", ")))
+  };
+  Ok(ast::QualifiedName::new(internal::INTERNAL_NAMESPACE, &leaf_type.name(), None))
+}
+
+fn other_keys(schema: &Yaml) -> Vec<String> {
+  schema.as_hash().into_iter().flatten().filter_map(|(key, value)| {
+    let key = key.as_str()?;
+    if READ_KEYS.contains(&key) || key.starts_with("$schema") || key == "$id" { None } else { Some(format!("{} {}", key, display(value))) }
+  }).collect()
+}
+
+fn display(value: &Yaml) -> String {
+  match value {
+    Yaml::String(s) | Yaml::Real(s) => s.clone()
+  , Yaml::Integer(i) => i.to_string()
+  , Yaml::Boolean(b) => b.to_string()
+  , Yaml::Array(values) => format!("[{}]", values.iter().map(display).collect::<Vec<String>>().join(", "))
+  , Yaml::Hash(entries) => {
+      format!("{{{}}}", entries.iter().map(|(k, v)| format!("{}: {}", display(k), display(v))).collect::<Vec<String>>().join(", "))
+    }
+  , _ => "null".to_string()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SCHEMA: &str = r##"{
+	"$schema": "https://json-schema.org/draft/2020-12/schema",
+	"title": "order",
+	"type": "object",
+	"required": ["customer"],
+	"properties": {
+		"customer": {"$ref": "#/$defs/customer"},
+		"name": {"type": "string"},
+		"total": {"type": ["number", "null"], "minimum": 0},
+		"lines": {"type": "array", "items": {"type": "string"}},
+		"status": {"enum": ["open", "paid"]},
+		"placed-at": {"type": "string", "format": "date-time"}
+	},
+	"$defs": {
+		"customer": {
+			"type": "object",
+			"additionalProperties": false,
+			"properties": {"name": {"type": "string", "maxLength": 80}, "vip": {"type": "boolean"}, "visits": {"type": "integer"}}
+		},
+		"money": {"type": "number"}
+	}
+}"##;
+
+  #[test]
+  fn test_read_json_schema() {
+    let (ast, notes) = read_json_schema("shop", "sales", SCHEMA).unwrap();
+    let source = printer::namespace_sources(&ast, &notes).remove("sales").unwrap();
+    assert_eq!(source, "namespace sales where
+
+struct persists Customer
+vip:: Customer -> Bool
+visits:: Customer -> Int
+
+struct persists Order
+customer:: Order -> Customer
+name:: Order -> String
+status:: Order -> String
+total:: Order -> Float
+");
+    assert_eq!(printer::notes_source(&ast, &notes), "namespace sales
+left out #/$defs/money, only object types with properties can be entities
+Customer: additionalProperties false
+Customer: left out property name, another object type has a property of that name
+Order: left out property lines, arrays have no gimbal type
+Order: left out property placed-at, it can't be a function name
+Order.customer: required
+Order.status: enum [open, paid]
+Order.total: minimum 0
+");
+    let compiled = ast_builder::build(&format!("app shop\n{}", source)).unwrap();
+    assert_eq!(compiled.entity_types().len(), 2);
+  }
+
+  #[test]
+  fn test_json_schema_errors() {
+    assert_eq!(read_json_schema("shop", "Sales", SCHEMA).unwrap_err(), "Sales can't be a namespace, name one with --namespace.");
+    assert!(read_json_schema("shop", "sales", "{\"a\": [}").unwrap_err().starts_with("I couldn't read the JSON Schema"));
+  }
+}
@@ -0,0 +1,3 @@
+// models read from files a design was kept in instead of a live database
+pub mod ddl;
+pub mod json_schema;
@@ -1,16 +1,20 @@
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::error;
+use std::time::Duration;
 
 use crate::lang::{ast, internal};
-use crate::database::drivers::mock;
-use crate::database::drivers::postgres;
+use crate::database::drivers;
 use crate::database::meta;
+use crate::database::migrations;
+use crate::database::naming::NamingStrategy;
+use crate::database::operations;
 
 
 #[derive(Debug)]
 pub struct DbDiff {
   entity_table: AstTable
+, database_table: Option<meta::Table>
 , diff_diagnosis: Vec<DiffDiagnosis>
 }
 
@@ -22,6 +26,14 @@ impl DbDiff {
   pub fn db_table(&self) -> &meta::Table {
     &self.entity_table.table
   }
+
+  pub fn database_table(&self) -> Option<&meta::Table> {
+    self.database_table.as_ref()
+  }
+
+  pub fn entity_name(&self) -> &ast::QualifiedName {
+    &self.entity_table.entity_name
+  }
 }
 
 #[derive(Debug)]
@@ -33,30 +45,39 @@ pub struct AstTable {
 #[derive(Debug, PartialEq)]
 pub enum DiffDiagnosis {
   NoDiff
+, SchemaMissing
 , TableMissing
+, TableMoved(String)
 , ColumnMissing(String)
-, ColumnTypeMismatch(String, internal::LeafType, internal::LeafType)
+, ColumnTypeMismatch(String, meta::ColumnType, meta::ColumnType)
 }
 
-fn ast_to_db(ast: &ast::Application) -> meta::Database {
-  let tables = ast.entity_functions().iter().map(|(k, _)| {
-    entity_to_table(ast, k)
-  }).collect::<Vec<meta::Table>>();
-  meta::Database::new(tables)
+fn entity_to_table(ast: &ast::Application, qn: &ast::QualifiedName, schema_mapping: meta::SchemaMapping, naming: &NamingStrategy) ->  meta::Table {
+  let fn_qns = ast.get_entity_functions(qn).expect("entity not found");
+  meta::Table::new(&entity_schema(qn, schema_mapping), &entity_table_name(qn, schema_mapping, naming), functions_to_columns(ast, fn_qns, naming))
 }
 
-fn entity_to_table(ast: &ast::Application, qn: &ast::QualifiedName) ->  meta::Table {
-  let fn_qns = ast.get_entity_functions(qn).expect("entity not found");
-  meta::Table::new("schema", &qn.table_name(), functions_to_columns(ast, fn_qns))
+fn entity_schema(qn: &ast::QualifiedName, schema_mapping: meta::SchemaMapping) -> String {
+  match schema_mapping {
+    meta::SchemaMapping::Flatten => "".to_string()
+  , meta::SchemaMapping::PerNamespace => qn.namespace()
+  }
 }
 
-fn functions_to_columns(ast: &ast::Application, qn: &Vec<ast::QualifiedName>) -> Vec<meta::Column> {
+pub fn entity_table_name(qn: &ast::QualifiedName, schema_mapping: meta::SchemaMapping, naming: &NamingStrategy) -> String {
+  match schema_mapping {
+    meta::SchemaMapping::Flatten => qn.table_name(naming)
+  , meta::SchemaMapping::PerNamespace => naming.table_name(&qn.name())
+  }
+}
+
+fn functions_to_columns(ast: &ast::Application, qn: &Vec<ast::QualifiedName>, naming: &NamingStrategy) -> Vec<meta::Column> {
   qn.iter().map(|qn| {
-    function_to_column(ast, qn)
+    function_to_column(ast, qn, naming)
   }).collect::<Vec<meta::Column>>()
 } 
 
-fn function_to_column(ast: &ast::Application, qn: &ast::QualifiedName) -> meta::Column {
+fn function_to_column(ast: &ast::Application, qn: &ast::QualifiedName, naming: &NamingStrategy) -> meta::Column {
   let a =  ast.get_type(&qn).expect("function not found");
   let c_qn = match a {
     ast::AType::FunctionType(f) => f.codom()
@@ -64,34 +85,159 @@ fn function_to_column(ast: &ast::Application, qn: &ast::QualifiedName) -> meta::
   };
   let c = ast.get_type(&c_qn).expect("codom not found");
   match c {
-    ast::AType::LeafType(lt) => meta::Column::new(&qn.name(), (*lt).clone())
-  , ast::AType::EntityType(_) => meta::Column::new(&qn.name(), internal::LeafType::Id)
+    ast::AType::LeafType(lt) => meta::Column::new(&naming.column_name(&qn.name()), (*lt).clone())
+  , ast::AType::EntityType(_) => meta::Column::new(&naming.column_name(&qn.name()), internal::LeafType::Id)
   , ast::AType::FunctionType(_) => {
-      meta::Column::new(&(qn.name() + "_param")
+      meta::Column::new(&naming.column_name(&(qn.name() + "_param"))
                                       , internal::LeafType::String)
     }
   }  
 }
 
-pub fn diagnose_db_diffs(ast: &ast::Application, db_config: &meta::DatabaseConfig) -> Vec<DbDiff> {
-  ast.entity_functions().keys().map(|e_qn| diagnose_diff(ast, db_config, e_qn)).collect()
+// identifies the tables a model asks for, migration scripts record the one they were generated from
+pub fn model_hash(ast: &ast::Application, db_config: &meta::DatabaseConfig) -> String {
+  let description: String = ordered_entities(ast).iter().map(|e_qn| {
+    describe_table(&entity_to_table(ast, e_qn, db_config.schema_mapping(), db_config.naming())) + "\n"
+  }).collect();
+  migrations::checksum(&description)
 }
 
-fn diagnose_diff(ast: &ast::Application, db_config: &meta::DatabaseConfig, entity_qname: &ast::QualifiedName) -> DbDiff {
-  let entity_table = entity_to_table(ast, entity_qname);
-  let database_table = match db_config {
-    meta::DatabaseConfig::MockDb(_) => mock::db_table_for_ast_table(db_config, &entity_table)
-  , meta::DatabaseConfig::Postgres(_) => postgres::db_table_for_ast_table(db_config, &entity_table)
-  };
-  let diff_diagnosis = diagnose_table(&entity_table, database_table);
+// identifies what the whole database looked like, not just the model's tables, so a plan can tell whether
+// anything has changed before it is applied. Gimbal's own tables change with every migration so they're left out
+pub fn schema_fingerprint(database: &meta::Database) -> String {
+  let own = [migrations::HISTORY_TABLE, migrations::CHECKPOINT_TABLE, migrations::LOCK_TABLE];
+  let tables: String = database.tables().iter().filter(|t| !own.contains(&t.name().as_str())).map(|t| describe_database_table(t) + "\n").collect();
+  migrations::checksum(&format!("{:?}\n{}", database.schemas(), tables))
+}
+
+pub fn database_fingerprint(schemas: &[String], db_config: &meta::DatabaseConfig) -> Result<String, String> {
+  introspect(Some(schemas), db_config).map(|d| schema_fingerprint(&d))
+}
+
+fn describe_table(table: &meta::Table) -> String {
+  let columns = table.columns().iter().map(|c| format!("{} {}", c.name(), c.data_type().name())).collect::<Vec<String>>().join(", ");
+  format!("{}.{} ({})", table.schema(), table.name(), columns)
+}
+
+// everything introspection found, so a changed default or a new index also changes the fingerprint
+fn describe_database_table(table: &meta::Table) -> String {
+  let columns = table.columns().iter().map(|c| {
+    let declared = c.sql_type().map_or(c.data_type().name(), |t| t.declaration());
+    format!("{} {} {} {}", c.name(), declared, if c.nullable() { "null" } else { "not null" }, c.default().unwrap_or(""))
+  }).collect::<Vec<String>>().join(", ");
+  format!("{}.{} ({}) {:?} {:?}", table.schema(), table.name(), columns, table.constraints(), table.indexes())
+}
+
+// reads every schema when there are no schemas to narrow it to
+pub fn introspect(schemas: Option<&[String]>, db_config: &meta::DatabaseConfig) -> Result<meta::Database, String> {
+  drivers::driver_for(db_config)?.introspect(schemas, db_config)
+}
+
+// the schemas the model's tables go in, none when they are flattened into the default one
+pub fn app_schemas(ast: &ast::Application, db_config: &meta::DatabaseConfig) -> Vec<String> {
+  let mut schemas: Vec<String> = ordered_entities(ast).iter()
+    .map(|e_qn| entity_to_table(ast, e_qn, db_config.schema_mapping(), db_config.naming()).schema())
+    .filter(|s| !s.is_empty())
+    .collect();
+  schemas.sort();
+  schemas.dedup();
+  schemas
+}
+
+pub fn diagnose_db_diffs(ast: &ast::Application, db_config: &meta::DatabaseConfig) -> Result<Vec<DbDiff>, String> {
+  let database = introspect(Some(&app_schemas(ast, db_config)), db_config)?;
+  Ok(diagnose_snapshot(ast, db_config, &database))
+}
+
+pub fn diagnose_snapshot(ast: &ast::Application, db_config: &meta::DatabaseConfig, database: &meta::Database) -> Vec<DbDiff> {
+  ordered_entities(ast).iter().map(|e_qn| diagnose_diff(ast, db_config, database, e_qn)).collect()
+}
+
+// data can only be written into tables that are the way the model has them
+pub fn check_matches_model(db_diffs: &[DbDiff], database: &str) -> Result<(), String> {
+  match db_diffs.iter().find(|d| d.diff_diagnosis().iter().any(|diagnosis| *diagnosis != DiffDiagnosis::NoDiff)) {
+    Some(d) => Err(format!("{} doesn't match the model, {} is out of date. Migrate it first.", database, d.db_table().name()))
+  , None => Ok(())
+  }
+}
+
+// tables in the schemas the model uses that no entity maps to, the migration history aside
+pub fn extra_tables(db_diffs: &[DbDiff], database: &meta::Database) -> Vec<meta::Table> {
+  let schemas: BTreeSet<String> = db_diffs.iter().map(|d| d.db_table().schema()).collect();
+  let claimed = |t: &meta::Table| db_diffs.iter().any(|d| is_table(database.table(&d.db_table().schema(), &d.db_table().name()), t));
+  schemas.iter()
+    .flat_map(|schema| database.tables_in_schema(schema))
+    .filter(|t| ![migrations::HISTORY_TABLE, migrations::CHECKPOINT_TABLE, migrations::LOCK_TABLE].contains(&t.name().as_str()) && !claimed(t))
+    .cloned()
+    .collect()
+}
+
+fn is_table(found: Option<&meta::Table>, table: &meta::Table) -> bool {
+  found.is_some_and(|f| f.schema() == table.schema() && f.name() == table.name())
+}
+
+// entities sorted by name and then so that any entity comes after the entities its functions refer to,
+// this keeps generated scripts identical from run to run. Anything left in a cycle keeps its sorted position.
+fn ordered_entities(ast: &ast::Application) -> Vec<ast::QualifiedName> {
+  let mut pending: BTreeMap<ast::QualifiedName, BTreeSet<ast::QualifiedName>> = ast.entity_functions().keys()
+    .map(|e_qn| (e_qn.clone(), entity_dependencies(ast, e_qn))).collect();
+  let mut ordered: Vec<ast::QualifiedName> = Vec::new();
+  while !pending.is_empty() {
+    let ready = pending.iter()
+      .find(|(_, deps)| deps.iter().all(|d| !pending.contains_key(d)))
+      .map(|(e_qn, _)| e_qn.clone())
+      .unwrap_or_else(|| pending.keys().next().unwrap().clone());
+    pending.remove(&ready);
+    ordered.push(ready);
+  }
+  ordered
+}
+
+fn entity_dependencies(ast: &ast::Application, entity_qname: &ast::QualifiedName) -> BTreeSet<ast::QualifiedName> {
+  let fn_qns = ast.get_entity_functions(entity_qname).expect("entity not found");
+  fn_qns.iter().filter_map(|qn| {
+    let codom = ast.get_type(qn)?.try_to_function_type()?.codom();
+    match ast.get_type(&codom)? {
+      ast::AType::EntityType(_) if &codom != entity_qname => Some(codom)
+    , _ => None
+    }
+  }).collect()
+}
+
+fn diagnose_diff(ast: &ast::Application, db_config: &meta::DatabaseConfig, database: &meta::Database, entity_qname: &ast::QualifiedName) -> DbDiff {
+  let entity_table = entity_to_table(ast, entity_qname, db_config.schema_mapping(), db_config.naming());
+  let database_table = database.table(&entity_table.schema(), &entity_table.name()).cloned();
+  let mut diff_diagnosis = diagnose_schema(database, &entity_table);
+  diff_diagnosis.extend(match &database_table {
+    Some(dt) => diagnose_columns(entity_table.columns(), dt.columns())
+  , None => diagnose_moved_table(ast, db_config, database, &entity_table)
+  });
   let ast_table = AstTable{ entity_name: entity_qname.clone(), table: entity_table };
-  DbDiff{ entity_table: ast_table, diff_diagnosis }
+  DbDiff{ entity_table: ast_table, database_table, diff_diagnosis }
+}
+
+fn diagnose_schema(database: &meta::Database, entity_table: &meta::Table) -> Vec<DiffDiagnosis> {
+  let schema = entity_table.schema();
+  if schema.is_empty() || database.has_schema(&schema) { vec!() } else { vec!(DiffDiagnosis::SchemaMissing) }
 }
 
-fn diagnose_table(entity_table: &meta::Table, database_table: Option<meta::Table>) -> Vec<DiffDiagnosis> {
-  match database_table {
+// a table of the same name in another schema that no entity claims is taken to be this entity
+// before its namespace changed, so it is moved rather than created again
+fn diagnose_moved_table(ast: &ast::Application, db_config: &meta::DatabaseConfig, database: &meta::Database, entity_table: &meta::Table) -> Vec<DiffDiagnosis> {
+  if entity_table.schema().is_empty() {
+    return vec!(DiffDiagnosis::TableMissing);
+  }
+  let claimed = |t: &meta::Table| ast.entity_functions().keys().any(|qn| {
+    is_table(database.table(&entity_schema(qn, db_config.schema_mapping()), &entity_table_name(qn, db_config.schema_mapping(), db_config.naming())), t)
+  });
+  let moved_from = database.tables_named(&entity_table.name()).into_iter().find(|t| t.schema() != entity_table.schema() && !claimed(t));
+  match moved_from {
     None => vec!(DiffDiagnosis::TableMissing)
-  , Some(dt) => diagnose_columns(entity_table.columns(), dt.columns())
+  , Some(mt) => {
+      let mut diagnosis = vec!(DiffDiagnosis::TableMoved(mt.schema()));
+      diagnosis.extend(diagnose_columns(entity_table.columns(), mt.columns()));
+      diagnosis
+    }
   }
 }
 
@@ -112,18 +258,31 @@ fn diagnose_column(entity_column: &meta::Column, database_columns: &Vec<meta::Co
   }
 }
 
-pub fn diffs_to_script(db_diffs: &Vec<DbDiff>, db_config: &meta::DatabaseConfig) -> meta::DatabaseChange {
-  match db_config {
-    meta::DatabaseConfig::MockDb(_) => mock::diffs_to_changes(db_diffs, db_config)
-  , meta::DatabaseConfig::Postgres(_) => postgres::diffs_to_changes(db_diffs, db_config)
-  }
+pub fn operations_to_script(operations: &[operations::Operation], db_config: &meta::DatabaseConfig) -> Result<meta::DatabaseChange, String> {
+  drivers::driver_for(db_config)?.changes(operations, db_config)
+}
+
+// the down script runs the reverse operations after a marker for each planned operation that loses data,
+// its reverse brings back the structure but not the data so rollback refuses to run it
+pub fn down_script(planned: &[operations::Operation], reverse: &[operations::Operation], db_config: &meta::DatabaseConfig) -> Result<meta::DatabaseChange, String> {
+  let markers = planned.iter().filter(|o| o.is_destructive()).map(|o| format!("{} {}", meta::IRREVERSIBLE_MARKER, o.describe()));
+  let down = operations_to_script(reverse, db_config)?;
+  Ok(meta::DatabaseChange::SqlDb(markers.chain(down.commands().iter().cloned()).collect()))
+}
+
+// a migration's history changes with its statements or not at all
+pub fn migrate_db(db_changes: &meta::DatabaseChange, history: Option<&migrations::HistoryChange>, db_config: &meta::DatabaseConfig) -> Result<(), String> {
+  drivers::driver_for(db_config)?.execute_changes(db_changes, history, db_config)
 }
 
-pub fn migrate_db(db_changes: &meta::DatabaseChange, db_config: &meta::DatabaseConfig) -> Result<(), String> {
-  match db_config {
-    meta::DatabaseConfig::MockDb(_) => mock::execute_changes(db_changes, db_config)
-  , meta::DatabaseConfig::Postgres(_) => postgres::execute_changes(db_changes, db_config)
-  } 
+pub fn applied_migrations(db_config: &meta::DatabaseConfig) -> Result<Vec<migrations::AppliedMigration>, String> {
+  drivers::driver_for(db_config)?.applied_migrations(db_config)
+}
+
+
+// nothing else migrates the database while the lock lives, holder says who took it to whoever waits for it
+pub fn lock<'a>(app: &str, holder: &str, timeout: Duration, db_config: &'a meta::DatabaseConfig) -> Result<Box<dyn migrations::HeldLock + 'a>, String> {
+  drivers::driver_for(db_config)?.lock(app, holder, timeout, db_config)
 }
 
 #[cfg(test)]
@@ -143,10 +302,9 @@ struct persists Agent
 name:: Agent -> String"#;
 
     let ast = ast_builder::build(code).unwrap();
-    let ast_db = ast_to_db(&ast);
-    let mock_db_config = meta::DatabaseConfig::MockDb(meta::MockDbConfig{ tables: Vec::new() });
-    let db_diff = diagnose_db_diffs(&ast, &mock_db_config);
-    assert_eq!(ast_db.tables()[0].name(),  "db_Agent");
+    let mock_db_config = meta::DatabaseConfig::MockDb(meta::MockDbConfig::new(Vec::new()));
+    let db_diff = diagnose_db_diffs(&ast, &mock_db_config).unwrap();
+    assert_eq!(db_diff[0].db_table().name(), "db_Agent");
     //println!("{:?}", db_diff);
     assert!(matches!(db_diff[0].diff_diagnosis[0], DiffDiagnosis::TableMissing));
   }
@@ -163,12 +321,11 @@ struct persists Agent
 name:: Agent -> String"#;
 
     let ast = ast_builder::build(code).unwrap();
-    let ast_db = ast_to_db(&ast);
     let mock_column = meta::Column::new("name", internal::LeafType::String);
     let mock_table = meta::Table::new("schema", "db_Agent", vec!(mock_column));
-    let mock_db_config = meta::DatabaseConfig::MockDb(meta::MockDbConfig{ tables: vec!(mock_table) });
-    let db_diff = diagnose_db_diffs(&ast, &mock_db_config);
-    assert_eq!(ast_db.tables()[0].name(),  "db_Agent");
+    let mock_db_config = meta::DatabaseConfig::MockDb(meta::MockDbConfig::new(vec!(mock_table)));
+    let db_diff = diagnose_db_diffs(&ast, &mock_db_config).unwrap();
+    assert_eq!(db_diff[0].db_table().name(), "db_Agent");
     assert!(matches!(db_diff[0].diff_diagnosis[0], DiffDiagnosis::NoDiff));
   }
 
@@ -184,14 +341,13 @@ struct persists Agent
 name:: Agent -> String"#;
 
     let ast = ast_builder::build(code).unwrap();
-    let ast_db = ast_to_db(&ast);
     let _entity_column_name = "name".to_string();
     let _mock_column_name = "another_column".to_string();
     let mock_column = meta::Column::new(&_mock_column_name, internal::LeafType::String);
     let mock_table = meta::Table::new("schema", "db_Agent", vec!(mock_column));
-    let mock_db_config = meta::DatabaseConfig::MockDb(meta::MockDbConfig{ tables: vec!(mock_table) });
-    let db_diff = diagnose_db_diffs(&ast, &mock_db_config);
-    assert_eq!(ast_db.tables()[0].name(),  "db_Agent");
+    let mock_db_config = meta::DatabaseConfig::MockDb(meta::MockDbConfig::new(vec!(mock_table)));
+    let db_diff = diagnose_db_diffs(&ast, &mock_db_config).unwrap();
+    assert_eq!(db_diff[0].db_table().name(), "db_Agent");
     assert!(matches!(&db_diff[0].diff_diagnosis[0], DiffDiagnosis::ColumnMissing(_entity_column_name)));
   }
 
@@ -207,15 +363,153 @@ struct persists Agent
 name:: Agent -> String"#;
 
     let ast = ast_builder::build(code).unwrap();
-    let ast_db = ast_to_db(&ast);
     let _entity_column_name = "name".to_string();
     let mock_column = meta::Column::new(&_entity_column_name, internal::LeafType::Int);
     let mock_table = meta::Table::new("schema", "db_Agent", vec!(mock_column));
-    let mock_db_config = meta::DatabaseConfig::MockDb(meta::MockDbConfig{ tables: vec!(mock_table) });
-    let db_diff = diagnose_db_diffs(&ast, &mock_db_config);
-    let db_change = diffs_to_script(&db_diff, &mock_db_config);
-    assert_eq!(ast_db.tables()[0].name(),  "db_Agent");
-    assert!(matches!(&db_diff[0].diff_diagnosis[0], DiffDiagnosis::ColumnTypeMismatch(_entity_column_name, internal::LeafType::String, internal::LeafType::Int)));
+    let mock_db_config = meta::DatabaseConfig::MockDb(meta::MockDbConfig::new(vec!(mock_table)));
+    let db_diff = diagnose_db_diffs(&ast, &mock_db_config).unwrap();
+    assert!(operations_to_script(&operations::plan(&db_diff), &mock_db_config).is_ok());
+    assert_eq!(db_diff[0].db_table().name(), "db_Agent");
+    assert!(matches!(&db_diff[0].diff_diagnosis[0], DiffDiagnosis::ColumnTypeMismatch(_entity_column_name, meta::ColumnType::Known(internal::LeafType::String), meta::ColumnType::Known(internal::LeafType::Int))));
+  }
+
+  #[test]
+  fn test_dependency_order() {
+    let code = r#"
+app database
+
+namespace db where
+
+struct persists Agent
+struct persists Resource
+struct persists Account
+
+name:: Agent -> String
+owner:: Resource -> Agent
+holder:: Account -> Resource
+email:: Agent -> String"#;
+
+    let ast = ast_builder::build(code).unwrap();
+    let mock_db_config = meta::DatabaseConfig::MockDb(meta::MockDbConfig::new(Vec::new()));
+    let table_names = |diffs: &Vec<DbDiff>| diffs.iter().map(|d| d.db_table().name()).collect::<Vec<String>>();
+    let first = diagnose_db_diffs(&ast, &mock_db_config).unwrap();
+    assert_eq!(table_names(&first), vec!("db_Agent", "db_Resource", "db_Account"));
+    assert_eq!(table_names(&first), table_names(&diagnose_db_diffs(&ast, &mock_db_config).unwrap()));
+    let columns = first[0].db_table().columns().iter().map(|c| c.name()).collect::<Vec<String>>();
+    assert_eq!(columns, vec!("email", "name"));
+  }
+
+  #[test]
+  fn test_schema_per_namespace() {
+    let code = r#"
+app database
+
+namespace db where
+
+struct persists Agent
+
+name:: Agent -> String"#;
+
+    let ast = ast_builder::build(code).unwrap();
+    let mock_db_config = meta::DatabaseConfig::MockDb(meta::MockDbConfig{ schema_mapping: meta::SchemaMapping::PerNamespace, ..meta::MockDbConfig::new(Vec::new()) });
+    let db_diff = diagnose_db_diffs(&ast, &mock_db_config).unwrap();
+    assert_eq!(db_diff[0].db_table().schema(), "db");
+    assert_eq!(db_diff[0].db_table().name(), "Agent");
+    assert_eq!(db_diff[0].diff_diagnosis, vec!(DiffDiagnosis::SchemaMissing, DiffDiagnosis::TableMissing));
+  }
+
+  #[test]
+  fn test_table_moved_namespace() {
+    let code = r#"
+app database
+
+namespace db where
+
+struct persists Agent
+
+name:: Agent -> String"#;
+
+    let ast = ast_builder::build(code).unwrap();
+    let mock_column = meta::Column::new("name", internal::LeafType::String);
+    let mock_tables = vec!(meta::Table::new("db", "Other", vec!()), meta::Table::new("old", "Agent", vec!(mock_column)));
+    let mock_db_config = meta::DatabaseConfig::MockDb(meta::MockDbConfig{ schema_mapping: meta::SchemaMapping::PerNamespace, ..meta::MockDbConfig::new(mock_tables) });
+    let db_diff = diagnose_db_diffs(&ast, &mock_db_config).unwrap();
+    assert_eq!(db_diff[0].diff_diagnosis, vec!(DiffDiagnosis::TableMoved("old".to_string()), DiffDiagnosis::NoDiff));
+    assert_eq!(app_schemas(&ast, &mock_db_config), vec!("db"));
+  }
+
+  #[test]
+  fn test_snapshot_default_schema() {
+    let code = r#"
+app database
+
+namespace db where
+
+struct persists Agent
+
+name:: Agent -> String"#;
+
+    let ast = ast_builder::build(code).unwrap();
+    let mock_db_config = meta::DatabaseConfig::MockDb(meta::MockDbConfig::new(Vec::new()));
+    let elsewhere = meta::Table::new("other", "db_Agent", vec!(meta::Column::new("name", internal::LeafType::String)));
+    let stray = meta::Table::new("public", "stray", vec!());
+    let database = meta::Database::new(vec!(elsewhere, stray)).with_schemas(vec!("other".to_string(), "public".to_string()), "public");
+    let db_diff = diagnose_snapshot(&ast, &mock_db_config, &database);
+    assert_eq!(db_diff[0].diff_diagnosis, vec!(DiffDiagnosis::TableMissing));
+    let extras = extra_tables(&db_diff, &database);
+    assert_eq!(extras.iter().map(|t| t.name()).collect::<Vec<String>>(), vec!("stray"));
+  }
+
+  #[test]
+  fn test_naming_strategy() {
+    let code = r#"
+app database
+
+namespace db where
+
+struct persists Category
+
+display_name:: Category -> String"#;
+
+    let ast = ast_builder::build(code).unwrap();
+    let naming = NamingStrategy{ snake_case: true, pluralise: true, ..NamingStrategy::default() };
+    let mock_column = meta::Column::new("display_name", internal::LeafType::String);
+    let mock_table = meta::Table::new("schema", "db_categories", vec!(mock_column));
+    let mock_db_config = meta::DatabaseConfig::MockDb(meta::MockDbConfig{ naming, ..meta::MockDbConfig::new(vec!(mock_table)) });
+    let db_diff = diagnose_db_diffs(&ast, &mock_db_config).unwrap();
+    assert_eq!(db_diff[0].db_table().name(), "db_categories");
+    assert_eq!(db_diff[0].diff_diagnosis, vec!(DiffDiagnosis::NoDiff));
+  }
+
+  #[test]
+  fn test_plan_summary() {
+    let code = r#"
+app database
+
+namespace db where
+
+struct persists Agent
+struct persists Resource
+
+name:: Agent -> String
+age:: Agent -> Int
+label:: Resource -> String"#;
+
+    let ast = ast_builder::build(code).unwrap();
+    let mock_table = meta::Table::new("schema", "db_Agent", vec!(meta::Column::new("name", internal::LeafType::Float)));
+    let mock_db_config = meta::DatabaseConfig::MockDb(meta::MockDbConfig::new(vec!(mock_table.clone())));
+    let db_diff = diagnose_db_diffs(&ast, &mock_db_config).unwrap();
+    let summary = operations::summarise(&operations::plan(&db_diff));
+    assert_eq!(summary.additions, vec!("create table db_Resource", "add column db_Agent.age"));
+    assert_eq!(summary.changes, vec!("change column db_Agent.name from Float to String"));
+    assert!(summary.destructive.is_empty());
+    let fingerprint = database_fingerprint(&[], &mock_db_config).unwrap();
+    assert_eq!(fingerprint, database_fingerprint(&app_schemas(&ast, &mock_db_config), &mock_db_config).unwrap());
+    let history = meta::Table::new("", migrations::HISTORY_TABLE, vec!(meta::Column::new("version", internal::LeafType::Int)));
+    assert_eq!(fingerprint, schema_fingerprint(&meta::Database::new(vec!(mock_table.clone(), history))));
+    // a table outside the model is part of the database too
+    let other = meta::Table::new("", "audit", vec!(meta::Column::new("entry", internal::LeafType::String)));
+    assert_ne!(fingerprint, schema_fingerprint(&meta::Database::new(vec!(mock_table, other))));
   }
 
   #[test]
@@ -229,10 +523,26 @@ struct persists Agent
 
 name:: Agent -> String"#;
     let ast = ast_builder::build(code).unwrap();
-    let ast_db = ast_to_db(&ast);
-    let mock_db_config = meta::DatabaseConfig::MockDb(meta::MockDbConfig{ tables: Vec::new() });
-    let db_diff = diagnose_db_diffs(&ast, &mock_db_config);
-    let db_change = diffs_to_script(&db_diff, &mock_db_config);
-    assert!(migrate_db(&db_change, &mock_db_config).is_ok());
+    let mock_db_config = meta::DatabaseConfig::MockDb(meta::MockDbConfig::new(Vec::new()));
+    let db_diff = diagnose_db_diffs(&ast, &mock_db_config).unwrap();
+    let db_change = operations_to_script(&operations::plan(&db_diff), &mock_db_config).unwrap();
+    assert!(migrate_db(&db_change, None, &mock_db_config).is_ok());
   }
-}
\ No newline at end of file
+
+  #[test]
+  fn test_irreversible_down_script() {
+    let db_config = meta::DatabaseConfig::Sqlite(meta::SqliteConfig{ path: "unused.db".to_string(), naming: NamingStrategy::default() });
+    let table = operations::TableRef::new("", "db_Agent");
+    let column = meta::Column::new("age", internal::LeafType::Int);
+    let added = vec!(operations::Operation::AddColumn(table.clone(), column.clone()));
+    let down = down_script(&added, &operations::reverse(&added), &db_config).unwrap();
+    assert_eq!(down.commands(), &vec!(r#"ALTER TABLE "db_Agent" DROP COLUMN "age""#.to_string()));
+
+    let dropped = vec!(operations::Operation::DropColumn(table, column));
+    let down = down_script(&dropped, &operations::reverse(&dropped), &db_config).unwrap();
+    assert_eq!(down.commands(), &vec!(
+      format!("{} drop column db_Agent.age", meta::IRREVERSIBLE_MARKER)
+    , r#"ALTER TABLE "db_Agent" ADD COLUMN "age" integer"#.to_string()
+    ));
+  }
+}
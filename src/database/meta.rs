@@ -1,21 +1,41 @@
 
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Mutex;
+
 use crate::lang::internal;
+use crate::database::migrations;
+use crate::database::operations;
+use crate::database::seed;
+use crate::database::naming::NamingStrategy;
+
+// starts a statement that postgres won't run inside a transaction, CREATE INDEX CONCURRENTLY for instance
+pub const NO_TRANSACTION_MARKER: &str = "/* gimbal:no-transaction */";
+
+// starts the first statement of each phase of a staged migration, followed by the phase's name and */
+pub const PHASE_MARKER: &str = "/* gimbal:phase=";
+
+// stands in for the reverse of a change that can't be undone, rollback refuses to run past it.
+// It isn't a header so it doesn't share their prefix
+pub const IRREVERSIBLE_MARKER: &str = "-- irreversible:";
 
 
 #[derive(Debug)]
 pub enum DatabaseChange {
-  MockDb(DatabaseConfig)
+  MockDb(Vec<operations::Operation>)
 , SqlDb(Vec<String>)
 }
 
-impl DatabaseChange {
-  pub fn to_string(&self) -> String {
+impl fmt::Display for DatabaseChange {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     match self {
-      DatabaseChange::MockDb(_) => "I'm just a mock".to_string()
-    , DatabaseChange::SqlDb(s) => s.join("\n")
+      DatabaseChange::MockDb(operations) => write!(f, "{}", operations.iter().map(|o| format!("{:?}", o)).collect::<Vec<String>>().join("\n"))
+    , DatabaseChange::SqlDb(s) => write!(f, "{}", s.join("\n"))
     }
   }
+}
 
+impl DatabaseChange {
   pub fn commands(&self) -> &Vec<String> {
     match self {
       DatabaseChange::SqlDb(s) => s
@@ -27,43 +47,222 @@ impl DatabaseChange {
 #[derive(Debug)]
 pub enum DatabaseConfig {
   MockDb(MockDbConfig)
-, Postgres(String)
+, Postgres(PostgresConfig)
+, Sqlite(SqliteConfig)
+, Mysql(MysqlConfig)
+, Duckdb(DuckdbConfig)
 }
 
+impl DatabaseConfig {
+  pub fn schema_mapping(&self) -> SchemaMapping {
+    match self {
+      DatabaseConfig::MockDb(c) => c.schema_mapping
+    , DatabaseConfig::Postgres(c) => c.schema_mapping
+    , DatabaseConfig::Sqlite(_) | DatabaseConfig::Mysql(_) => SchemaMapping::Flatten
+    , DatabaseConfig::Duckdb(c) => c.schema_mapping
+    }
+  }
+
+  pub fn dialect(&self) -> &str {
+    match self {
+      DatabaseConfig::MockDb(_) => "mock"
+    , DatabaseConfig::Postgres(_) => "postgres"
+    , DatabaseConfig::Sqlite(_) => "sqlite"
+    , DatabaseConfig::Mysql(_) => "mysql"
+    , DatabaseConfig::Duckdb(_) => "duckdb"
+    }
+  }
+
+  pub fn secrets(&self) -> Vec<&Secret> {
+    match self {
+      DatabaseConfig::MockDb(_) | DatabaseConfig::Sqlite(_) | DatabaseConfig::Duckdb(_) => vec!()
+    , DatabaseConfig::Postgres(c) => c.password.iter().collect()
+    , DatabaseConfig::Mysql(c) => c.password.iter().collect()
+    }
+  }
+
+  pub fn naming(&self) -> &NamingStrategy {
+    match self {
+      DatabaseConfig::MockDb(c) => &c.naming
+    , DatabaseConfig::Postgres(c) => &c.naming
+    , DatabaseConfig::Sqlite(c) => &c.naming
+    , DatabaseConfig::Mysql(c) => &c.naming
+    , DatabaseConfig::Duckdb(c) => &c.naming
+    }
+  }
+}
 
+// Flatten puts every table in the connection's default schema and prefixes it with its namespace,
+// PerNamespace gives each namespace its own schema
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SchemaMapping {
+  Flatten
+, PerNamespace
+}
 
+// the mock holds its database behind a lock so executing changes can update it through a shared config
 #[derive(Debug)]
 pub struct MockDbConfig {
+  pub state: Mutex<MockState>
+, pub schema_mapping: SchemaMapping
+, pub naming: NamingStrategy
+}
+
+impl MockDbConfig {
+  pub fn new(tables: Vec<Table>) -> MockDbConfig {
+    let schemas = Database::new(tables.clone()).schemas;
+    MockDbConfig{ state: Mutex::new(MockState{ tables, schemas, ..MockState::default() }), schema_mapping: SchemaMapping::Flatten, naming: NamingStrategy::default() }
+  }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MockState {
   pub tables: Vec<Table>
+, pub schemas: Vec<String>
+, pub history: Vec<migrations::AppliedMigration>
+, pub lock: Option<String>
+  // the rows seeded into each table, by table
+, pub rows: BTreeMap<String, Vec<Vec<(String, seed::Value)>>>
+}
+
+#[derive(Debug)]
+pub struct PostgresConfig {
+  pub host: String
+, pub port: u16
+, pub database: String
+, pub user: String
+, pub password: Option<Secret>
+, pub schema_mapping: SchemaMapping
+, pub naming: NamingStrategy
+}
+
+// sqlite has no schemas so every namespace is flattened into the one file
+#[derive(Debug)]
+pub struct SqliteConfig {
+  pub path: String
+, pub naming: NamingStrategy
 }
 
+// also used for MariaDB. A MySQL schema is a whole database, so namespaces are flattened into the configured one
+#[derive(Debug)]
+pub struct MysqlConfig {
+  pub host: String
+, pub port: u16
+, pub database: String
+, pub user: String
+, pub password: Option<Secret>
+, pub naming: NamingStrategy
+}
 
+// an embedded database file, read and written through the duckdb command line
+#[derive(Debug)]
+pub struct DuckdbConfig {
+  pub path: String
+, pub cli: String
+, pub schema_mapping: SchemaMapping
+, pub naming: NamingStrategy
+}
+
+// a value that must never be printed, its Debug output is redacted
+#[derive(Clone, PartialEq)]
+pub struct Secret(String);
+
+impl Secret {
+  pub fn new(value: &str) -> Secret {
+    Secret(value.to_string())
+  }
+
+  pub fn expose(&self) -> &str {
+    &self.0
+  }
+}
+
+impl fmt::Debug for Secret {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "Secret(******)")
+  }
+}
+
+// a snapshot of a database's structure, introspected once and then consulted in memory.
+// Tables asked for with an empty schema are looked for in the default schema, or in any
+// schema when the database doesn't have one
 #[derive(Debug)]
 pub struct Database {
   tables: Vec<Table>
+, schemas: Vec<String>
+, default_schema: Option<String>
 }
 
 impl Database {
   pub fn new(tables: Vec<Table>) -> Database {
-    Database{ tables }
+    let mut schemas: Vec<String> = tables.iter().map(|t| t.schema()).filter(|s| !s.is_empty()).collect();
+    schemas.sort();
+    schemas.dedup();
+    Database{ tables, schemas, default_schema: None }
+  }
+
+  pub fn with_schemas(mut self, schemas: Vec<String>, default_schema: &str) -> Database {
+    self.schemas = schemas;
+    self.default_schema = Some(default_schema.to_string());
+    self
+  }
+
+  // schemas created before any table was put in them
+  pub fn with_empty_schemas(mut self, schemas: Vec<String>) -> Database {
+    self.schemas.extend(schemas);
+    self.schemas.sort();
+    self.schemas.dedup();
+    self
   }
 
   pub fn tables(&self) -> &Vec<Table> {
     &self.tables
   }
+
+  pub fn schemas(&self) -> &Vec<String> {
+    &self.schemas
+  }
+
+  pub fn has_schema(&self, schema: &str) -> bool {
+    self.schemas.iter().any(|s| s == schema)
+  }
+
+  pub fn table(&self, schema: &str, name: &str) -> Option<&Table> {
+    self.tables_in_schema(schema).into_iter().find(|t| t.name == name)
+  }
+
+  pub fn tables_in_schema(&self, schema: &str) -> Vec<&Table> {
+    let schema = if schema.is_empty() { self.default_schema.as_deref() } else { Some(schema) };
+    self.tables.iter().filter(|t| schema.is_none_or(|s| t.schema == s)).collect()
+  }
+
+  // sorted by schema
+  pub fn tables_named(&self, name: &str) -> Vec<&Table> {
+    let mut tables: Vec<&Table> = self.tables.iter().filter(|t| t.name == name).collect();
+    tables.sort_by(|a, b| a.schema.cmp(&b.schema));
+    tables
+  }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Table {
   schema: String
 , name: String
 , columns: Vec<Column>
+, constraints: Vec<Constraint>
+, indexes: Vec<Index>
 }
 
 
 impl Table {
   pub fn new(schema: &str, name: &str, columns: Vec<Column>) -> Table {
-    Table{ schema: schema.to_string(), name: name.to_string(), columns }
+    Table{ schema: schema.to_string(), name: name.to_string(), columns, constraints: vec!(), indexes: vec!() }
+  }
+
+  pub fn with_keys(mut self, constraints: Vec<Constraint>, indexes: Vec<Index>) -> Table {
+    self.constraints = constraints;
+    self.indexes = indexes;
+    self
   }
 
   pub fn name(&self) -> String {
@@ -77,24 +276,134 @@ impl Table {
   pub fn columns(&self) -> &Vec<Column> {
     &self.columns
   }
+
+  pub fn constraints(&self) -> &Vec<Constraint> {
+    &self.constraints
+  }
+
+  pub fn indexes(&self) -> &Vec<Index> {
+    &self.indexes
+  }
 }
 
-#[derive(Debug)]
+// columns made from the model only know their leaf type, introspected ones also know how the
+// database declared them
+#[derive(Debug, Clone, PartialEq)]
 pub struct Column {
   name: String
-, data_type: internal::LeafType
+, data_type: ColumnType
+, sql_type: Option<SqlType>
+, nullable: bool
+, default: Option<String>
 }
 
 impl Column {
   pub fn new(name: &str, data_type: internal::LeafType) -> Column {
-    Column{ name: name.to_string(), data_type: data_type }
+    Column::typed(name, ColumnType::Known(data_type))
+  }
+
+  pub fn typed(name: &str, data_type: ColumnType) -> Column {
+    Column{ name: name.to_string(), data_type, sql_type: None, nullable: true, default: None }
+  }
+
+  pub fn introspected(name: &str, data_type: ColumnType, sql_type: SqlType, nullable: bool, default: Option<String>) -> Column {
+    Column{ name: name.to_string(), data_type, sql_type: Some(sql_type), nullable, default }
+  }
+
+  // the same column declared with another type, whatever sql type it had no longer applies
+  pub fn with_data_type(mut self, data_type: ColumnType) -> Column {
+    self.data_type = data_type;
+    self.sql_type = None;
+    self
+  }
+
+  pub fn with_name(mut self, name: &str) -> Column {
+    self.name = name.to_string();
+    self
   }
 
   pub fn name(&self) -> String {
     self.name.clone()
   }
 
-  pub fn data_type(&self) -> internal::LeafType {
+  pub fn data_type(&self) -> ColumnType {
     self.data_type.clone()
   }
-}
\ No newline at end of file
+
+  pub fn sql_type(&self) -> Option<&SqlType> {
+    self.sql_type.as_ref()
+  }
+
+  pub fn nullable(&self) -> bool {
+    self.nullable
+  }
+
+  pub fn default(&self) -> Option<&str> {
+    self.default.as_deref()
+  }
+}
+
+// a column type with no leaf type to hold it keeps its declaration so it can still be described and restored
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColumnType {
+  Known(internal::LeafType)
+, Unknown(String)
+}
+
+impl ColumnType {
+  pub fn name(&self) -> String {
+    match self {
+      ColumnType::Known(leaf_type) => leaf_type.name()
+    , ColumnType::Unknown(declaration) => declaration.clone()
+    }
+  }
+
+  pub fn leaf_type(&self) -> Option<&internal::LeafType> {
+    match self {
+      ColumnType::Known(leaf_type) => Some(leaf_type)
+    , ColumnType::Unknown(_) => None
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SqlType {
+  pub name: String
+, pub length: Option<i32>
+, pub precision: Option<i32>
+, pub scale: Option<i32>
+}
+
+impl SqlType {
+  pub fn declaration(&self) -> String {
+    match (self.length, self.precision, self.scale) {
+      (Some(length), _, _) => format!("{}({})", self.name, length)
+    , (None, Some(precision), Some(scale)) => format!("{}({},{})", self.name, precision, scale)
+    , (None, Some(precision), None) => format!("{}({})", self.name, precision)
+    , _ => self.name.clone()
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConstraintKind {
+  PrimaryKey
+, Unique
+, ForeignKey(String)
+, Check
+, Other
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Constraint {
+  pub name: String
+, pub kind: ConstraintKind
+, pub columns: Vec<String>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Index {
+  pub name: String
+, pub columns: Vec<String>
+, pub unique: bool
+}
@@ -0,0 +1,438 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+
+use crate::database::integration;
+use crate::database::meta;
+use crate::database::policy;
+use crate::database::sql_script;
+
+pub const MIGRATIONS_DIR: &str = "migrations";
+pub const HISTORY_TABLE: &str = "gimbal_migrations";
+// how far a migration that commits as it goes got, so running it again carries on from there
+pub const CHECKPOINT_TABLE: &str = "gimbal_checkpoint";
+// who holds each app's migration lock, for databases without advisory locks
+pub const LOCK_TABLE: &str = "gimbal_lock";
+const DOWN_EXTENSION: &str = "down.sql";
+
+// migration files are called <version>_<utc timestamp>.sql, the version orders them,
+// the script that undoes one sits next to it as <version>_<utc timestamp>.down.sql
+#[derive(Debug)]
+pub struct MigrationFile {
+  version: i32
+, path: PathBuf
+, script: String
+, down: Option<String>
+}
+
+impl MigrationFile {
+  pub fn version(&self) -> i32 {
+    self.version
+  }
+
+  pub fn file_name(&self) -> String {
+    self.path.file_name().map(|f| f.to_string_lossy().to_string()).unwrap_or_default()
+  }
+
+  pub fn script(&self) -> &str {
+    &self.script
+  }
+
+  pub fn checksum(&self) -> String {
+    checksum(&self.script)
+  }
+
+  pub fn down(&self) -> Option<&str> {
+    self.down.as_deref()
+  }
+}
+
+// one app's migration lock, held for as long as it lives and released when it is dropped
+pub trait HeldLock {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppliedMigration {
+  pub version: i32
+, pub checksum: String
+, pub applied_at: String
+, pub duration_ms: i64
+}
+
+// what running a migration does to the history, written in the same session as its statements
+#[derive(Debug, Clone)]
+pub enum HistoryChange {
+  Record{ version: i32, checksum: String, started: Instant }
+, Remove(i32)
+}
+
+impl HistoryChange {
+  pub fn version(&self) -> i32 {
+    match self {
+      HistoryChange::Record{ version, .. } => *version
+    , HistoryChange::Remove(version) => *version
+    }
+  }
+
+  // what a partly run migration's checkpoint is kept under, undoing it is checkpointed apart from running it
+  pub fn checkpoint_key(&self) -> String {
+    match self {
+      HistoryChange::Record{ version, .. } => version.to_string()
+    , HistoryChange::Remove(version) => format!("{} down", version)
+    }
+  }
+
+  // a migration being recorded took from when it started until its history row is written
+  pub fn applied(&self) -> Option<AppliedMigration> {
+    match self {
+      HistoryChange::Record{ version, checksum, started } => {
+        Some(AppliedMigration{ version: *version, checksum: checksum.clone(), applied_at: "".to_string(), duration_ms: started.elapsed().as_millis() as i64 })
+      }
+    , HistoryChange::Remove(_) => None
+    }
+  }
+}
+
+pub fn checksum(script: &str) -> String {
+  format!("{:x}", Sha256::digest(script.as_bytes()))
+}
+
+pub fn read_migrations(dir: &Path) -> Result<Vec<MigrationFile>, String> {
+  if !dir.exists() {
+    return Ok(vec!());
+  }
+  let entries = fs::read_dir(dir).map_err(|e| format!("I couldn't read the migrations in {} because: {}", dir.display(), e))?;
+  let mut files: Vec<MigrationFile> = Vec::new();
+  for entry in entries {
+    let path = entry.map_err(|e| e.to_string())?.path();
+    if let Some(version) = file_version(&path) {
+      let script = fs::read_to_string(&path).map_err(|e| format!("I couldn't read {} because: {}", path.display(), e))?;
+      let down = fs::read_to_string(down_path(&path)).ok();
+      files.push(MigrationFile{ version, path, script, down });
+    }
+  }
+  files.sort_by_key(|f| f.version);
+  match files.windows(2).find(|w| w[0].version == w[1].version) {
+    Some(w) => Err(format!("I found two migrations numbered {}: {} and {}.", w[0].version, w[0].file_name(), w[1].file_name()))
+  , None => Ok(files)
+  }
+}
+
+fn file_version(path: &Path) -> Option<i32> {
+  let file_name = path.file_name()?.to_str()?;
+  if !file_name.ends_with(".sql") || file_name.ends_with(DOWN_EXTENSION) {
+    return None;
+  }
+  file_name.split('_').next()?.parse::<i32>().ok()
+}
+
+fn down_path(path: &Path) -> PathBuf {
+  path.with_extension(DOWN_EXTENSION)
+}
+
+pub fn write_migration(dir: &Path, script: &str, down: &str) -> Result<MigrationFile, String> {
+  let version = read_migrations(dir)?.last().map_or(1, |f| f.version + 1);
+  let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+  let path = dir.join(format!("{:04}_{}.sql", version, timestamp(seconds)));
+  fs::create_dir_all(dir)
+    .and_then(|_| fs::write(&path, script))
+    .and_then(|_| fs::write(down_path(&path), down))
+    .map_err(|e| format!("I couldn't save database migration script because: {}", e))?;
+  Ok(MigrationFile{ version, path, script: script.to_string(), down: Some(down.to_string()) })
+}
+
+// the files not yet applied, in order, as long as every applied file is still there and unchanged
+pub fn pending<'a>(files: &'a [MigrationFile], applied: &[AppliedMigration]) -> Result<Vec<&'a MigrationFile>, String> {
+  for a in applied {
+    match files.iter().find(|f| f.version == a.version) {
+      None => return Err(format!("Migration {} has been applied but I can't find its file.", a.version))
+    , Some(f) if f.checksum() != a.checksum => {
+        return Err(format!("{} has changed since it was applied on {}, I won't migrate until it is put back.", f.file_name(), a.applied_at))
+      }
+    , Some(_) => {}
+    }
+  }
+  let latest = applied.iter().map(|a| a.version).max().unwrap_or(0);
+  match files.iter().find(|f| f.version < latest && !applied.iter().any(|a| a.version == f.version)) {
+    Some(f) => Err(format!("{} is older than the latest applied migration {} but was never applied.", f.file_name(), latest))
+  , None => Ok(files.iter().filter(|f| f.version > latest).collect())
+  }
+}
+
+pub fn migrate(dir: &Path, policy: &policy::Policy, db_config: &meta::DatabaseConfig) -> Result<Vec<i32>, String> {
+  run_pending(dir, policy, db_config, &|_, _| Ok(()))
+}
+
+// every pending file is checked against the policy before any of them runs, before_each checks
+// each one again just before it runs
+fn run_pending(dir: &Path, policy: &policy::Policy, db_config: &meta::DatabaseConfig,
+               before_each: &dyn Fn(&MigrationFile, &sql_script::Script) -> Result<(), String>) -> Result<Vec<i32>, String> {
+  let files = read_migrations(dir)?;
+  let applied = integration::applied_migrations(db_config)?;
+  let scripts = pending(&files, &applied)?.into_iter().map(|file| {
+    let script = parse_script(file.file_name(), file.script(), db_config)?;
+    policy.check(&file.file_name(), &script.header().operations, script.statements())?;
+    Ok((file, script))
+  }).collect::<Result<Vec<(&MigrationFile, sql_script::Script)>, String>>()?;
+  let mut versions: Vec<i32> = Vec::new();
+  for (file, script) in scripts {
+    before_each(file, &script)?;
+    let history = HistoryChange::Record{ version: file.version(), checksum: file.checksum(), started: Instant::now() };
+    integration::migrate_db(&meta::DatabaseChange::SqlDb(script.statements().clone()), Some(&history), db_config)
+      .map_err(|e| format!("{} failed: {}", file.file_name(), e))?;
+    versions.push(file.version());
+  }
+  Ok(versions)
+}
+
+// undoes the applied migrations newer than to_version, or just the latest one, newest first. Every down
+// script is checked against the policy before any of them runs
+pub fn rollback(dir: &Path, to_version: Option<i32>, policy: &policy::Policy, db_config: &meta::DatabaseConfig) -> Result<Vec<i32>, String> {
+  let files = read_migrations(dir)?;
+  let applied = integration::applied_migrations(db_config)?;
+  let scripts = rollback_plan(&files, &applied, to_version)?.into_iter().map(|(file, down)| {
+    let file_name = down_path(&file.path).file_name().map_or("".to_string(), |n| n.to_string_lossy().to_string());
+    let script = parse_script(file_name.clone(), down, db_config)?;
+    policy.check(&file_name, &script.header().operations, script.statements())?;
+    Ok((file, script))
+  }).collect::<Result<Vec<(&MigrationFile, sql_script::Script)>, String>>()?;
+  let mut versions: Vec<i32> = Vec::new();
+  for (file, script) in scripts {
+    integration::migrate_db(&meta::DatabaseChange::SqlDb(script.statements().clone()), Some(&HistoryChange::Remove(file.version())), db_config)
+      .map_err(|e| format!("Rolling back {} failed: {}", file.file_name(), e))?;
+    versions.push(file.version());
+  }
+  Ok(versions)
+}
+
+// the versions of the migration files not yet applied
+pub fn unapplied(dir: &Path, db_config: &meta::DatabaseConfig) -> Result<Vec<i32>, String> {
+  let files = read_migrations(dir)?;
+  let applied = integration::applied_migrations(db_config)?;
+  Ok(pending(&files, &applied)?.iter().map(|f| f.version()).collect())
+}
+
+// migrates only if the pending migrations were planned from this model against a database that still
+// looks the way it did then. Each one was planned against the database the ones before it leave behind
+// so its fingerprint is checked just before it runs, the model is the one the newest was planned from
+pub fn apply(dir: &Path, model_hash: &str, schemas: &[String], policy: &policy::Policy, db_config: &meta::DatabaseConfig) -> Result<Vec<i32>, String> {
+  let files = read_migrations(dir)?;
+  let applied = integration::applied_migrations(db_config)?;
+  let headers = pending(&files, &applied)?.into_iter().map(|file| {
+    let script = sql_script::parse(file.script()).map_err(|e| format!("I can't use {} because {}.", file.file_name(), e))?;
+    Ok((file.file_name(), script.header().clone()))
+  }).collect::<Result<Vec<(String, sql_script::ScriptHeader)>, String>>()?;
+  for (file_name, header) in &headers {
+    check_planned(file_name, header)?;
+  }
+  if let Some((file_name, header)) = headers.last() {
+    check_model(file_name, header, model_hash)?;
+  }
+  run_pending(dir, policy, db_config, &|file, script| {
+    check_fingerprint(&file.file_name(), script.header(), &integration::database_fingerprint(schemas, db_config)?)
+  })
+}
+
+fn check_planned(file_name: &str, header: &sql_script::ScriptHeader) -> Result<(), String> {
+  match header.fingerprint {
+    None => Err(format!("{} wasn't made by plan so I can't tell if it still fits the database, use migrate to run it anyway.", file_name))
+  , Some(_) => Ok(())
+  }
+}
+
+fn check_model(file_name: &str, header: &sql_script::ScriptHeader, model_hash: &str) -> Result<(), String> {
+  match &header.model {
+    Some(m) if m != model_hash => Err(format!("The model has changed since {} was planned, plan again.", file_name))
+  , _ => Ok(())
+  }
+}
+
+fn check_fingerprint(file_name: &str, header: &sql_script::ScriptHeader, fingerprint: &str) -> Result<(), String> {
+  match &header.fingerprint {
+    Some(f) if f != fingerprint => Err(format!("The database has changed since {} was planned, plan again.", file_name))
+  , _ => Ok(())
+  }
+}
+
+fn parse_script(file_name: String, text: &str, db_config: &meta::DatabaseConfig) -> Result<sql_script::Script, String> {
+  let script = sql_script::parse(text).map_err(|e| format!("I can't use {} because {}.", file_name, e))?;
+  match &script.header().dialect {
+    Some(dialect) if dialect != db_config.dialect() => {
+      Err(format!("{} was written for {} but this database is {}.", file_name, dialect, db_config.dialect()))
+    }
+  , _ => Ok(script)
+  }
+}
+
+// every migration to undo is checked before anything runs so a missing or irreversible step stops the whole rollback
+fn rollback_plan<'a>(files: &'a [MigrationFile], applied: &[AppliedMigration], to_version: Option<i32>) -> Result<Vec<(&'a MigrationFile, &'a str)>, String> {
+  pending(files, applied)?;
+  let mut versions: Vec<i32> = applied.iter().map(|a| a.version).collect();
+  versions.sort_unstable_by(|a, b| b.cmp(a));
+  let versions: Vec<i32> = match to_version {
+    Some(to) => versions.into_iter().filter(|v| *v > to).collect()
+  , None => versions.into_iter().take(1).collect()
+  };
+  versions.iter().map(|version| {
+    let file = files.iter().find(|f| f.version == *version).expect("applied migration checked by pending");
+    let down = file.down().ok_or_else(|| format!("I can't roll back {} because it has no down script.", file.file_name()))?;
+    match down.lines().find(|l| l.trim_start().starts_with(meta::IRREVERSIBLE_MARKER)) {
+      Some(step) => Err(format!("I won't roll back {} because this step can't be undone:\n{}", file.file_name(), step))
+    , None => Ok((file, down))
+    }
+  }).collect()
+}
+
+// YYYYMMDDHHMMSS in UTC, the date from days since the epoch following Howard Hinnant's civil_from_days
+fn timestamp(seconds: u64) -> String {
+  let days = (seconds / 86400) as i64;
+  let secs = seconds % 86400;
+  let z = days + 719468;
+  let era = z.div_euclid(146097);
+  let doe = z - era * 146097;
+  let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let day = doy - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+  format!("{:04}{:02}{:02}{:02}{:02}{:02}", year, month, day, secs / 3600, secs % 3600 / 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::env;
+  use crate::database::operations;
+
+  fn migration(version: i32, script: &str) -> MigrationFile {
+    MigrationFile{ version, path: PathBuf::from(format!("{:04}_20261018000000.sql", version)), script: script.to_string(), down: None }
+  }
+
+  fn applied(file: &MigrationFile) -> AppliedMigration {
+    AppliedMigration{ version: file.version(), checksum: file.checksum(), applied_at: "2026-10-18".to_string(), duration_ms: 1 }
+  }
+
+  #[test]
+  fn test_timestamp() {
+    assert_eq!(timestamp(0), "19700101000000");
+    assert_eq!(timestamp(1792328645), "20261018130405");
+  }
+
+  #[test]
+  fn test_pending() {
+    let files = vec!(migration(1, "CREATE TABLE a ()"), migration(2, "CREATE TABLE b ()"));
+    let pending_files = pending(&files, &[applied(&files[0])]).unwrap();
+    assert_eq!(pending_files.iter().map(|f| f.version()).collect::<Vec<i32>>(), vec!(2));
+    assert!(pending(&files, &[applied(&files[0]), applied(&files[1])]).unwrap().is_empty());
+  }
+
+  #[test]
+  fn test_changed_file() {
+    let files = vec!(migration(1, "CREATE TABLE a ()"));
+    let mut changed = applied(&files[0]);
+    changed.checksum = checksum("CREATE TABLE z ()");
+    assert!(pending(&files, &[changed]).is_err());
+    assert!(pending(&[], &[applied(&files[0])]).is_err());
+  }
+
+  #[test]
+  fn test_rollback_plan() {
+    let mut files = vec!(migration(1, "CREATE TABLE a ()"), migration(2, "CREATE TABLE b ()"), migration(3, "CREATE TABLE c ()"));
+    files[0].down = Some("DROP TABLE a".to_string());
+    files[1].down = Some("DROP TABLE b".to_string());
+    files[2].down = Some("DROP TABLE c".to_string());
+    let applied = files.iter().map(applied).collect::<Vec<AppliedMigration>>();
+    let versions = |plan: Vec<(&MigrationFile, &str)>| plan.iter().map(|(f, _)| f.version()).collect::<Vec<i32>>();
+    assert_eq!(versions(rollback_plan(&files, &applied, None).unwrap()), vec!(3));
+    assert_eq!(versions(rollback_plan(&files, &applied, Some(1)).unwrap()), vec!(3, 2));
+
+    files[1].down = Some(format!("DROP TABLE b\n{} ALTER TABLE a DROP COLUMN x", meta::IRREVERSIBLE_MARKER));
+    assert!(rollback_plan(&files, &applied, None).is_ok());
+    assert!(rollback_plan(&files, &applied, Some(1)).is_err());
+  }
+
+  #[test]
+  fn test_check_plan() {
+    let header = sql_script::ScriptHeader{ model: Some("model".to_string()), fingerprint: Some("db".to_string()), ..sql_script::ScriptHeader::default() };
+    assert!(check_planned("0001.sql", &header).is_ok());
+    assert!(check_model("0001.sql", &header, "model").is_ok());
+    assert!(check_fingerprint("0001.sql", &header, "db").is_ok());
+    assert!(check_fingerprint("0001.sql", &header, "changed").is_err());
+    assert!(check_model("0001.sql", &header, "changed").is_err());
+    assert!(check_planned("0001.sql", &sql_script::ScriptHeader::default()).is_err());
+  }
+
+  #[test]
+  fn test_policy() {
+    let dir = env::temp_dir().join(format!("gimbal_policy_test_{}", std::process::id()));
+    let dropped = operations::Operation::DropTable(meta::Table::new("", "a", vec!()));
+    let script = sql_script::Script::new("model", "mock", vec!("DROP TABLE a".to_string())).with_operations(&[dropped]);
+    write_migration(&dir, &script.to_string(), "").unwrap();
+    let db_config = meta::DatabaseConfig::MockDb(meta::MockDbConfig::new(vec!()));
+    let result = migrate(&dir, &policy::Policy::new("prod", vec!(operations::Risk::Safe)), &db_config);
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(result.unwrap_err().starts_with("I won't run 0001_"));
+    assert!(integration::applied_migrations(&db_config).unwrap().is_empty());
+  }
+
+  #[test]
+  fn test_rollback_policy() {
+    let dir = env::temp_dir().join(format!("gimbal_rollback_policy_test_{}", std::process::id()));
+    let path = env::temp_dir().join(format!("gimbal_rollback_policy_test_{}.db", std::process::id()));
+    let db_config = meta::DatabaseConfig::Sqlite(meta::SqliteConfig{ path: path.to_string_lossy().to_string(), naming: crate::database::naming::NamingStrategy::default() });
+    let created = operations::Operation::CreateTable(meta::Table::new("", "a", vec!()));
+    let up = sql_script::Script::new("model", "sqlite", vec!("CREATE TABLE a (x integer)".to_string())).with_operations(std::slice::from_ref(&created));
+    let down = sql_script::Script::new("model", "sqlite", vec!("DROP TABLE a".to_string())).with_operations(&[created.reverse()]);
+    write_migration(&dir, &up.to_string(), &down.to_string()).unwrap();
+    let safe = policy::Policy::new("prod", vec!(operations::Risk::Safe));
+    migrate(&dir, &safe, &db_config).unwrap();
+    let refused = rollback(&dir, None, &safe, &db_config);
+    let applied = integration::applied_migrations(&db_config).unwrap();
+    let allowed = rollback(&dir, None, &policy::Policy::allow_all("dev"), &db_config);
+    fs::remove_dir_all(&dir).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(refused.unwrap_err().starts_with("I won't run 0001_"));
+    assert_eq!(applied.len(), 1);
+    assert_eq!(allowed, Ok(vec!(1)));
+  }
+
+  #[test]
+  fn test_apply() {
+    let dir = env::temp_dir().join(format!("gimbal_apply_test_{}", std::process::id()));
+    let path = env::temp_dir().join(format!("gimbal_apply_test_{}.db", std::process::id()));
+    let db_config = meta::DatabaseConfig::Sqlite(meta::SqliteConfig{ path: path.to_string_lossy().to_string(), naming: crate::database::naming::NamingStrategy::default() });
+    let created = |name: &str| operations::Operation::CreateTable(meta::Table::new("", name, vec!()));
+    let fingerprint = integration::database_fingerprint(&[], &db_config).unwrap();
+    let first = sql_script::Script::new("model", "sqlite", vec!("CREATE TABLE a (x integer)".to_string()))
+      .with_fingerprint(&fingerprint).with_operations(&[created("a")]);
+    // planned against the database as it was before the first one ran
+    let second = sql_script::Script::new("model", "sqlite", vec!("CREATE TABLE b (x integer)".to_string()))
+      .with_fingerprint(&fingerprint).with_operations(&[created("b")]);
+    write_migration(&dir, &first.to_string(), "").unwrap();
+    write_migration(&dir, &second.to_string(), "").unwrap();
+    let policy = policy::Policy::new("dev", operations::Risk::all());
+    assert!(apply(&dir, "changed", &[], &policy, &db_config).unwrap_err().starts_with("The model has changed since 0002_"));
+    let result = apply(&dir, "model", &[], &policy, &db_config);
+    let applied = integration::applied_migrations(&db_config).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(result.unwrap_err().starts_with("The database has changed since 0002_"));
+    assert_eq!(applied.iter().map(|a| a.version).collect::<Vec<i32>>(), vec!(1));
+  }
+
+  #[test]
+  fn test_write_read() {
+    let dir = env::temp_dir().join(format!("gimbal_migrations_test_{}", std::process::id()));
+    let first = write_migration(&dir, "CREATE TABLE a ()", "DROP TABLE a").unwrap();
+    let second = write_migration(&dir, "CREATE TABLE b ()", "DROP TABLE b").unwrap();
+    let files = read_migrations(&dir).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!((first.version(), second.version()), (1, 2));
+    assert_eq!(files.len(), 2);
+    assert_eq!(files[1].script(), "CREATE TABLE b ()");
+    assert_eq!(files[1].down(), Some("DROP TABLE b"));
+    assert!(files[0].file_name().starts_with("0001_"));
+  }
+}
@@ -1,3 +1,14 @@
+pub mod copy;
+pub mod drift;
+pub mod expand_contract;
+pub mod import;
 pub mod integration;
 pub mod meta;
+pub mod migrations;
+pub mod naming;
+pub mod operations;
+pub mod policy;
+pub mod reverse;
+pub mod seed;
+pub mod sql_script;
 mod drivers;
\ No newline at end of file
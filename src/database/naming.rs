@@ -0,0 +1,111 @@
+// the longest identifier postgres keeps before silently truncating it
+pub const DEFAULT_MAX_LENGTH: usize = 63;
+
+const HASH_LENGTH: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NamingStrategy {
+  pub snake_case: bool
+, pub pluralise: bool
+, pub prefix: String
+, pub max_length: usize
+}
+
+impl Default for NamingStrategy {
+  fn default() -> NamingStrategy {
+    NamingStrategy{ snake_case: false, pluralise: false, prefix: "".to_string(), max_length: DEFAULT_MAX_LENGTH }
+  }
+}
+
+impl NamingStrategy {
+  pub fn table_name(&self, name: &str) -> String {
+    let cased = self.cased(name);
+    let plural = if self.pluralise { pluralise(&cased) } else { cased };
+    self.truncated(&format!("{}{}", self.prefix, plural))
+  }
+
+  pub fn column_name(&self, name: &str) -> String {
+    self.truncated(&self.cased(name))
+  }
+
+  fn cased(&self, name: &str) -> String {
+    if self.snake_case { snake_case(name) } else { name.to_string() }
+  }
+
+  // names that are too long keep as much of themselves as fits and end with a hash of the full name
+  // so two long names sharing a beginning still come out different
+  fn truncated(&self, name: &str) -> String {
+    if name.len() <= self.max_length || self.max_length <= HASH_LENGTH + 1 {
+      return name.to_string();
+    }
+    let keep: String = name.chars().take(self.max_length - HASH_LENGTH - 1).collect();
+    format!("{}_{:08x}", keep, fnv1a(name))
+  }
+}
+
+fn snake_case(name: &str) -> String {
+  let mut snake = String::new();
+  let mut previous: Option<char> = None;
+  name.chars().for_each(|c| {
+    if c.is_uppercase() {
+      if let Some(p) = previous {
+        if p.is_lowercase() || p.is_ascii_digit() {
+          snake.push('_');
+        }
+      }
+      snake.extend(c.to_lowercase());
+    } else {
+      snake.push(c);
+    }
+    previous = Some(c);
+  });
+  snake
+}
+
+fn pluralise(name: &str) -> String {
+  let ends_with_consonant_y = name.ends_with('y') && !name.chars().rev().nth(1).is_some_and(|c| "aeiouAEIOU".contains(c));
+  if ends_with_consonant_y {
+    format!("{}ies", &name[..name.len() - 1])
+  } else if ["s", "x", "z", "ch", "sh"].iter().any(|e| name.ends_with(e)) {
+    format!("{}es", name)
+  } else {
+    format!("{}s", name)
+  }
+}
+
+// a hash that stays the same across builds and platforms, names derived from it end up in databases
+pub fn fnv1a(text: &str) -> u32 {
+  text.bytes().fold(0x811c9dc5, |hash, b| (hash ^ b as u32).wrapping_mul(0x01000193))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn default_keeps_names() {
+    let naming = NamingStrategy::default();
+    assert_eq!(naming.table_name("db_Agent"), "db_Agent");
+    assert_eq!(naming.column_name("first_name"), "first_name");
+  }
+
+  #[test]
+  fn snake_case_plural_prefix() {
+    let naming = NamingStrategy{ snake_case: true, pluralise: true, prefix: "app_".to_string(), ..NamingStrategy::default() };
+    assert_eq!(naming.table_name("db_ChangeName"), "app_db_change_names");
+    assert_eq!(naming.table_name("db_Category"), "app_db_categories");
+    assert_eq!(naming.table_name("db_Address"), "app_db_addresses");
+    assert_eq!(naming.column_name("lastName"), "last_name");
+  }
+
+  #[test]
+  fn truncation_is_unique() {
+    let naming = NamingStrategy{ max_length: 16, ..NamingStrategy::default() };
+    let first = naming.table_name("namespace_VeryLongEntityOne");
+    let second = naming.table_name("namespace_VeryLongEntityTwo");
+    assert_eq!(first.len(), 16);
+    assert!(first.starts_with("namespa_"));
+    assert_ne!(first, second);
+    assert_eq!(first, naming.table_name("namespace_VeryLongEntityOne"));
+  }
+}
@@ -0,0 +1,343 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::database::expand_contract::Step;
+use crate::database::integration::{DbDiff, DiffDiagnosis};
+use crate::database::meta;
+use crate::lang::internal;
+
+// a table by where it is, schema is empty when the model flattens namespaces
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableRef {
+  pub schema: String
+, pub name: String
+}
+
+impl TableRef {
+  pub fn new(schema: &str, name: &str) -> TableRef {
+    TableRef{ schema: schema.to_string(), name: name.to_string() }
+  }
+
+  pub fn of(table: &meta::Table) -> TableRef {
+    TableRef::new(&table.schema(), &table.name())
+  }
+}
+
+impl fmt::Display for TableRef {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if self.schema.is_empty() { write!(f, "{}", self.name) } else { write!(f, "{}.{}", self.schema, self.name) }
+  }
+}
+
+// a column given another type. The table as the database had it before the migration is kept so
+// a dialect can declare the column's nullability and default again, or rebuild the whole table
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnChange {
+  pub table: TableRef
+, pub column: String
+, pub from: meta::ColumnType
+, pub to: meta::ColumnType
+, pub database_table: Option<meta::Table>
+}
+
+impl ColumnChange {
+  pub fn database_column(&self) -> Option<&meta::Column> {
+    self.database_table.as_ref().and_then(|t| t.columns().iter().find(|c| c.name() == self.column))
+  }
+
+  // going back to the type the database had, the column is declared the way the database declared it
+  pub fn declaration(&self, type_ddl: fn(&meta::ColumnType) -> String) -> String {
+    match self.database_column().filter(|c| c.data_type() == self.to).and_then(|c| c.sql_type()) {
+      Some(sql_type) => sql_type.declaration()
+    , None => type_ddl(&self.to)
+    }
+  }
+
+  pub fn reverse(&self) -> ColumnChange {
+    ColumnChange{ from: self.to.clone(), to: self.from.clone(), ..self.clone() }
+  }
+}
+
+// how much a running application could notice an operation. Locking ones hold a lock that blocks
+// writes while they run, data rewriting ones rewrite every row and destructive ones can lose data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Risk {
+  Safe
+, Locking
+, DataRewriting
+, Destructive
+}
+
+impl Risk {
+  pub fn all() -> Vec<Risk> {
+    vec!(Risk::Safe, Risk::Locking, Risk::DataRewriting, Risk::Destructive)
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      Risk::Safe => "safe"
+    , Risk::Locking => "locking"
+    , Risk::DataRewriting => "data-rewriting"
+    , Risk::Destructive => "destructive"
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Risk> {
+    Risk::all().into_iter().find(|r| r.name() == name)
+  }
+}
+
+// one change to the structure of a database, planned from the diffs and rendered by each dialect
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+  CreateSchema(String)
+, DropSchema(String)
+, CreateTable(meta::Table)
+, DropTable(meta::Table)
+, MoveTable(TableRef, String)
+, AddColumn(TableRef, meta::Column)
+, DropColumn(TableRef, meta::Column)
+, AlterColumnType(ColumnChange)
+, RenameColumn(TableRef, String, String)
+, AddForeignKey(TableRef, meta::Constraint)
+, DropForeignKey(TableRef, meta::Constraint)
+, CreateIndex(TableRef, meta::Index)
+, DropIndex(TableRef, meta::Index)
+, Staged(Step)
+}
+
+impl Operation {
+  // every operation carries what it takes to undo it, dropped tables and columns keep their definitions
+  pub fn reverse(&self) -> Operation {
+    match self {
+      Operation::CreateSchema(s) => Operation::DropSchema(s.clone())
+    , Operation::DropSchema(s) => Operation::CreateSchema(s.clone())
+    , Operation::CreateTable(t) => Operation::DropTable(t.clone())
+    , Operation::DropTable(t) => Operation::CreateTable(t.clone())
+    , Operation::MoveTable(t, to) => Operation::MoveTable(TableRef::new(to, &t.name), t.schema.clone())
+    , Operation::AddColumn(t, c) => Operation::DropColumn(t.clone(), c.clone())
+    , Operation::DropColumn(t, c) => Operation::AddColumn(t.clone(), c.clone())
+    , Operation::AlterColumnType(change) => Operation::AlterColumnType(change.reverse())
+    , Operation::RenameColumn(t, from, to) => Operation::RenameColumn(t.clone(), to.clone(), from.clone())
+    , Operation::AddForeignKey(t, k) => Operation::DropForeignKey(t.clone(), k.clone())
+    , Operation::DropForeignKey(t, k) => Operation::AddForeignKey(t.clone(), k.clone())
+    , Operation::CreateIndex(t, i) => Operation::DropIndex(t.clone(), i.clone())
+    , Operation::DropIndex(t, i) => Operation::CreateIndex(t.clone(), i.clone())
+    , Operation::Staged(step) => step.reverse()
+    }
+  }
+
+  pub fn describe(&self) -> String {
+    match self {
+      Operation::CreateSchema(s) => format!("create schema {}", s)
+    , Operation::DropSchema(s) => format!("drop schema {}", s)
+    , Operation::CreateTable(t) => format!("create table {}", TableRef::of(t))
+    , Operation::DropTable(t) => format!("drop table {}", TableRef::of(t))
+    , Operation::MoveTable(t, to) => format!("move table {} to {}", t, to)
+    , Operation::AddColumn(t, c) => format!("add column {}.{}", t, c.name())
+    , Operation::DropColumn(t, c) => format!("drop column {}.{}", t, c.name())
+    , Operation::AlterColumnType(change) => {
+        format!("change column {}.{} from {} to {}", change.table, change.column, change.from.name(), change.to.name())
+      }
+    , Operation::RenameColumn(t, from, to) => format!("rename column {}.{} to {}", t, from, to)
+    , Operation::AddForeignKey(t, k) => format!("add foreign key {} on {}", k.name, t)
+    , Operation::DropForeignKey(t, k) => format!("drop foreign key {} on {}", k.name, t)
+    , Operation::CreateIndex(t, i) => format!("create index {} on {}", i.name, t)
+    , Operation::DropIndex(t, i) => format!("drop index {} on {}", i.name, t)
+    , Operation::Staged(step) => step.describe()
+    }
+  }
+
+  // adding a nullable column only changes the catalog, a new table or schema has no one using it yet.
+  // Creating or dropping the trigger that keeps a shadow column in sync blocks writes while it runs, the other
+  // staged steps only take locks that don't, except the swap which drops the old column
+  pub fn risk(&self) -> Risk {
+    match self {
+      Operation::CreateSchema(_) | Operation::CreateTable(_) | Operation::AddColumn(_, _) => Risk::Safe
+    , Operation::Staged(Step::Backfill(_)) => Risk::DataRewriting
+    , Operation::Staged(Step::SwapColumn(change)) if is_lossy(&change.from, &change.to) => Risk::Destructive
+    , Operation::Staged(Step::SwapColumn(_)) | Operation::Staged(Step::SyncColumn(_)) | Operation::Staged(Step::UnsyncColumn(_)) => Risk::Locking
+    , Operation::Staged(_) => Risk::Safe
+    , Operation::MoveTable(_, _) | Operation::RenameColumn(_, _, _) | Operation::AddForeignKey(_, _) | Operation::DropForeignKey(_, _)
+    | Operation::CreateIndex(_, _) | Operation::DropIndex(_, _) => Risk::Locking
+    , Operation::AlterColumnType(change) if !is_lossy(&change.from, &change.to) => Risk::DataRewriting
+    , Operation::AlterColumnType(_) | Operation::DropSchema(_) | Operation::DropTable(_) | Operation::DropColumn(_, _) => Risk::Destructive
+    }
+  }
+
+  // whether running it can lose data that is in the database
+  pub fn is_destructive(&self) -> bool {
+    self.risk() == Risk::Destructive
+  }
+
+  fn is_addition(&self) -> bool {
+    matches!(self, Operation::CreateSchema(_) | Operation::CreateTable(_) | Operation::AddColumn(_, _) | Operation::AddForeignKey(_, _) | Operation::CreateIndex(_, _)
+      | Operation::Staged(Step::AddForeignKey(_, _)) | Operation::Staged(Step::CreateIndex(_, _)))
+  }
+
+  fn phase(&self) -> ChangePhase {
+    match self {
+      Operation::CreateSchema(_) | Operation::DropSchema(_) => ChangePhase::Schema
+    , Operation::CreateTable(_) | Operation::DropTable(_) | Operation::MoveTable(_, _) => ChangePhase::Table
+    , Operation::AddForeignKey(_, _) | Operation::DropForeignKey(_, _) | Operation::CreateIndex(_, _) | Operation::DropIndex(_, _) => ChangePhase::Key
+    , _ => ChangePhase::Column
+    }
+  }
+}
+
+// whether every value of one type has a value of the other, anything can be written as a string
+fn is_lossy(from: &meta::ColumnType, to: &meta::ColumnType) -> bool {
+  match (from.leaf_type(), to.leaf_type()) {
+    _ if from == to => false
+  , (_, Some(internal::LeafType::String)) => false
+  , (Some(internal::LeafType::Int), Some(internal::LeafType::Float)) => false
+  , _ => true
+  }
+}
+
+// operations are grouped so that everything an operation depends on has already been created:
+// schema, table, column then key, where indexes go with the keys. The diffs arrive in dependency
+// order and the sort is stable so that order is kept within a phase
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum ChangePhase {
+  Schema
+, Table
+, Column
+, Key
+}
+
+pub fn plan(db_diffs: &[DbDiff]) -> Vec<Operation> {
+  let mut operations: Vec<Operation> = db_diffs.iter()
+    .flat_map(|d| d.diff_diagnosis().iter().filter_map(move |diagnosis| diagnosis_to_operation(d, diagnosis)))
+    .collect();
+  operations.sort_by_key(|o| o.phase());
+  // entities sharing a namespace each report its schema as missing
+  let mut schemas = HashSet::new();
+  operations.retain(|o| match o {
+    Operation::CreateSchema(s) => schemas.insert(s.clone())
+  , _ => true
+  });
+  operations
+}
+
+// undoes the operations, last one first
+pub fn reverse(operations: &[Operation]) -> Vec<Operation> {
+  operations.iter().rev().map(|o| o.reverse()).collect()
+}
+
+fn diagnosis_to_operation(db_diff: &DbDiff, diagnosis: &DiffDiagnosis) -> Option<Operation> {
+  let table = db_diff.db_table();
+  match diagnosis {
+    DiffDiagnosis::NoDiff => None
+  , DiffDiagnosis::SchemaMissing => Some(Operation::CreateSchema(table.schema()))
+  , DiffDiagnosis::TableMissing => Some(Operation::CreateTable(table.clone()))
+  , DiffDiagnosis::TableMoved(from) => Some(Operation::MoveTable(TableRef::new(from, &table.name()), table.schema()))
+  , DiffDiagnosis::ColumnMissing(name) => {
+      let column = table.columns().iter().find(|c| &c.name() == name).expect("diagnosed column not in table");
+      Some(Operation::AddColumn(TableRef::of(table), column.clone()))
+    }
+  , DiffDiagnosis::ColumnTypeMismatch(name, entity_type, database_type) => {
+      Some(Operation::AlterColumnType(ColumnChange{
+        table: TableRef::of(table), column: name.clone(), from: database_type.clone(), to: entity_type.clone(), database_table: db_diff.database_table().cloned()
+      }))
+    }
+  }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ChangeSummary {
+  pub additions: Vec<String>
+, pub changes: Vec<String>
+, pub destructive: Vec<String>
+}
+
+impl fmt::Display for ChangeSummary {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let sections = vec!(("Additions", &self.additions), ("Changes", &self.changes), ("Destructive", &self.destructive));
+    let text = sections.into_iter().filter(|(_, lines)| !lines.is_empty())
+      .map(|(title, lines)| format!("{}:\n{}", title, lines.iter().map(|l| format!("  {}\n", l)).collect::<String>()))
+      .collect::<Vec<String>>().join("\n");
+    write!(f, "{}", text)
+  }
+}
+
+// the operations in the order they run, sorted by what they do to the data
+pub fn summarise(operations: &[Operation]) -> ChangeSummary {
+  let mut summary = ChangeSummary::default();
+  operations.iter().for_each(|o| {
+    match o {
+      Operation::AlterColumnType(_) | Operation::Staged(Step::SwapColumn(_)) if o.is_destructive() => summary.destructive.push(o.describe() + ", existing values may not convert")
+    , _ if o.is_destructive() => summary.destructive.push(o.describe())
+    , _ if o.is_addition() => summary.additions.push(o.describe())
+    , _ => summary.changes.push(o.describe())
+    }
+  });
+  summary
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::database::integration;
+  use crate::lang::ast_builder;
+
+  #[test]
+  fn test_plan() {
+    let code = r#"
+app database
+
+namespace db where
+
+struct persists Agent
+struct persists Resource
+
+name:: Agent -> String
+age:: Agent -> Int
+owner:: Resource -> Agent"#;
+
+    let ast = ast_builder::build(code).unwrap();
+    let agent = meta::Table::new("old", "Agent", vec!(meta::Column::new("name", internal::LeafType::Int)));
+    let mock_db_config = meta::DatabaseConfig::MockDb(meta::MockDbConfig{ schema_mapping: meta::SchemaMapping::PerNamespace, ..meta::MockDbConfig::new(vec!(agent)) });
+    let operations = plan(&integration::diagnose_db_diffs(&ast, &mock_db_config).unwrap());
+    let described = operations.iter().map(|o| o.describe()).collect::<Vec<String>>();
+    assert_eq!(described, vec!(
+      "create schema db", "move table old.Agent to db", "create table db.Resource", "add column db.Agent.age", "change column db.Agent.name from Int to String"
+    ));
+    assert_eq!(reverse(&reverse(&operations)), operations);
+    assert_eq!(reverse(&operations)[0].describe(), "change column db.Agent.name from String to Int");
+    assert!(reverse(&operations)[0].is_destructive());
+    let risks = operations.iter().map(|o| o.risk().name()).collect::<Vec<&str>>();
+    assert_eq!(risks, vec!("safe", "locking", "safe", "safe", "data-rewriting"));
+  }
+
+  #[test]
+  fn test_declaration() {
+    let database_table = meta::Table::new("", "Agent", vec!(meta::Column::introspected("age", meta::ColumnType::Known(internal::LeafType::String),
+      meta::SqlType{ name: "varchar".to_string(), length: Some(10), precision: None, scale: None }, true, None)));
+    let change = ColumnChange{
+      table: TableRef::of(&database_table), column: "age".to_string(), database_table: Some(database_table.clone())
+    , from: meta::ColumnType::Known(internal::LeafType::String), to: meta::ColumnType::Known(internal::LeafType::Int)
+    };
+    let type_ddl: fn(&meta::ColumnType) -> String = |t| t.name().to_lowercase();
+    assert_eq!(change.declaration(type_ddl), "int");
+    assert_eq!(change.reverse().declaration(type_ddl), "varchar(10)");
+  }
+
+  #[test]
+  fn test_summary() {
+    let table = TableRef::new("", "Agent");
+    let column = meta::Column::new("age", internal::LeafType::Int);
+    let index = meta::Index{ name: "agent_age".to_string(), columns: vec!("age".to_string()), unique: false };
+    let operations = vec!(
+      Operation::AddColumn(table.clone(), column.clone())
+    , Operation::CreateIndex(table.clone(), index)
+    , Operation::RenameColumn(table.clone(), "name".to_string(), "title".to_string())
+    , Operation::MoveTable(table.clone(), "db".to_string())
+    , Operation::DropColumn(table, column)
+    );
+    let summary = summarise(&operations);
+    assert_eq!(summary.additions, vec!("add column Agent.age", "create index agent_age on Agent"));
+    assert_eq!(summary.changes, vec!("rename column Agent.name to title", "move table Agent to db"));
+    assert_eq!(summary.destructive, vec!("drop column Agent.age"));
+  }
+}
@@ -0,0 +1,159 @@
+use crate::database::operations::Risk;
+use crate::database::sql_script::{self, Span};
+
+// the risks an environment lets migrate and rollback run without asking, along with the operations named on the
+// command line to run anyway
+#[derive(Debug, Clone, PartialEq)]
+pub struct Policy {
+  environment: String
+, allowed: Vec<Risk>
+, overrides: Vec<String>
+}
+
+// a script without operation headers wasn't made by plan, nothing says what its statements do so
+// they are taken to be destructive
+pub const UNCLASSIFIED: &str = "statements without operation headers";
+
+// anyone can recompute a script's checksum, so the headers aren't taken at their word. Statements that
+// lose or rewrite data count as that even if no header says so
+pub const UNDERSTATED: &str = "statements riskier than their operation headers say";
+
+// words after DROP in an ALTER TABLE that don't drop a column
+const NOT_COLUMNS: [&str; 9] = ["constraint", "default", "not", "index", "key", "primary", "foreign", "check", "expression"];
+
+impl Policy {
+  pub fn new(environment: &str, allowed: Vec<Risk>) -> Policy {
+    Policy{ environment: environment.to_string(), allowed, overrides: vec!() }
+  }
+
+  pub fn allow_all(environment: &str) -> Policy {
+    Policy::new(environment, Risk::all())
+  }
+
+  pub fn with_overrides(mut self, overrides: Vec<String>) -> Policy {
+    self.overrides = overrides;
+    self
+  }
+
+  pub fn allows(&self, risk: Risk) -> bool {
+    self.allowed.contains(&risk)
+  }
+
+  // every refused operation is listed so they can all be looked at, and overridden, in one go
+  pub fn check(&self, file_name: &str, operations: &[(Risk, String)], statements: &[String]) -> Result<(), String> {
+    let mut operations = operations.to_vec();
+    if operations.is_empty() {
+      operations.push((Risk::Destructive, UNCLASSIFIED.to_string()));
+    }
+    let risk = statements_risk(statements);
+    if operations.iter().all(|(r, _)| *r < risk) {
+      operations.push((risk, UNDERSTATED.to_string()));
+    }
+    let refused: Vec<&(Risk, String)> = operations.iter().filter(|(risk, o)| !self.allows(*risk) && !self.overrides.contains(o)).collect();
+    if refused.is_empty() {
+      return Ok(());
+    }
+    let lines: String = refused.iter().map(|(risk, o)| format!("  {} ({})\n", o, risk.name())).collect();
+    let flags = refused.iter().map(|(_, o)| format!("--allow-operation \"{}\"", o)).collect::<Vec<String>>().join(" ");
+    Err(format!("I won't run {} because the {} environment doesn't allow:\n{}If you mean to, run again with {}", file_name, self.environment, lines, flags))
+  }
+}
+
+// the words of a statement, lowercased unless they are quoted names, without its comments and strings.
+// The parts of a qualified name make one word
+fn words(statement: &str) -> Vec<String> {
+  let chars: Vec<char> = statement.chars().collect();
+  let mut words: Vec<String> = Vec::new();
+  let mut word = String::new();
+  let mut i = 0;
+  while i < chars.len() {
+    let (end, span) = sql_script::span(&chars, i);
+    let c = chars[i];
+    match span {
+      Span::Code if c.is_alphanumeric() || c == '_' || c == '.' => word.extend(c.to_lowercase())
+    , Span::Quoted(quote) if quote != '\'' => word.extend(&chars[i..end])
+    , _ => {
+        if !word.is_empty() {
+          words.push(std::mem::take(&mut word));
+        }
+        if span == Span::Code && !c.is_whitespace() {
+          words.push(c.to_string());
+        }
+      }
+    }
+    i = end;
+  }
+  if !word.is_empty() {
+    words.push(word);
+  }
+  words
+}
+
+// the most a script's statements can do going by what they say. A table is only dropped for good if
+// nothing copied its rows out first, and a column if no other column takes its name afterwards, which
+// is how the rebuilds and swaps gimbal writes keep their data
+fn statements_risk(statements: &[String]) -> Risk {
+  let statements: Vec<Vec<String>> = statements.iter().map(|s| words(s)).collect();
+  let is = |w: &[String], expected: &[&str]| w.len() >= expected.len() && w.iter().zip(expected).all(|(w, e)| w == e);
+  statements.iter().enumerate().map(|(n, w)| {
+    let copied = |table: &String| statements[..n].iter().any(|earlier| earlier.windows(2).any(|p| p[0] == "from" && &p[1] == table));
+    let renamed_to = |column: &String| statements[n + 1..].iter().any(|later| later.windows(2).any(|p| p[0] == "to" && &p[1] == column));
+    if is(w, &["drop", "schema"]) || is(w, &["truncate"]) || is(w, &["delete"]) {
+      Risk::Destructive
+    } else if is(w, &["drop", "table"]) {
+      let tables = w[2..].iter().filter(|t| !["if", "exists", ",", "cascade", "restrict"].contains(&t.as_str()));
+      if tables.clone().count() > 0 && tables.into_iter().all(copied) { Risk::Locking } else { Risk::Destructive }
+    } else if is(w, &["alter", "table"]) {
+      let dropped: Vec<&String> = w.iter().enumerate().filter(|(_, d)| *d == "drop").filter_map(|(i, _)| match w.get(i + 1) {
+        Some(c) if c == "column" => w.get(i + 2)
+      , Some(c) if NOT_COLUMNS.contains(&c.as_str()) => None
+      , c => c
+      }).collect();
+      if dropped.into_iter().any(|c| !renamed_to(c)) {
+        Risk::Destructive
+      } else if w.iter().any(|t| t == "type" || t == "modify") {
+        Risk::DataRewriting
+      } else {
+        Risk::Safe
+      }
+    } else if is(w, &["update"]) {
+      Risk::DataRewriting
+    } else {
+      Risk::Safe
+    }
+  }).max().unwrap_or(Risk::Safe)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_check() {
+    let operations = vec!((Risk::Safe, "add column Agent.age".to_string()), (Risk::Destructive, "drop table Team".to_string()));
+    let policy = Policy::new("prod", vec!(Risk::Safe, Risk::Locking));
+    let error = policy.check("0002_20261019000000.sql", &operations, &[]).unwrap_err();
+    assert_eq!(error, "I won't run 0002_20261019000000.sql because the prod environment doesn't allow:\n  drop table Team (destructive)\nIf you mean to, run again with --allow-operation \"drop table Team\"");
+    let unclassified = policy.check("0003_20261019000000.sql", &[], &[]).unwrap_err();
+    assert!(unclassified.ends_with("doesn't allow:\n  statements without operation headers (destructive)\nIf you mean to, run again with --allow-operation \"statements without operation headers\""));
+    assert!(policy.with_overrides(vec!("drop table Team".to_string())).check("0002_20261019000000.sql", &operations, &[]).is_ok());
+    assert!(Policy::allow_all("dev").check("0002_20261019000000.sql", &operations, &[]).is_ok());
+    assert!(Policy::new("prod", vec!(Risk::Safe)).with_overrides(vec!(UNCLASSIFIED.to_string())).check("0003_20261019000000.sql", &[], &[]).is_ok());
+    let relabelled = vec!((Risk::Safe, "drop table Team".to_string()));
+    let understated = Policy::new("prod", vec!(Risk::Safe, Risk::Locking)).check("0004_20261019000000.sql", &relabelled, &["DROP TABLE \"Team\"".to_string()]).unwrap_err();
+    assert!(understated.contains("  statements riskier than their operation headers say (destructive)\n"));
+  }
+
+  #[test]
+  fn test_statements_risk() {
+    let risk = |statements: &[&str]| statements_risk(&statements.iter().map(|s| s.to_string()).collect::<Vec<String>>());
+    assert_eq!(risk(&["CREATE TABLE a (x integer)", "-- gimbal:irreversible drop table b"]), Risk::Safe);
+    assert_eq!(risk(&["/* gimbal:phase=contract */ ALTER TABLE \"db\".\"User\" DROP COLUMN \"age\""]), Risk::Destructive);
+    assert_eq!(risk(&["ALTER TABLE a DROP x"]), Risk::Destructive);
+    assert_eq!(risk(&["ALTER TABLE a DROP COLUMN \"age\"", "ALTER TABLE a RENAME COLUMN \"age_gimbal_new\" TO \"age\""]), Risk::Safe);
+    assert_eq!(risk(&["ALTER TABLE a DROP CONSTRAINT a_check", "ALTER TABLE a ALTER COLUMN x TYPE bigint USING x::bigint"]), Risk::DataRewriting);
+    assert_eq!(risk(&["CREATE TABLE \"_gimbal_rebuild_User\" (x text)", "INSERT INTO \"_gimbal_rebuild_User\" (x) SELECT x FROM \"User\"", "DROP TABLE \"User\""]), Risk::Locking);
+    assert_eq!(risk(&["DELETE FROM a WHERE 'drop table' = x"]), Risk::Destructive);
+    assert_eq!(risk(&["UPDATE a SET y = x"]), Risk::DataRewriting);
+  }
+}
@@ -0,0 +1,309 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::database::integration;
+use crate::database::meta;
+use crate::database::migrations;
+use crate::database::naming::NamingStrategy;
+use crate::lang::{ast, ast_builder, internal};
+use crate::lang::printer::{self, Notes};
+
+type TableKey = (String, String);
+
+// the model of an existing database, named so that compiling it against the same database asks for
+// the tables it was read from. PerNamespace makes a namespace of each schema and Flatten takes it from
+// the start of each table's name. Whatever gimbal can't express is left out and noted instead, as is a column
+// named like a function another entity of the namespace already has, since a namespace names each function once
+pub fn reverse(app_name: &str, database: &meta::Database, db_config: &meta::DatabaseConfig, schema: Option<&str>) -> (ast::Application, Notes) {
+  let schema_mapping = db_config.schema_mapping();
+  let naming = db_config.naming();
+  let tables: Vec<&meta::Table> = match schema_mapping {
+    meta::SchemaMapping::PerNamespace => database.tables().iter().collect()
+  , meta::SchemaMapping::Flatten => database.tables_in_schema("")
+  }.into_iter()
+    .filter(|t| schema.is_none_or(|s| t.schema() == s))
+    .filter(|t| ![migrations::HISTORY_TABLE, migrations::CHECKPOINT_TABLE, migrations::LOCK_TABLE].contains(&t.name().as_str()))
+    .collect();
+  let entities: BTreeMap<TableKey, ast::QualifiedName> = tables.iter()
+    .filter_map(|t| Some(((t.schema(), t.name()), entity_name(t, schema_mapping, naming)?)))
+    .collect();
+  let mut notes = Notes::default();
+  let mut atypes: HashMap<ast::QualifiedName, ast::AType> = HashMap::new();
+  let mut function_names: HashSet<(String, String)> = HashSet::new();
+  for table in tables {
+    let e_qn = match entities.get(&(table.schema(), table.name())) {
+      None => {
+        note_left_out_table(&mut notes, table, schema_mapping);
+        continue;
+      }
+    , Some(e_qn) => e_qn
+    };
+    atypes.insert(e_qn.clone(), ast::AType::EntityType(ast::EntityType::new(e_qn.clone())));
+    let mut references: Vec<String> = Vec::new();
+    for column in table.columns() {
+      match column_function(column, table, e_qn, database, &entities, naming) {
+        Err(reason) => notes.note(e_qn, &format!("left out column {} {}, {}", column.name(), declared(column), reason))
+      , Ok(f) if !function_names.insert((f.qualified_name().namespace(), f.name())) => {
+          notes.note(e_qn, &format!("left out column {} {}, another entity in {} has a function of that name", column.name(), declared(column), e_qn.namespace()))
+        }
+      , Ok(f) => {
+          if f.codom().namespace() != internal::INTERNAL_NAMESPACE {
+            references.push(column.name());
+          }
+          if let Some(note) = declaration_note(column) {
+            notes.note(&f.qualified_name(), &note);
+          }
+          atypes.insert(f.qualified_name(), ast::AType::FunctionType(f));
+        }
+      }
+    }
+    key_notes(table, &references).iter().for_each(|n| notes.note(e_qn, n));
+  }
+  (ast_builder::application(app_name, atypes), notes)
+}
+
+// the entity a table was made for, found by undoing the naming strategy and kept only if the entity
+// names the same table again
+fn entity_name(table: &meta::Table, schema_mapping: meta::SchemaMapping, naming: &NamingStrategy) -> Option<ast::QualifiedName> {
+  let unprefixed = table.name().strip_prefix(naming.prefix.as_str())?.to_string();
+  let singulars = if naming.pluralise { singulars(&unprefixed) } else { vec!(unprefixed) };
+  singulars.iter().flat_map(|s| match schema_mapping {
+    meta::SchemaMapping::PerNamespace => vec!((table.schema(), s.clone()))
+  , meta::SchemaMapping::Flatten => s.match_indices('_').map(|(i, _)| (s[..i].to_string(), s[i + 1..].to_string())).collect()
+  }).map(|(namespace, name)| ast::QualifiedName::new(&namespace, &entity_cased(&name, naming), None))
+    .find(|qn| {
+      printer::is_namespace(&qn.namespace()) && printer::is_type_name(&qn.name())
+        && integration::entity_table_name(qn, schema_mapping, naming) == table.name()
+    })
+}
+
+// every singular the plural could have come from, the wrong ones don't name the table again
+fn singulars(plural: &str) -> Vec<String> {
+  [("ies", "y"), ("es", ""), ("s", "")].iter()
+    .filter_map(|(ending, singular)| plural.strip_suffix(ending).map(|s| format!("{}{}", s, singular)))
+    .collect()
+}
+
+fn entity_cased(name: &str, naming: &NamingStrategy) -> String {
+  if !naming.snake_case {
+    return name.to_string();
+  }
+  name.split('_').map(|part| {
+    let mut chars = part.chars();
+    chars.next().map_or(String::new(), |c| c.to_uppercase().chain(chars).collect())
+  }).collect()
+}
+
+fn note_left_out_table(notes: &mut Notes, table: &meta::Table, schema_mapping: meta::SchemaMapping) {
+  let note = format!("left out table {}, no entity name gives it under the configured naming", table_display(table));
+  match schema_mapping {
+    meta::SchemaMapping::PerNamespace if printer::is_namespace(&table.schema()) => notes.note_namespace(&table.schema(), &note)
+  , _ => notes.note_app(&note)
+  }
+}
+
+fn table_display(table: &meta::Table) -> String {
+  if table.schema().is_empty() { table.name() } else { format!("{}.{}", table.schema(), table.name()) }
+}
+
+fn column_function(column: &meta::Column, table: &meta::Table, e_qn: &ast::QualifiedName, database: &meta::Database,
+                   entities: &BTreeMap<TableKey, ast::QualifiedName>, naming: &NamingStrategy) -> Result<ast::FunctionType, String> {
+  let name = column.name();
+  if !printer::is_function_name(&name) || naming.column_name(&name) != name {
+    return Err("no function name gives it under the configured naming".to_string());
+  }
+  let codom = match column.data_type() {
+    meta::ColumnType::Unknown(declaration) => return Err(format!("{} has no gimbal type", declaration))
+  , meta::ColumnType::Known(internal::LeafType::Id) => referenced_entity(table, &name, database, entities)?
+  , meta::ColumnType::Known(leaf_type) => ast::QualifiedName::new(internal::INTERNAL_NAMESPACE, &leaf_type.name(), None)
+  };
+  let f_qn = ast::QualifiedName::new(&e_qn.namespace(), &name, Some((&e_qn.namespace(), &e_qn.name())));
+  Ok(ast::FunctionType::new(f_qn, e_qn.clone(), codom))
+}
+
+// gimbal only keeps ids to refer to entities, so an id column needs a foreign key to a table that became one
+fn referenced_entity(table: &meta::Table, column: &str, database: &meta::Database, entities: &BTreeMap<TableKey, ast::QualifiedName>) -> Result<ast::QualifiedName, String> {
+  let referenced = table.constraints().iter().find_map(|k| match &k.kind {
+    meta::ConstraintKind::ForeignKey(referenced) if k.columns == [column] => Some(referenced.clone())
+  , _ => None
+  }).ok_or_else(|| "it holds ids without referencing another table".to_string())?;
+  referenced_table(database, &referenced)
+    .and_then(|t| entities.get(&(t.schema(), t.name())))
+    .cloned()
+    .ok_or_else(|| format!("it references {}, which was left out", referenced))
+}
+
+// foreign keys name the table they reference the way the database prints it, with its schema when
+// it needs one and quoted where it has to be
+fn referenced_table<'a>(database: &'a meta::Database, referenced: &str) -> Option<&'a meta::Table> {
+  let mut parts: Vec<String> = vec!(String::new());
+  let mut quoted = false;
+  let mut chars = referenced.chars().peekable();
+  while let Some(c) = chars.next() {
+    match c {
+      '"' if quoted && chars.peek() == Some(&'"') => {
+        chars.next();
+        parts.last_mut()?.push(c);
+      }
+    , '"' => quoted = !quoted
+    , '.' if !quoted => parts.push(String::new())
+    , _ => parts.last_mut()?.push(c)
+    }
+  }
+  match parts.as_slice() {
+    [name] => database.table("", name)
+  , [schema, name] => database.table(schema, name)
+  , _ => None
+  }
+}
+
+// what the declaration says beyond the column's gimbal type
+fn declaration_note(column: &meta::Column) -> Option<String> {
+  let sized = column.sql_type().is_some_and(|t| t.length.is_some() || t.precision.is_some());
+  if column.nullable() && column.default().is_none() && !sized {
+    return None;
+  }
+  Some(format!("declared {}", declared(column)))
+}
+
+fn declared(column: &meta::Column) -> String {
+  let mut declaration = column.sql_type().map_or(column.data_type().name(), |t| t.declaration());
+  if !column.nullable() {
+    declaration.push_str(" not null");
+  }
+  if let Some(default) = column.default() {
+    declaration.push_str(&format!(" default {}", default));
+  }
+  declaration
+}
+
+// keys and indexes have no gimbal equivalent, apart from the foreign keys of columns that became references.
+// An index that backs a key is noted with the key
+fn key_notes(table: &meta::Table, references: &[String]) -> Vec<String> {
+  let columns = |c: &Vec<String>| c.join(", ");
+  let mut notes: Vec<String> = table.constraints().iter().filter_map(|k| {
+    let described = match &k.kind {
+      meta::ConstraintKind::ForeignKey(_) if k.columns.len() == 1 && references.contains(&k.columns[0]) => return None
+    , meta::ConstraintKind::ForeignKey(referenced) => format!("foreign key {} ({}) references {}", k.name, columns(&k.columns), referenced)
+    , meta::ConstraintKind::PrimaryKey => format!("primary key {} ({})", k.name, columns(&k.columns))
+    , meta::ConstraintKind::Unique => format!("unique {} ({})", k.name, columns(&k.columns))
+    , meta::ConstraintKind::Check => format!("check {} ({})", k.name, columns(&k.columns))
+    , meta::ConstraintKind::Other => format!("constraint {} ({})", k.name, columns(&k.columns))
+    };
+    Some(described)
+  }).collect();
+  let backs_key = |i: &meta::Index| table.constraints().iter().any(|k| {
+    k.name == i.name || (i.unique && k.columns == i.columns && [meta::ConstraintKind::PrimaryKey, meta::ConstraintKind::Unique].contains(&k.kind))
+  });
+  notes.extend(table.indexes().iter().filter(|i| !backs_key(i)).map(|i| {
+    format!("{} {} ({})", if i.unique { "unique index" } else { "index" }, i.name, columns(&i.columns))
+  }));
+  notes
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::database::integration::DiffDiagnosis;
+  use crate::lang::printer;
+
+  fn column(name: &str, data_type: meta::ColumnType, declared: &str, nullable: bool, default: Option<&str>) -> meta::Column {
+    let sql_type = meta::SqlType{ name: declared.to_string(), length: None, precision: None, scale: None };
+    meta::Column::introspected(name, data_type, sql_type, nullable, default.map(|d| d.to_string()))
+  }
+
+  fn known(leaf_type: internal::LeafType) -> meta::ColumnType {
+    meta::ColumnType::Known(leaf_type)
+  }
+
+  fn shop_config() -> meta::DatabaseConfig {
+    let customers = meta::Table::new("shop", "customers", vec!(
+      column("id", known(internal::LeafType::Id), "uuid", false, None)
+    , column("name", known(internal::LeafType::String), "text", false, None)
+    , column("loyalty_points", known(internal::LeafType::Int), "int4", true, Some("0"))
+    )).with_keys(vec!(meta::Constraint{ name: "customers_pkey".to_string(), kind: meta::ConstraintKind::PrimaryKey, columns: vec!("id".to_string()) })
+               , vec!(meta::Index{ name: "customers_pkey".to_string(), columns: vec!("id".to_string()), unique: true }));
+    let orders = meta::Table::new("shop", "orders", vec!(
+      column("customer", known(internal::LeafType::Id), "uuid", true, None)
+    , column("name", known(internal::LeafType::String), "text", true, None)
+    , column("quantity", known(internal::LeafType::Int), "int4", true, None)
+    , column("placed_at", meta::ColumnType::Unknown("timestamptz".to_string()), "timestamptz", true, None)
+    , column("legacyCode", known(internal::LeafType::String), "text", true, None)
+    )).with_keys(vec!(meta::Constraint{ name: "orders_customer_fkey".to_string(), kind: meta::ConstraintKind::ForeignKey("shop.customers".to_string()), columns: vec!("customer".to_string()) })
+               , vec!(meta::Index{ name: "orders_placed_at".to_string(), columns: vec!("placed_at".to_string()), unique: false }));
+    let tables = vec!(
+      customers
+    , orders
+    , meta::Table::new("shop", "order_items", vec!())
+    , meta::Table::new("shop", "Audit", vec!(column("entry", known(internal::LeafType::String), "text", true, None)))
+    , meta::Table::new("Legacy", "things", vec!())
+    , meta::Table::new("shop", migrations::HISTORY_TABLE, vec!())
+    );
+    let naming = NamingStrategy{ snake_case: true, pluralise: true, ..NamingStrategy::default() };
+    meta::DatabaseConfig::MockDb(meta::MockDbConfig{ schema_mapping: meta::SchemaMapping::PerNamespace, naming, ..meta::MockDbConfig::new(tables) })
+  }
+
+  #[test]
+  fn test_reverse() {
+    let db_config = shop_config();
+    let database = integration::introspect(None, &db_config).unwrap();
+    let (ast, notes) = reverse("shop", &database, &db_config, None);
+    let sources = printer::namespace_sources(&ast, &notes);
+    assert_eq!(sources.keys().collect::<Vec<&String>>(), vec!("shop"));
+    assert_eq!(sources.get("shop").unwrap(), "namespace shop where
+
+struct persists Customer
+loyalty_points:: Customer -> Int
+name:: Customer -> String
+
+struct persists Order
+customer:: Order -> Customer
+quantity:: Order -> Int
+");
+    assert_eq!(printer::notes_source(&ast, &notes), "left out table Legacy.things, no entity name gives it under the configured naming
+
+namespace shop
+left out table shop.order_items, no entity name gives it under the configured naming
+left out table shop.Audit, no entity name gives it under the configured naming
+Customer: left out column id uuid not null, it holds ids without referencing another table
+Customer: primary key customers_pkey (id)
+Customer.loyalty_points: declared int4 default 0
+Customer.name: declared text not null
+Order: left out column name text, another entity in shop has a function of that name
+Order: left out column placed_at timestamptz, timestamptz has no gimbal type
+Order: left out column legacyCode text, no function name gives it under the configured naming
+Order: index orders_placed_at (placed_at)
+");
+  }
+
+  #[test]
+  fn test_reverse_compiles_without_diffs() {
+    let db_config = shop_config();
+    let database = integration::introspect(None, &db_config).unwrap();
+    let (ast, notes) = reverse("shop", &database, &db_config, Some("shop"));
+    let main_code = format!("app shop\n{}", printer::namespace_sources(&ast, &notes).get("shop").unwrap());
+    let compiled = ast_builder::build(&main_code).unwrap();
+    let diffs = integration::diagnose_db_diffs(&compiled, &db_config).unwrap();
+    assert_eq!(diffs.len(), 2);
+    assert!(diffs.iter().all(|d| d.diff_diagnosis().iter().all(|diagnosis| *diagnosis == DiffDiagnosis::NoDiff)));
+  }
+
+  #[test]
+  fn test_entity_name() {
+    let flatten = NamingStrategy{ prefix: "app_".to_string(), ..NamingStrategy::default() };
+    let table = |name: &str| meta::Table::new("", name, vec!());
+    assert_eq!(entity_name(&table("app_db_Agent"), meta::SchemaMapping::Flatten, &flatten), Some(ast::QualifiedName::new("db", "Agent", None)));
+    assert_eq!(entity_name(&table("db_Agent"), meta::SchemaMapping::Flatten, &flatten), None);
+    let snake = NamingStrategy{ snake_case: true, pluralise: true, ..NamingStrategy::default() };
+    assert_eq!(entity_name(&table("sales_categories"), meta::SchemaMapping::Flatten, &snake), Some(ast::QualifiedName::new("sales", "Category", None)));
+    assert_eq!(entity_name(&table("sales_addresses"), meta::SchemaMapping::Flatten, &snake), Some(ast::QualifiedName::new("sales", "Address", None)));
+    assert_eq!(entity_name(&meta::Table::new("db", "strings", vec!()), meta::SchemaMapping::PerNamespace, &snake), None);
+  }
+
+  #[test]
+  fn test_referenced_table() {
+    let database = meta::Database::new(vec!(meta::Table::new("db", "Customer", vec!()), meta::Table::new("my.schema", "a\"b", vec!())));
+    assert_eq!(referenced_table(&database, "db.\"Customer\"").map(|t| t.name()), Some("Customer".to_string()));
+    assert_eq!(referenced_table(&database, "\"my.schema\".\"a\"\"b\"").map(|t| t.schema()), Some("my.schema".to_string()));
+    assert!(referenced_table(&database, "other.Customer").is_none());
+  }
+}
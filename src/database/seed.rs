@@ -0,0 +1,524 @@
+use std::collections::{BTreeMap, HashMap};
+
+use sha2::{Digest, Sha256};
+use yaml_rust::{Yaml, YamlLoader};
+
+use crate::database::drivers;
+use crate::database::integration::{self, DbDiff};
+use crate::database::meta;
+use crate::database::operations::TableRef;
+use crate::lang::{ast, internal};
+
+// the model's reference data, beside the main file
+pub const FIXTURES_FILE: &str = "fixtures.yml";
+
+const KEY: &str = "key";
+const ROWS: &str = "rows";
+
+// a value as its column takes it, a reference is the key of the row it refers to
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+  String(String)
+, Int(i64)
+, Float(f64)
+, Bool(bool)
+, Id(String)
+, Null(internal::LeafType)
+}
+
+impl Value {
+  pub fn leaf_type(&self) -> internal::LeafType {
+    match self {
+      Value::String(_) => internal::LeafType::String
+    , Value::Int(_) => internal::LeafType::Int
+    , Value::Float(_) => internal::LeafType::Float
+    , Value::Bool(_) => internal::LeafType::Bool
+    , Value::Id(_) => internal::LeafType::Id
+    , Value::Null(leaf_type) => leaf_type.clone()
+    }
+  }
+
+  // how the value goes into a row's id, so ids don't change with how SQL writes values
+  fn key_text(&self) -> String {
+    match self {
+      Value::String(s) | Value::Id(s) => s.clone()
+    , Value::Int(i) => i.to_string()
+    , Value::Float(f) => f.to_string()
+    , Value::Bool(b) => b.to_string()
+    , Value::Null(_) => "null".to_string()
+    }
+  }
+}
+
+// one row to write into a table, the values of its key columns pick the row it replaces
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+  pub table: TableRef
+, pub key: Vec<(String, Value)>
+, pub values: Vec<(String, Value)>
+}
+
+// an entity's fixture rows, each by the symbolic name other fixtures refer to it by. The functions
+// named as its key tell its rows apart, and its rows' ids are made from them
+#[derive(Debug)]
+pub struct Fixture {
+  entity: ast::QualifiedName
+, key: Vec<String>
+, rows: Vec<FixtureRow>
+}
+
+#[derive(Debug)]
+struct FixtureRow {
+  name: String
+, id: String
+, values: Vec<(String, Value)>
+}
+
+// a function's value before the rows it refers to have ids
+#[derive(Debug)]
+enum Field {
+  Value(Value)
+, Reference(ast::QualifiedName, String)
+}
+
+// the fixtures as the file gives them, checked against the model
+struct ReadFixture {
+  entity: ast::QualifiedName
+, key: Vec<String>
+, rows: Vec<(String, Vec<(String, Field)>)>
+}
+
+// writes the fixtures into the database, updating the rows they have already written. The tables
+// they go in must match the model. Returns how many rows went into each table
+pub fn seed(ast: &ast::Application, text: &str, db_config: &meta::DatabaseConfig) -> Result<Vec<(String, usize)>, String> {
+  let fixtures = read_fixtures(ast, text)?;
+  let db_diffs: Vec<DbDiff> = integration::diagnose_db_diffs(ast, db_config)?.into_iter()
+    .filter(|d| fixtures.iter().any(|f| &f.entity == d.entity_name()))
+    .collect();
+  integration::check_matches_model(&db_diffs, "The database to seed")?;
+  check_references(&fixtures, db_config)?;
+  let rows = rows(&fixtures, &db_diffs, db_config);
+  drivers::driver_for(db_config)?.upsert(&rows, db_config)?;
+  Ok(fixtures.iter().map(|f| (entity_table(&db_diffs, &f.entity).name, f.rows.len())).collect())
+}
+
+// postgres and duckdb keep references in uuid columns, which only take the keys that are uuids
+fn check_references(fixtures: &[Fixture], db_config: &meta::DatabaseConfig) -> Result<(), String> {
+  if !["postgres", "duckdb"].contains(&db_config.dialect()) {
+    return Ok(());
+  }
+  let errors: Vec<String> = fixtures.iter().flat_map(|f| f.rows.iter().flat_map(move |row| row.values.iter().filter_map(move |(name, value)| match value {
+    Value::Id(key) if uuid::Uuid::parse_str(key).is_err() => {
+      Some(format!("{} {}: {} refers to a row keyed by {}, {} keeps references as uuids.", qualified(&f.entity), row.name, name, key, db_config.dialect()))
+    }
+  , _ => None
+  }))).collect();
+  if errors.is_empty() { Ok(()) } else { Err(errors.join("\n")) }
+}
+
+fn entity_table(db_diffs: &[DbDiff], entity: &ast::QualifiedName) -> TableRef {
+  db_diffs.iter().find(|d| d.entity_name() == entity).map(|d| TableRef::of(d.db_table())).expect("fixture entity not in the model")
+}
+
+// the fixtures in the order the file gives them, every problem with them is reported at once
+pub fn read_fixtures(ast: &ast::Application, text: &str) -> Result<Vec<Fixture>, String> {
+  let documents = YamlLoader::load_from_str(text).map_err(|e| format!("I couldn't read the fixtures: {}", e))?;
+  let mut errors: Vec<String> = Vec::new();
+  let mut read: Vec<ReadFixture> = Vec::new();
+  for (entity, fixture) in documents.first().and_then(|d| d.as_hash()).into_iter().flatten() {
+    match read_fixture(ast, entity, fixture, &mut errors) {
+      Some(f) if read.iter().any(|r| r.entity == f.entity) => errors.push(format!("{} has fixtures twice.", describe(entity)))
+    , Some(f) => read.push(f)
+    , None => {}
+    }
+  }
+  let mut ids: HashMap<(ast::QualifiedName, String), String> = HashMap::new();
+  for fixture in &read {
+    for (name, _) in &fixture.rows {
+      if let Err(e) = row_id(&read, &fixture.entity, name, &mut ids, &mut vec!()) {
+        errors.push(e);
+      }
+    }
+  }
+  let fixtures: Vec<Fixture> = read.iter().map(|f| resolve(f, &read, &ids, &mut errors)).collect();
+  for fixture in &fixtures {
+    let mut by_id: BTreeMap<&str, &str> = BTreeMap::new();
+    for row in &fixture.rows {
+      if let Some(other) = by_id.insert(&row.id, &row.name) {
+        errors.push(format!("{} {} and {} have the same key.", qualified(&fixture.entity), other, row.name));
+      }
+    }
+  }
+  if errors.is_empty() { Ok(fixtures) } else { Err(errors.join("\n")) }
+}
+
+fn read_fixture(ast: &ast::Application, entity: &Yaml, fixture: &Yaml, errors: &mut Vec<String>) -> Option<ReadFixture> {
+  let e_qn = match entity.as_str().and_then(|e| e.rsplit_once('.')) {
+    Some((namespace, name)) => ast::QualifiedName::new(namespace, name, None)
+  , None => {
+      errors.push(format!("{} isn't an entity, fixtures are given for namespace.Entity.", describe(entity)));
+      return None;
+    }
+  };
+  let functions = match ast.get_entity_functions(&e_qn) {
+    Some(functions) => functions
+  , None => {
+      errors.push(format!("There is no entity {} in the model.", qualified(&e_qn)));
+      return None;
+    }
+  };
+  let function = |name: &str| functions.iter().filter_map(|f| ast.get_type(f)?.try_to_function_type()).find(|f| f.name() == name);
+  if let Some(other) = fixture.as_hash().into_iter().flatten().map(|(k, _)| k).find(|k| k.as_str() != Some(KEY) && k.as_str() != Some(ROWS)) {
+    errors.push(format!("{} fixtures only have a {} and {}, not {}.", qualified(&e_qn), KEY, ROWS, describe(other)));
+  }
+  let key: Vec<String> = fixture[KEY].as_vec().into_iter().flatten().map(|k| k.as_str().unwrap_or_default().to_string()).collect();
+  if key.is_empty() {
+    errors.push(format!("{} needs a {} naming the functions that tell its rows apart.", qualified(&e_qn), KEY));
+  }
+  key.iter().filter(|k| function(k).is_none()).for_each(|k| errors.push(format!("{} can't be keyed by {}, it has no such function.", qualified(&e_qn), k)));
+  let mut rows: Vec<(String, Vec<(String, Field)>)> = Vec::new();
+  for (name, row) in fixture[ROWS].as_hash().into_iter().flatten() {
+    let name = match name.as_str() {
+      Some(n) => n.to_string()
+    , None => {
+        errors.push(format!("{} rows are named by strings, not {}.", qualified(&e_qn), describe(name)));
+        continue;
+      }
+    };
+    let mut fields: Vec<(String, Field)> = Vec::new();
+    for (f_name, value) in row.as_hash().into_iter().flatten() {
+      let f_name = f_name.as_str().unwrap_or_default();
+      match function(f_name).ok_or_else(|| format!("{} has no function {}", qualified(&e_qn), f_name)).and_then(|f| field(ast, f, value)) {
+        Ok(Field::Value(Value::Null(_))) if key.iter().any(|k| k == f_name) => errors.push(format!("{} {}: {} can't be null, it is part of the key.", qualified(&e_qn), name, f_name))
+      , Ok(field) => fields.push((f_name.to_string(), field))
+      , Err(e) => errors.push(format!("{} {}: {}.", qualified(&e_qn), name, e))
+      }
+    }
+    if row.as_hash().is_none() {
+      errors.push(format!("{} {}: a row gives its functions' values, not {}.", qualified(&e_qn), name, describe(row)));
+    }
+    key.iter().filter(|k| function(k).is_some() && !fields.iter().any(|(f, _)| &f == k))
+      .for_each(|k| errors.push(format!("{} {}: {} is missing, it is part of the key.", qualified(&e_qn), name, k)));
+    rows.push((name, fields));
+  }
+  Some(ReadFixture{ entity: e_qn, key, rows })
+}
+
+// values are checked against the function's codomain, a reference gives the name of the row it refers to
+fn field(ast: &ast::Application, function: &ast::FunctionType, value: &Yaml) -> Result<Field, String> {
+  let codom = function.codom();
+  let leaf_type = match ast.get_type(&codom) {
+    Some(ast::AType::LeafType(leaf_type)) => leaf_type.clone()
+  , Some(ast::AType::EntityType(_)) => {
+      return match value {
+        Yaml::String(name) => Ok(Field::Reference(codom, name.clone()))
+      , Yaml::Null => Ok(Field::Value(Value::Null(internal::LeafType::Id)))
+      , _ => Err(format!("{} refers to a {} row by its name, not {}", function.name(), qualified(&codom), describe(value)))
+      };
+    }
+  , _ => return Err(format!("{} can't be seeded", function.name()))
+  };
+  let typed = match (&leaf_type, value) {
+    (_, Yaml::Null) => Some(Value::Null(leaf_type.clone()))
+  , (internal::LeafType::String, Yaml::String(s)) => Some(Value::String(s.clone()))
+  , (internal::LeafType::Int, Yaml::Integer(i)) => Some(Value::Int(*i))
+  , (internal::LeafType::Float, Yaml::Integer(i)) => Some(Value::Float(*i as f64))
+  // no dialect writes NaN or infinity the same way, so only finite numbers are seeded
+  , (internal::LeafType::Float, Yaml::Real(r)) => r.parse().ok().filter(|f: &f64| f.is_finite()).map(Value::Float)
+  , (internal::LeafType::Bool, Yaml::Boolean(b)) => Some(Value::Bool(*b))
+  , _ => None
+  };
+  typed.map(Field::Value).ok_or_else(|| format!("{} is {}, not {}", function.name(), leaf_type.name(), describe(value)))
+}
+
+// a row's id is made from its entity and its key, so it is the same every time and in every database
+// it is seeded into. A key that refers to another row takes that row's id, which is made first
+fn row_id(fixtures: &[ReadFixture], entity: &ast::QualifiedName, name: &str, ids: &mut HashMap<(ast::QualifiedName, String), String>,
+          making: &mut Vec<String>) -> Result<String, String> {
+  if let Some(id) = ids.get(&(entity.clone(), name.to_string())) {
+    return Ok(id.clone());
+  }
+  let row = format!("{} {}", qualified(entity), name);
+  if making.contains(&row) {
+    making.push(row);
+    return Err(format!("The keys of {} refer to each other.", making.join(", ")));
+  }
+  let fixture = fixtures.iter().find(|f| &f.entity == entity).ok_or_else(|| format!("there are no {} fixtures", qualified(entity)))?;
+  let (_, fields) = fixture.rows.iter().find(|(n, _)| n == name).ok_or_else(|| format!("there is no {} fixture {}", qualified(entity), name))?;
+  making.push(row);
+  let mut text = qualified(entity);
+  for k in &fixture.key {
+    let value = match fields.iter().find(|(f, _)| f == k) {
+      Some((_, Field::Value(value))) => value.key_text()
+    , Some((_, Field::Reference(to, to_name))) => row_id(fixtures, to, to_name, ids, making)?
+    , None => String::new()
+    };
+    text.push_str(&format!("\n{}={}", k, value));
+  }
+  making.pop();
+  let mut bytes = [0; 16];
+  bytes.copy_from_slice(&Sha256::digest(text.as_bytes())[..16]);
+  let id = uuid::Builder::from_bytes(bytes).set_variant(uuid::Variant::RFC4122).set_version(uuid::Version::Sha1).build().to_string();
+  ids.insert((entity.clone(), name.to_string()), id.clone());
+  Ok(id)
+}
+
+// a reference holds the key of the row it refers to, so the rows join on the referred to entity's key
+// column. Only an entity keyed by one function can be referred to, and a key that is a reference holds
+// the key of the row it refers to in turn. Rows without ids are in a loop of keys and have no key
+fn row_key(fixtures: &[ReadFixture], entity: &ast::QualifiedName, name: &str, ids: &HashMap<(ast::QualifiedName, String), String>) -> Option<Value> {
+  ids.get(&(entity.clone(), name.to_string()))?;
+  let fixture = fixtures.iter().find(|f| &f.entity == entity)?;
+  let key = match fixture.key.as_slice() {
+    [key] => key
+  , _ => return None
+  };
+  let (_, fields) = fixture.rows.iter().find(|(n, _)| n == name)?;
+  match fields.iter().find(|(f, _)| f == key)? {
+    (_, Field::Value(value)) => Some(value.clone())
+  , (_, Field::Reference(to, to_name)) => row_key(fixtures, to, to_name, ids).map(|k| Value::Id(k.key_text()))
+  }
+}
+
+fn resolve(fixture: &ReadFixture, fixtures: &[ReadFixture], ids: &HashMap<(ast::QualifiedName, String), String>, errors: &mut Vec<String>) -> Fixture {
+  let rows = fixture.rows.iter().filter_map(|(name, fields)| {
+    let id = ids.get(&(fixture.entity.clone(), name.clone()))?.clone();
+    let values = fields.iter().filter_map(|(f, field)| match field {
+      Field::Value(value) => Some((f.clone(), value.clone()))
+    , Field::Reference(to, to_name) if !ids.contains_key(&(to.clone(), to_name.clone())) => {
+        errors.push(format!("{} {}: {} refers to {}, which isn't a {} fixture.", qualified(&fixture.entity), name, f, to_name, qualified(to)));
+        None
+      }
+    , Field::Reference(to, to_name) => match row_key(fixtures, to, to_name, ids) {
+        Some(key) => Some((f.clone(), Value::Id(key.key_text())))
+      , None => {
+          errors.push(format!("{} {}: {} refers to {}, but only an entity keyed by one function can be referred to and {} isn't.",
+                              qualified(&fixture.entity), name, f, to_name, qualified(to)));
+          None
+        }
+      }
+    }).collect();
+    Some(FixtureRow{ name: name.clone(), id, values })
+  }).collect();
+  Fixture{ entity: fixture.entity.clone(), key: fixture.key.clone(), rows }
+}
+
+// each fixture row in its entity's table, functions named as the configured naming names their columns
+fn rows(fixtures: &[Fixture], db_diffs: &[DbDiff], db_config: &meta::DatabaseConfig) -> Vec<Row> {
+  fixtures.iter().flat_map(|fixture| {
+    let table = entity_table(db_diffs, &fixture.entity);
+    fixture.rows.iter().map(move |row| {
+      let (key, values) = row.values.iter().map(|(f, v)| (db_config.naming().column_name(f), v.clone())).partition(|(c, _)| {
+        fixture.key.iter().any(|k| &db_config.naming().column_name(k) == c)
+      });
+      Row{ table: table.clone(), key, values }
+    })
+  }).collect()
+}
+
+fn qualified(qn: &ast::QualifiedName) -> String {
+  format!("{}.{}", qn.namespace(), qn.name())
+}
+
+fn describe(value: &Yaml) -> String {
+  match value {
+    Yaml::String(s) => format!("\"{}\"", s)
+  , Yaml::Real(r) => r.clone()
+  , Yaml::Integer(i) => i.to_string()
+  , Yaml::Boolean(b) => b.to_string()
+  , Yaml::Array(_) => "a list".to_string()
+  , Yaml::Hash(_) => "a mapping".to_string()
+  , _ => "nothing".to_string()
+  }
+}
+
+// how a dialect writes the statements that seed a row
+pub struct SqlDialect {
+  pub quote_ident: fn(&str) -> String
+, pub literal: fn(&Value) -> String
+  // mysql only selects values without a table from its dummy one
+, pub select_from: &'static str
+}
+
+// a value written the way SQL writes it, dialects that need its type say it around this
+pub fn literal(value: &Value) -> String {
+  match value {
+    Value::String(s) | Value::Id(s) => format!("'{}'", s.replace('\'', "''"))
+  , Value::Int(i) => i.to_string()
+  , Value::Float(f) => format!("{:?}", f)
+  , Value::Bool(b) => if *b { "TRUE".to_string() } else { "FALSE".to_string() }
+  , Value::Null(_) => "NULL".to_string()
+  }
+}
+
+// the row with the key is updated and the row is inserted where no row has the key, so seeding again
+// changes nothing. The tables have no keys of their own for an upsert to conflict on
+pub fn upsert_statements(row: &Row, table: &str, dialect: &SqlDialect) -> Vec<String> {
+  let assignments = |values: &[(String, Value)], separator: &str| {
+    values.iter().map(|(c, v)| format!("{} = {}", (dialect.quote_ident)(c), (dialect.literal)(v))).collect::<Vec<String>>().join(separator)
+  };
+  let condition = assignments(&row.key, " AND ");
+  let mut statements: Vec<String> = Vec::new();
+  if !row.values.is_empty() {
+    statements.push(format!("UPDATE {} SET {} WHERE {}", table, assignments(&row.values, ", "), condition));
+  }
+  let all: Vec<&(String, Value)> = row.key.iter().chain(row.values.iter()).collect();
+  let columns = all.iter().map(|(c, _)| (dialect.quote_ident)(c)).collect::<Vec<String>>().join(", ");
+  let values = all.iter().map(|(_, v)| (dialect.literal)(v)).collect::<Vec<String>>().join(", ");
+  statements.push(format!("INSERT INTO {} ({}) SELECT {}{} WHERE NOT EXISTS (SELECT 1 FROM {} WHERE {})",
+                          table, columns, values, dialect.select_from, table, condition));
+  statements
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{env, fs};
+
+  use super::*;
+  use crate::database::naming::NamingStrategy;
+  use crate::database::operations;
+  use crate::lang::ast_builder;
+
+  const MODEL: &str = "\napp shop\n\nnamespace sales where\n\nstruct persists Country\nstruct persists Customer\n\ncode:: Country -> String\nlabel:: Country -> String\n\
+                       name:: Customer -> String\nvip:: Customer -> Bool\ndiscount:: Customer -> Float\ncountry:: Customer -> Country";
+
+  const FIXTURES: &str = "
+sales.Country:
+  key: [code]
+  rows:
+    fr: {code: FR, label: France}
+    us: {code: US, label: United States}
+sales.Customer:
+  key: [name, country]
+  rows:
+    acme:
+      name: Acme
+      country: us
+      vip: true
+      discount: 5
+";
+
+  fn id(fixtures: &[Fixture], entity: &str, name: &str) -> String {
+    let fixture = fixtures.iter().find(|f| f.entity.name() == entity).unwrap();
+    fixture.rows.iter().find(|r| r.name == name).unwrap().id.clone()
+  }
+
+  #[test]
+  fn test_read_fixtures() {
+    let ast = ast_builder::build(MODEL).unwrap();
+    let fixtures = read_fixtures(&ast, FIXTURES).unwrap();
+    let us = id(&fixtures, "Country", "us");
+    assert_ne!(us, id(&fixtures, "Country", "fr"));
+    assert_eq!(us, id(&read_fixtures(&ast, FIXTURES).unwrap(), "Country", "us"));
+    assert_eq!(fixtures[1].rows[0].values, vec!(
+      ("name".to_string(), Value::String("Acme".to_string()))
+    , ("country".to_string(), Value::Id("US".to_string()))
+    , ("vip".to_string(), Value::Bool(true))
+    , ("discount".to_string(), Value::Float(5.0))
+    ));
+    let renamed = read_fixtures(&ast, &FIXTURES.replace("us:", "usa:").replace("country: us", "country: usa")).unwrap();
+    assert_eq!(id(&renamed, "Country", "usa"), id(&fixtures, "Country", "us"));
+  }
+
+  #[test]
+  fn test_fixture_errors() {
+    let ast = ast_builder::build(MODEL).unwrap();
+    let fixtures = "
+sales.Country:
+  key: [code]
+  rows:
+    fr: {code: FR, population: 67}
+    france: {code: FR, label: 12}
+    none: {label: Nowhere}
+sales.Customer:
+  key: [name]
+  rows:
+    acme: {name: Acme, country: de, vip: yes}
+    bolt: {name: Bolt, discount: .nan}
+    crux: {name: Crux, discount: -.inf}
+    dent: {name: Dent, discount: 1e999}
+sales.Shop:
+  rows: {}
+";
+    assert_eq!(read_fixtures(&ast, fixtures).unwrap_err(), "\
+sales.Country fr: sales.Country has no function population.
+sales.Country france: label is String, not 12.
+sales.Country none: code is missing, it is part of the key.
+sales.Customer acme: vip is Bool, not \"yes\".
+sales.Customer bolt: discount is Float, not .nan.
+sales.Customer crux: discount is Float, not -.inf.
+sales.Customer dent: discount is Float, not 1e999.
+There is no entity sales.Shop in the model.
+sales.Customer acme: country refers to de, which isn't a sales.Country fixture.
+sales.Country fr and france have the same key.");
+    let referred_to_later = "
+sales.Customer:
+  key: [country]
+  rows:
+    acme: {country: us}
+sales.Country:
+  key: [code]
+  rows:
+    us: {code: US}
+";
+    assert!(read_fixtures(&ast, referred_to_later).is_ok());
+  }
+
+  #[test]
+  fn test_seed() {
+    let ast = ast_builder::build(MODEL).unwrap();
+    let tables = integration::diagnose_db_diffs(&ast, &meta::DatabaseConfig::MockDb(meta::MockDbConfig::new(vec!()))).unwrap()
+      .iter().map(|d| d.db_table().clone()).collect();
+    let mock_db_config = meta::DatabaseConfig::MockDb(meta::MockDbConfig::new(tables));
+    assert_eq!(seed(&ast, FIXTURES, &mock_db_config).unwrap(), vec!(("sales_Country".to_string(), 2), ("sales_Customer".to_string(), 1)));
+    let changed = FIXTURES.replace("label: United States", "label: USA");
+    seed(&ast, &changed, &mock_db_config).unwrap();
+    let rows = match &mock_db_config {
+      meta::DatabaseConfig::MockDb(c) => c.state.lock().unwrap().rows.get("sales_Country").cloned().unwrap()
+    , _ => unreachable!()
+    };
+    assert_eq!(rows.len(), 2);
+    assert!(rows[1].contains(&("label".to_string(), Value::String("USA".to_string()))));
+    let outdated = meta::DatabaseConfig::MockDb(meta::MockDbConfig::new(vec!()));
+    assert_eq!(seed(&ast, FIXTURES, &outdated).unwrap_err(), "The database to seed doesn't match the model, sales_Country is out of date. Migrate it first.");
+  }
+
+  #[test]
+  fn test_references_join() {
+    let ast = ast_builder::build(MODEL).unwrap();
+    let path = env::temp_dir().join(format!("gimbal_seed_join_test_{}.db", std::process::id()));
+    let db_config = meta::DatabaseConfig::Sqlite(meta::SqliteConfig{ path: path.to_string_lossy().to_string(), naming: NamingStrategy::default() });
+    let planned = operations::plan(&integration::diagnose_db_diffs(&ast, &db_config).unwrap());
+    integration::migrate_db(&integration::operations_to_script(&planned, &db_config).unwrap(), None, &db_config).unwrap();
+    seed(&ast, FIXTURES, &db_config).unwrap();
+    seed(&ast, FIXTURES, &db_config).unwrap();
+    let connection = rusqlite::Connection::open(&path).unwrap();
+    let joined = connection.prepare(r#"SELECT c."name", k."label" FROM "sales_Customer" c JOIN "sales_Country" k ON c."country" = k."code""#).unwrap()
+      .query_map([], |r| Ok((r.get(0)?, r.get(1)?))).unwrap()
+      .collect::<Result<Vec<(String, String)>, rusqlite::Error>>();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(joined.unwrap(), vec!(("Acme".to_string(), "United States".to_string())));
+    let two_keys = read_fixtures(&ast, &FIXTURES.replace("key: [code]", "key: [code, label]")).unwrap_err();
+    assert_eq!(two_keys, "sales.Customer acme: country refers to us, but only an entity keyed by one function can be referred to and sales.Country isn't.");
+    let postgres = meta::DatabaseConfig::Postgres(meta::PostgresConfig{
+      host: "localhost".to_string(), port: 5432, database: "shop".to_string(), user: "gimbal".to_string(), password: None
+    , schema_mapping: meta::SchemaMapping::Flatten, naming: NamingStrategy::default()
+    });
+    assert_eq!(check_references(&read_fixtures(&ast, FIXTURES).unwrap(), &postgres).unwrap_err(),
+               "sales.Customer acme: country refers to a row keyed by US, postgres keeps references as uuids.");
+  }
+
+  #[test]
+  fn test_upsert_statements() {
+    let row = Row{
+      table: TableRef::new("", "sales_Country")
+    , key: vec!(("code".to_string(), Value::String("O'K".to_string())))
+    , values: vec!(("name".to_string(), Value::Null(internal::LeafType::String)))
+    };
+    let dialect = SqlDialect{ quote_ident: |i| format!("\"{}\"", i), literal, select_from: "" };
+    assert_eq!(upsert_statements(&row, "\"sales_Country\"", &dialect), vec!(
+      r#"UPDATE "sales_Country" SET "name" = NULL WHERE "code" = 'O''K'"#
+    , r#"INSERT INTO "sales_Country" ("code", "name") SELECT 'O''K', NULL WHERE NOT EXISTS (SELECT 1 FROM "sales_Country" WHERE "code" = 'O''K')"#
+    ));
+  }
+}
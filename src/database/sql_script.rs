@@ -0,0 +1,343 @@
+use std::fmt;
+
+use crate::database::migrations;
+use crate::database::operations::{Operation, Risk};
+
+// headers are comments at the top of a migration file, before any statement
+const HEADER_PREFIX: &str = "-- gimbal:";
+const CHECKSUM_HEADER: &str = "checksum";
+const MODEL_HEADER: &str = "model";
+const DIALECT_HEADER: &str = "dialect";
+const FINGERPRINT_HEADER: &str = "fingerprint";
+const OPERATION_HEADER: &str = "operation";
+
+#[derive(Debug, PartialEq)]
+pub struct Script {
+  header: ScriptHeader
+, statements: Vec<String>
+}
+
+// checksum covers everything below its own header, the other headers included, so a hand edited file is noticed
+// and so is an operation header edited to get past a policy. Model is the hash of the model
+// the script was generated from, dialect the database it was written for and fingerprint what the
+// database looked like when it was planned. There is an operation header for each planned operation,
+// its risk and then its description
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ScriptHeader {
+  pub checksum: Option<String>
+, pub model: Option<String>
+, pub dialect: Option<String>
+, pub fingerprint: Option<String>
+, pub operations: Vec<(Risk, String)>
+}
+
+impl Script {
+  pub fn new(model: &str, dialect: &str, statements: Vec<String>) -> Script {
+    let statements: Vec<String> = statements.into_iter().filter(|s| !s.trim().is_empty()).collect();
+    let header = ScriptHeader{ model: Some(model.to_string()), dialect: Some(dialect.to_string()), ..ScriptHeader::default() };
+    Script{ header, statements }.sealed()
+  }
+
+  pub fn with_fingerprint(mut self, fingerprint: &str) -> Script {
+    self.header.fingerprint = Some(fingerprint.to_string());
+    self.sealed()
+  }
+
+  pub fn with_operations(mut self, operations: &[Operation]) -> Script {
+    self.header.operations = operations.iter().map(|o| (o.risk(), o.describe())).collect();
+    self.sealed()
+  }
+
+  fn sealed(mut self) -> Script {
+    self.header.checksum = Some(migrations::checksum(&self.checksummed()));
+    self
+  }
+
+  // everything written below the checksum header
+  fn checksummed(&self) -> String {
+    let headers = vec!(
+      (MODEL_HEADER, &self.header.model)
+    , (DIALECT_HEADER, &self.header.dialect)
+    , (FINGERPRINT_HEADER, &self.header.fingerprint)
+    );
+    let header_lines: String = headers.into_iter()
+      .filter_map(|(k, v)| v.as_ref().map(|v| format!("{}{}={}\n", HEADER_PREFIX, k, v)))
+      .chain(self.header.operations.iter().map(|(risk, o)| format!("{}{}={} {}\n", HEADER_PREFIX, OPERATION_HEADER, risk.name(), o)))
+      .collect();
+    format!("{}\n{}", header_lines, render_statements(&self.statements))
+  }
+
+  pub fn header(&self) -> &ScriptHeader {
+    &self.header
+  }
+
+  pub fn statements(&self) -> &Vec<String> {
+    &self.statements
+  }
+}
+
+impl fmt::Display for Script {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if let Some(checksum) = &self.header.checksum {
+      writeln!(f, "{}{}={}", HEADER_PREFIX, CHECKSUM_HEADER, checksum)?;
+    }
+    write!(f, "{}", self.checksummed())
+  }
+}
+
+// a statement that is only a comment, like the irreversible marker, is written without a terminator
+// so the semicolon doesn't end up inside it
+fn render_statements(statements: &[String]) -> String {
+  statements.iter().map(|s| if split_statements(s).is_empty() { format!("{}\n", s) } else { format!("{};\n", s) }).collect()
+}
+
+pub fn parse(text: &str) -> Result<Script, String> {
+  let mut header = ScriptHeader::default();
+  let header_lines: Vec<&str> = text.lines().take_while(|l| l.starts_with(HEADER_PREFIX)).collect();
+  let mut checksummed = String::new();
+  for l in &header_lines {
+    let (key, value) = match l[HEADER_PREFIX.len()..].find('=') {
+      Some(i) => (&l[HEADER_PREFIX.len()..HEADER_PREFIX.len() + i], Some(l[HEADER_PREFIX.len() + i + 1..].trim().to_string()))
+    , None => (&l[HEADER_PREFIX.len()..], None)
+    };
+    if key != CHECKSUM_HEADER {
+      checksummed.push_str(&format!("{}\n", l));
+    }
+    match key {
+      CHECKSUM_HEADER => header.checksum = value
+    , MODEL_HEADER => header.model = value
+    , DIALECT_HEADER => header.dialect = value
+    , FINGERPRINT_HEADER => header.fingerprint = value
+    , OPERATION_HEADER => header.operations.push(parse_operation(value.as_deref().unwrap_or_default())?)
+    , _ => {}
+    }
+  }
+  let body_start = header_lines.iter().map(|l| l.len() + 1).sum::<usize>().min(text.len());
+  checksummed.push_str(&text[body_start..]);
+  let body = &text[body_start..];
+  let body = body.strip_prefix('\n').unwrap_or(body);
+  let statements = split_statements(body);
+  // operation headers are what a policy goes by, so a script that has them without a checksum was tampered with
+  match &header.checksum {
+    Some(checksum) if *checksum != migrations::checksum(&checksummed) => {
+      return Err("the script doesn't match the checksum in its header, it has been edited since it was generated".to_string());
+    }
+    None if !header.operations.is_empty() => {
+      return Err("the script has operation headers but no checksum, it has been edited since it was generated".to_string());
+    }
+  , _ => {}
+  }
+  Ok(Script{ header, statements })
+}
+
+fn parse_operation(value: &str) -> Result<(Risk, String), String> {
+  let (risk, description) = value.split_once(' ').unwrap_or((value, ""));
+  let risk = Risk::from_name(risk).ok_or_else(|| format!("{} isn't a risk I know in its operation header {}", risk, value))?;
+  Ok((risk, description.to_string()))
+}
+
+// splits on semicolons outside quotes, escape strings, quoted identifiers, dollar quoted bodies and comments.
+// Comments stay with the statement that follows them, statements that are only comments are dropped
+pub fn split_statements(text: &str) -> Vec<String> {
+  let chars: Vec<char> = text.chars().collect();
+  let mut statements: Vec<String> = Vec::new();
+  let mut current = String::new();
+  let mut has_code = false;
+  let mut i = 0;
+  while i < chars.len() {
+    let c = chars[i];
+    let (end, span) = span(&chars, i);
+    if c == ';' {
+      if has_code {
+        statements.push(current.trim().to_string());
+      }
+      current = String::new();
+      has_code = false;
+    } else {
+      current.extend(&chars[i..end]);
+      has_code = has_code || (span != Span::Comment && !c.is_whitespace());
+    }
+    i = end;
+  }
+  if has_code {
+    statements.push(current.trim().to_string());
+  }
+  statements
+}
+
+// what a stretch of SQL is, as far as reading it goes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Span {
+  Comment
+, Quoted(char)
+, Code
+}
+
+// where the stretch of SQL starting at i ends and what it is. A comment or anything quoted runs to its end,
+// code is a character at a time. Escape strings and dollar quoted bodies are quoted with '
+pub fn span(chars: &[char], i: usize) -> (usize, Span) {
+  let c = chars[i];
+  let next = chars.get(i + 1).copied();
+  if c == '-' && next == Some('-') {
+    (find_from(chars, i, "\n").map_or(chars.len(), |e| e + 1), Span::Comment)
+  } else if c == '/' && next == Some('*') {
+    (block_comment_end(chars, i), Span::Comment)
+  } else if c == '\'' && is_escape_string(chars, i) {
+    (escape_string_end(chars, i), Span::Quoted(c))
+  } else if c == '\'' || c == '"' || c == '`' {
+    (quoted_end(chars, i, c), Span::Quoted(c))
+  } else if let Some(tag) = dollar_tag(chars, i) {
+    (find_from(chars, i + tag.len(), &tag).map_or(chars.len(), |e| e + tag.len()), Span::Quoted('\''))
+  } else {
+    (i + 1, Span::Code)
+  }
+}
+
+fn find_from(chars: &[char], from: usize, pattern: &str) -> Option<usize> {
+  let pattern: Vec<char> = pattern.chars().collect();
+  (from..chars.len()).find(|&i| chars[i..].starts_with(&pattern))
+}
+
+// postgres block comments nest
+fn block_comment_end(chars: &[char], start: usize) -> usize {
+  let mut depth = 0;
+  let mut i = start;
+  while i + 1 < chars.len() {
+    if chars[i] == '/' && chars[i + 1] == '*' {
+      depth += 1;
+      i += 2;
+    } else if chars[i] == '*' && chars[i + 1] == '/' {
+      depth -= 1;
+      i += 2;
+      if depth == 0 {
+        return i;
+      }
+    } else {
+      i += 1;
+    }
+  }
+  chars.len()
+}
+
+// a doubled quote inside a quoted string or identifier, mysql's backquoted ones too, is the quote itself
+fn quoted_end(chars: &[char], start: usize, quote: char) -> usize {
+  let mut i = start + 1;
+  while i < chars.len() {
+    if chars[i] == quote {
+      if chars.get(i + 1) == Some(&quote) {
+        i += 2;
+        continue;
+      }
+      return i + 1;
+    }
+    i += 1;
+  }
+  chars.len()
+}
+
+// E'...' where the E isn't the end of an identifier
+fn is_escape_string(chars: &[char], quote: usize) -> bool {
+  quote > 0 && matches!(chars[quote - 1], 'E' | 'e') && !(quote > 1 && (chars[quote - 2].is_alphanumeric() || chars[quote - 2] == '_'))
+}
+
+// inside an escape string a backslash escapes the character after it, a doubled quote still is the quote
+fn escape_string_end(chars: &[char], start: usize) -> usize {
+  let mut i = start + 1;
+  while i < chars.len() {
+    match chars[i] {
+      '\\' => i += 2
+    , '\'' if chars.get(i + 1) == Some(&'\'') => i += 2
+    , '\'' => return i + 1
+    , _ => i += 1
+    }
+  }
+  chars.len()
+}
+
+// $$ or $tag$ where a tag is an identifier, anything else ($1 parameters for instance) isn't a quote
+fn dollar_tag(chars: &[char], start: usize) -> Option<String> {
+  if chars[start] != '$' {
+    return None;
+  }
+  let mut i = start + 1;
+  while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+    i += 1;
+  }
+  let tag: String = chars[start..i].iter().collect();
+  let valid = chars.get(i) == Some(&'$') && !tag[1..].starts_with(|c: char| c.is_ascii_digit());
+  if valid { Some(format!("{}$", tag)) } else { None }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_split() {
+    let script = r#"
+-- make the table
+CREATE TABLE "a;b" (
+  x varchar(10) DEFAULT 'it''s; fine'
+);
+
+/* nested /* comment; */ still comment */
+CREATE FUNCTION f() RETURNS integer AS $body$
+  SELECT 1; SELECT $$;$$;
+$body$ LANGUAGE sql;
+-- only a comment;
+;
+SELECT $1"#;
+    let statements = split_statements(script);
+    assert_eq!(statements.len(), 3);
+    assert!(statements[0].starts_with("-- make the table\nCREATE TABLE \"a;b\""));
+    assert!(statements[0].ends_with("'it''s; fine'\n)"));
+    assert!(statements[1].ends_with("$body$ LANGUAGE sql"));
+    assert_eq!(statements[2], "SELECT $1");
+  }
+
+  #[test]
+  fn test_escape_string() {
+    let statements = split_statements(r"INSERT INTO a VALUES (E'it\'s; fine', e'\\', 'x\'); SELECT 1; SELECT type'a;b'");
+    assert_eq!(statements, vec!(r"INSERT INTO a VALUES (E'it\'s; fine', e'\\', 'x\')", "SELECT 1", "SELECT type'a;b'"));
+    assert_eq!(split_statements("CREATE TABLE `a;b` (x int); SELECT 1").len(), 2);
+  }
+
+  #[test]
+  fn test_round_trip() {
+    let script = Script::new("abc", "postgres", vec!("CREATE TABLE a (x integer)".to_string(), "".to_string(), "CREATE TABLE b (\n  y integer\n)".to_string()))
+      .with_fingerprint("def");
+    let parsed = parse(&script.to_string()).unwrap();
+    assert_eq!(parsed, script);
+    assert_eq!(parsed.header().dialect.as_deref(), Some("postgres"));
+    assert_eq!(parsed.header().fingerprint.as_deref(), Some("def"));
+    assert_eq!(parsed.statements().len(), 2);
+  }
+
+  #[test]
+  fn test_comment_statement() {
+    let marker = format!("{} drop column a.x", crate::database::meta::IRREVERSIBLE_MARKER);
+    let script = Script::new("abc", "postgres", vec!(marker.clone(), "ALTER TABLE a ADD COLUMN x integer".to_string()));
+    assert!(script.to_string().ends_with(&format!("\n{}\nALTER TABLE a ADD COLUMN x integer;\n", marker)));
+    assert_eq!(parse(&script.to_string()).unwrap().statements(), &vec!(format!("{}\nALTER TABLE a ADD COLUMN x integer", marker)));
+  }
+
+  #[test]
+  fn test_operations() {
+    let dropped = Operation::DropTable(crate::database::meta::Table::new("", "a", vec!()));
+    let script = Script::new("abc", "postgres", vec!("DROP TABLE a".to_string())).with_operations(&[dropped]);
+    assert!(script.to_string().contains("-- gimbal:operation=destructive drop table a\n"));
+    let parsed = parse(&script.to_string()).unwrap();
+    assert_eq!(parsed.header().operations, vec!((Risk::Destructive, "drop table a".to_string())));
+    assert!(parse(&script.to_string().replace("=destructive", "=fine")).is_err());
+    let downgraded = parse(&script.to_string().replace("=destructive", "=safe")).unwrap_err();
+    assert!(downgraded.starts_with("the script doesn't match the checksum"));
+    let unsealed = script.to_string().lines().filter(|l| !l.starts_with("-- gimbal:checksum=")).collect::<Vec<&str>>().join("\n");
+    assert!(parse(&unsealed.replace("=destructive", "=safe")).unwrap_err().starts_with("the script has operation headers but no checksum"));
+  }
+
+  #[test]
+  fn test_edited() {
+    let text = Script::new("abc", "postgres", vec!("CREATE TABLE a (x integer)".to_string())).to_string();
+    assert!(parse(&text.replace("integer", "bigint")).is_err());
+  }
+}
@@ -4,6 +4,7 @@ use std::error::Error;
 use std::fmt;
 
 use crate::lang::internal;
+use crate::database::naming::NamingStrategy;
 
 
 #[derive(Debug)]
@@ -50,8 +51,8 @@ impl QualifiedName {
   }
 
   // this only applies to non-function names
-  pub fn table_name(&self) -> String {
-    format!("{}_{}", &self.namespace, &self.name)
+  pub fn table_name(&self, naming: &NamingStrategy) -> String {
+    naming.table_name(&format!("{}_{}", &self.namespace, &self.name))
   }
 }
 
@@ -78,14 +79,24 @@ impl Application {
     self.entity_functions.get(qualified_name)
   }
 
-  pub fn entity_unique_names(&self) -> Vec<String> {
-    self.entity_functions.keys().map(|k| k.table_name()).collect()
+  pub fn entity_unique_names(&self, naming: &NamingStrategy) -> Vec<String> {
+    self.entity_functions.keys().map(|k| k.table_name(naming)).collect()
   }
 
   pub fn entity_functions(&self) -> &HashMap<QualifiedName, Vec<QualifiedName>> {
     &self.entity_functions
   }
 
+  // every entity sorted by name, including the ones without any functions
+  pub fn entity_types(&self) -> Vec<&EntityType> {
+    let mut entities: Vec<&EntityType> = self.types.values().filter_map(|t| match t {
+      AType::EntityType(e) => Some(e)
+    , _ => None
+    }).collect();
+    entities.sort_by_key(|e| e.qualified_name());
+    entities
+  }
+
 
 }
 
@@ -46,7 +46,12 @@ pub fn build(main_code: &str) -> Result<ast::Application, Box<dyn Error>> {
     });
 
   });
+  Ok(application(&app_name, atypes))
+}
 
+// the application made of the entities and functions given, completed with the leaf types and each
+// entity's functions
+pub fn application(app_name: &str, mut atypes: HashMap<ast::QualifiedName, ast::AType>) -> ast::Application {
   let leaf_types = internal::LeafType::all();
   atypes.extend(leaf_types.into_iter().map(|l| {
     let qn = ast::QualifiedName::new(internal::INTERNAL_NAMESPACE, &l.name(), None);
@@ -75,9 +80,10 @@ pub fn build(main_code: &str) -> Result<ast::Application, Box<dyn Error>> {
     }
 
   });
+  domains.values_mut().for_each(|fs| fs.sort());
   
   // = import_csts.iter().map(|c| c.entity_types().iter().map(|e| (c.namespace(), ast::AType::EntityType(ast::EntityType::new(&c.namespace(), &e.name()))))).collect();
-  Ok(ast::Application::new(&app_name, atypes, domains))
+  ast::Application::new(app_name, atypes, domains)
 }
 
 fn qn_default(default_namespace: &str, qualified_pair: (Option<String>, String)) -> ast::QualifiedName {
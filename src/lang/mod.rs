@@ -1,4 +1,5 @@
 pub mod ast_builder;
 pub mod internal;
 pub mod ast;
+pub mod printer;
 mod cst;
\ No newline at end of file
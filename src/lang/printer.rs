@@ -0,0 +1,194 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::lang::{ast, internal};
+
+// what gimbal can't express of whatever the model was made from. The language has no comments, so
+// they are written to a notes file beside the main file: app notes first, then each namespace's notes
+// followed by the notes on its entities and their functions
+#[derive(Debug, Default)]
+pub struct Notes {
+  app: Vec<String>
+, namespaces: BTreeMap<String, Vec<String>>
+, types: BTreeMap<ast::QualifiedName, Vec<String>>
+}
+
+impl Notes {
+  pub fn note_app(&mut self, note: &str) {
+    self.app.push(note.to_string());
+  }
+
+  pub fn note_namespace(&mut self, namespace: &str, note: &str) {
+    self.namespaces.entry(namespace.to_string()).or_default().push(note.to_string());
+  }
+
+  pub fn note(&mut self, qualified_name: &ast::QualifiedName, note: &str) {
+    self.types.entry(qualified_name.clone()).or_default().push(note.to_string());
+  }
+
+  pub fn app_notes(&self) -> &[String] {
+    &self.app
+  }
+
+  pub fn count(&self) -> usize {
+    self.app.len() + self.namespaces.values().chain(self.types.values()).map(|n| n.len()).sum::<usize>()
+  }
+}
+
+// the entities of each namespace in name order, with every namespace that has notes even if it has no entities
+fn namespace_entities<'a>(ast: &'a ast::Application, notes: &Notes) -> BTreeMap<String, Vec<&'a ast::EntityType>> {
+  let mut entities: BTreeMap<String, Vec<&ast::EntityType>> = notes.namespaces.keys().map(|n| (n.clone(), vec!())).collect();
+  ast.entity_types().into_iter().for_each(|e| entities.entry(e.qualified_name().namespace()).or_default().push(e));
+  entities
+}
+
+fn entity_functions<'a>(ast: &'a ast::Application, entity: &ast::EntityType) -> Vec<&'a ast::FunctionType> {
+  ast.get_entity_functions(&entity.qualified_name()).into_iter().flatten().filter_map(|f_qn| ast.get_type(f_qn)?.try_to_function_type()).collect()
+}
+
+// the source of each namespace, keyed by namespace. Entities come in name order, each followed by its functions
+pub fn namespace_sources(ast: &ast::Application, notes: &Notes) -> BTreeMap<String, String> {
+  namespace_entities(ast, notes).into_iter().map(|(namespace, entities)| {
+    let mut code = format!("namespace {} where\n", namespace);
+    entities.iter().for_each(|e| code.push_str(&entity_source(ast, &namespace, e)));
+    (namespace, code)
+  }).collect()
+}
+
+fn entity_source(ast: &ast::Application, namespace: &str, entity: &ast::EntityType) -> String {
+  let mut code = format!("\nstruct persists {}\n", entity.name());
+  entity_functions(ast, entity).into_iter().for_each(|f| {
+    code.push_str(&format!("{}:: {} -> {}\n", f.name(), type_reference(namespace, &f.dom()), type_reference(namespace, &f.codom())));
+  });
+  code
+}
+
+// leaf types and types in the same namespace go by their name alone
+fn type_reference(namespace: &str, qn: &ast::QualifiedName) -> String {
+  if qn.namespace() == namespace || qn.namespace() == internal::INTERNAL_NAMESPACE {
+    qn.name()
+  } else {
+    format!("{}.{}", qn.namespace(), qn.name())
+  }
+}
+
+// the notes file, a paragraph for the app's notes and one for each namespace that has notes. Each note on
+// an entity or function starts with what it is about
+pub fn notes_source(ast: &ast::Application, notes: &Notes) -> String {
+  let lines = |about: &str, notes: Option<&Vec<String>>| -> String {
+    notes.into_iter().flatten().map(|n| format!("{}{}\n", about, n.replace('\n', " "))).collect()
+  };
+  let mut paragraphs: Vec<String> = vec!(lines("", Some(&notes.app)));
+  for (namespace, entities) in namespace_entities(ast, notes) {
+    let mut paragraph = lines("", notes.namespaces.get(&namespace));
+    for entity in entities {
+      paragraph.push_str(&lines(&format!("{}: ", entity.name()), notes.types.get(&entity.qualified_name())));
+      entity_functions(ast, entity).into_iter().for_each(|f| {
+        paragraph.push_str(&lines(&format!("{}.{}: ", entity.name(), f.name()), notes.types.get(&f.qualified_name())));
+      });
+    }
+    if !paragraph.is_empty() {
+      paragraphs.push(format!("namespace {}\n{}", namespace, paragraph));
+    }
+  }
+  paragraphs.into_iter().filter(|p| !p.is_empty()).collect::<Vec<String>>().join("\n")
+}
+
+// the main file importing each namespace's file ahead of what it already had
+pub fn main_source(main_code: &str, namespaces: &[String]) -> String {
+  let imports: String = namespaces.iter().map(|n| format!("import \"./{}\"\n", source_file_name(n))).collect();
+  format!("{}{}", imports, main_code.trim_start())
+}
+
+// the names the grammar accepts, entities can't take a leaf type's name
+pub fn is_namespace(name: &str) -> bool {
+  !name.is_empty() && name.chars().all(|c| c.is_ascii_lowercase() || c == '_')
+}
+
+pub fn is_type_name(name: &str) -> bool {
+  name.starts_with(|c: char| c.is_ascii_uppercase()) && name.chars().skip(1).all(|c| c.is_ascii_lowercase()) && !internal::LeafType::is_leaf_type(name)
+}
+
+pub fn is_function_name(name: &str) -> bool {
+  is_namespace(name)
+}
+
+pub fn source_file_name(namespace: &str) -> String {
+  format!("{}.gim", namespace)
+}
+
+// the notes file goes by the main file's name
+pub fn notes_file_name(main_path: &Path) -> String {
+  main_path.with_extension("notes").file_name().unwrap_or_default().to_string_lossy().to_string()
+}
+
+// writes each namespace's source beside the main file and adds their imports to it, with the notes file
+// if there are notes. Nothing is written if any of the files is already there
+pub fn write_sources(main_path: &Path, ast: &ast::Application, sources: &BTreeMap<String, String>, notes: &Notes) -> Result<Vec<String>, String> {
+  let dir = main_path.parent().unwrap_or_else(|| Path::new("."));
+  let mut file_names: Vec<String> = sources.keys().map(|n| source_file_name(n)).collect();
+  if notes.count() > 0 {
+    file_names.push(notes_file_name(main_path));
+  }
+  if let Some(existing) = file_names.iter().find(|f| dir.join(f).exists()) {
+    return Err(format!("I won't overwrite {}, move it out of the way and try again.", existing));
+  }
+  let main_code = fs::read_to_string(main_path).map_err(|e| format!("I couldn't read {}: {}", main_path.display(), e))?;
+  let write = |path: &Path, code: &str| fs::write(path, code).map_err(|e| format!("I couldn't write {}: {}", path.display(), e));
+  for (file_name, code) in file_names.iter().zip(sources.values()) {
+    write(&dir.join(file_name), code)?;
+  }
+  if notes.count() > 0 {
+    write(&dir.join(notes_file_name(main_path)), &notes_source(ast, notes))?;
+  }
+  write(main_path, &main_source(&main_code, &sources.keys().cloned().collect::<Vec<String>>()))?;
+  Ok(file_names)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lang::ast_builder;
+
+  #[test]
+  fn namespace_sources_test() {
+    let ast = ast_builder::build("\napp shop\n\nnamespace sales where\n\nstruct persists Order\nstruct persists Customer\n\nname:: Customer -> String\ncustomer:: Order -> Customer\ntotal:: Order -> Float").unwrap();
+    let mut notes = Notes::default();
+    notes.note_namespace("sales", "left out table audit");
+    notes.note(&ast::QualifiedName::new("sales", "Order", None), "primary key orders_pkey (id)");
+    notes.note(&ast::QualifiedName::new("sales", "total", Some(("sales", "Order"))), "declared numeric(10,2) not null");
+    notes.note_app("left out schema Legacy");
+    let sources = namespace_sources(&ast, &notes);
+    assert_eq!(sources.get("sales").unwrap(), "namespace sales where
+
+struct persists Customer
+name:: Customer -> String
+
+struct persists Order
+customer:: Order -> Customer
+total:: Order -> Float
+");
+    assert_eq!(notes_source(&ast, &notes), "left out schema Legacy
+
+namespace sales
+left out table audit
+Order: primary key orders_pkey (id)
+Order.total: declared numeric(10,2) not null
+");
+    assert_eq!(main_source("\napp shop\n", &["sales".to_string()]), "import \"./sales.gim\"\napp shop\n");
+    assert_eq!(notes_file_name(Path::new("models/main.gim")), "main.notes");
+    assert_eq!(notes.count(), 4);
+  }
+
+  #[test]
+  fn names_test() {
+    assert!(is_namespace("order_items"));
+    assert!(!is_namespace("shop2"));
+    assert!(is_type_name("Customer"));
+    assert!(!is_type_name("OrderItem"));
+    assert!(!is_type_name("String"));
+    assert!(is_function_name("unit_price"));
+    assert!(!is_function_name("legacyCode"));
+  }
+}
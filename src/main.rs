@@ -5,7 +5,6 @@ extern crate postgres;
 use std::env;
 use std::fs;
 use std::path::Path;
-use std::io::prelude::*;
 
 //use yaml_rust::{YamlLoader, YamlEmitter};
 //use postgres::{Client, NoTls, Error};
@@ -15,50 +14,356 @@ extern crate pest_derive;
 
 mod lang;
 mod database;
+mod config;
 
 
 fn main() {
     /* arg[1] is command
-       compile
-       migrate
+       plan, writes the next migration and summarises it, compile does the same,
+         --zero-downtime stages it in expand, backfill and contract phases that run while the app keeps going
+       apply, runs the planned migration unless the model or the database changed since
+       migrate, runs every pending migration without checking that it was planned against this database,
+         both refuse operations riskier than the environment allows unless each is named with --allow-operation,
+         statements in a file without operation headers count as destructive
+       drift, reports how the database differs from the model, --format json for machines,
+         exits 0 when in sync, 1 when it has drifted and 2 when the database couldn't be read
+       rollback, --to VERSION undoes every migration after VERSION instead of just the latest, under the same policy as migrate
+       copy, --from ENV --to ENV copies the rows of every table from postgres into a duckdb copy
+       reverse, reads the database into a .gim file per namespace beside the main file and imports them there,
+         the main file only needs its app line, --schema reads just that schema. A .notes file named after the main file says what was left out
+       import, does the same from arg[3], a .sql DDL file read like the environment's database would read it
+         or a .json JSON Schema whose object types go in the app's namespace unless --namespace names another
+       seed, writes the rows of arg[3] or else of fixtures.yml beside the main file into the database, again and again
+         without writing them twice. Rows are keyed by the functions each entity's fixtures name and refer to each other by name,
+         a reference holds the key of the row it refers to, so only entities keyed by one function can be referred to
+       plan, apply, migrate, rollback and seed take the app's migration lock first, waiting lock_timeout seconds for it
+       arg[2] is the main file, --env chooses the environment from config.yml
     */
     let args: Vec<String> = env::args().collect();
-    if args[1] == "compile" {
-        println!("{}", compile(&args));
+    if args[1] == "plan" || args[1] == "compile" {
+        println!("{}", plan(&args));
+    } else if args[1] == "apply" {
+        println!("{}", apply(&args));
+    } else if args[1] == "drift" {
+        let (report, code) = drift(&args);
+        println!("{}", report);
+        std::process::exit(code);
     } else if args[1] == "migrate" {
         println!("{}", migrate(&args));
+    } else if args[1] == "rollback" {
+        println!("{}", rollback(&args));
+    } else if args[1] == "copy" {
+        println!("{}", copy(&args));
+    } else if args[1] == "reverse" {
+        println!("{}", reverse(&args));
+    } else if args[1] == "import" {
+        println!("{}", import(&args));
+    } else if args[1] == "seed" {
+        println!("{}", seed(&args));
     } else {
         println!("Error in command");
     }
 }
 
-fn compile(args: &Vec<String>) -> String {
+fn plan(args: &[String]) -> String {
     let ast = lang::ast_builder::build_from_main_file(&args[2]).unwrap();
-    let config = database::meta::DatabaseConfig::Postgres("".to_string());
-    let diffs = database::integration::diagnose_db_diffs(&ast, &config);
-    let script = database::integration::diffs_to_script(&diffs, &config);
-    let path = Path::new("changes.sql");
-    let mut file = match fs::File::create(&path) {
-      Err(m) => panic!("I couldn't save database migration script because: {}", m)
+    let config = match database_config(args, &ast) {
+      Err(e) => return e
+    , Ok(c) => c
+    };
+    let _lock = match lock(args, &ast, &config) {
+      Err(e) => return e
+    , Ok(l) => l
+    };
+    let dir = Path::new(database::migrations::MIGRATIONS_DIR);
+    match database::migrations::unapplied(dir, &config) {
+      Err(m) => return config::redact(&m, &config)
+    , Ok(versions) if !versions.is_empty() => {
+        return format!("Migration {} hasn't been applied yet, apply it before planning another", versions[0]);
+      }
+    , Ok(_) => {}
+    }
+    let diffs = match database::integration::diagnose_db_diffs(&ast, &config) {
+      Err(m) => return config::redact(&m, &config)
+    , Ok(d) => d
+    };
+    let planned = database::operations::plan(&diffs);
+    if planned.is_empty() {
+      return "The database is up to date".to_string();
+    }
+    // a staged migration is undone by staging the reverse of what was planned
+    let staged = if args.iter().any(|a| a == "--zero-downtime") {
+      database::expand_contract::stage(&planned)
+        .and_then(|s| Ok((s, database::expand_contract::stage(&database::operations::reverse(&planned))?)))
+    } else {
+      Ok((planned.clone(), database::operations::reverse(&planned)))
+    };
+    let (operations, reverse) = match staged {
+      Err(m) => return config::redact(&m, &config)
+    , Ok(o) => o
+    };
+    let (script, down) = match database::integration::operations_to_script(&operations, &config)
+      .and_then(|s| database::integration::down_script(&planned, &reverse, &config).map(|d| (s, d))) {
+      Err(m) => return config::redact(&m, &config)
+    , Ok(scripts) => scripts
+    };
+    let model_hash = database::integration::model_hash(&ast, &config);
+    let fingerprint = match database::integration::database_fingerprint(&database::integration::app_schemas(&ast, &config), &config) {
+      Err(m) => return config::redact(&m, &config)
     , Ok(f) => f
     };
+    let up_script = database::sql_script::Script::new(&model_hash, config.dialect(), script.commands().clone())
+      .with_fingerprint(&fingerprint)
+      .with_operations(&operations);
+    let down_script = database::sql_script::Script::new(&model_hash, config.dialect(), down.commands().clone())
+      .with_operations(&reverse);
+    match database::migrations::write_migration(dir, &up_script.to_string(), &down_script.to_string()) {
+      Err(m) => config::redact(&m, &config)
+    , Ok(f) => format!("Migration saved as {}\n\n{}", f.file_name(), database::operations::summarise(&operations))
+    }
+}
+
+fn apply(args: &[String]) -> String {
+    let ast = lang::ast_builder::build_from_main_file(&args[2]).unwrap();
+    let config = match database_config(args, &ast) {
+      Err(e) => return e
+    , Ok(c) => c
+    };
+    let _lock = match lock(args, &ast, &config) {
+      Err(e) => return e
+    , Ok(l) => l
+    };
+    let policy = match policy(args, &ast) {
+      Err(e) => return e
+    , Ok(p) => p
+    };
+    let model_hash = database::integration::model_hash(&ast, &config);
+    let schemas = database::integration::app_schemas(&ast, &config);
+    match database::migrations::apply(Path::new(database::migrations::MIGRATIONS_DIR), &model_hash, &schemas, &policy, &config) {
+      Ok(versions) if versions.is_empty() => "There were no migrations to apply".to_string()
+    , Ok(versions) => {
+        format!("Migration completed, applied {}", versions.iter().map(|v| v.to_string()).collect::<Vec<String>>().join(", "))
+      }
+    , Err(s) => config::redact(&s, &config)
+    }
+}
 
-    match file.write_all(script.to_string().as_bytes()) {
-      Err(m) => format!("I couldn't save database migration script because: {}", m)
-    , Ok(_) => "Migration saved".to_string()
+// config.yml sits next to the main file, which building the ast has made the working directory
+fn database_config(args: &[String], ast: &lang::ast::Application) -> Result<database::meta::DatabaseConfig, String> {
+    let env = flag_value(args, "--env");
+    config::load(config::CONFIG_FILE, &ast.name())
+      .and_then(|c| c.database_config(env.as_deref()))
+      .map_err(|e| e.to_string())
+}
+
+// the environment's policy, letting through every operation named with --allow-operation
+fn policy(args: &[String], ast: &lang::ast::Application) -> Result<database::policy::Policy, String> {
+    let env = flag_value(args, "--env");
+    config::load(config::CONFIG_FILE, &ast.name())
+      .and_then(|c| c.policy(env.as_deref()))
+      .map(|p| p.with_overrides(flag_values(args, "--allow-operation")))
+      .map_err(|e| e.to_string())
+}
+
+// the app's migration lock, taken before the database is read so nothing else migrates it in the meantime
+fn lock<'a>(args: &[String], ast: &lang::ast::Application, config: &'a database::meta::DatabaseConfig) -> Result<Box<dyn database::migrations::HeldLock + 'a>, String> {
+    let env = flag_value(args, "--env");
+    let timeout = config::load(config::CONFIG_FILE, &ast.name())
+      .and_then(|c| c.lock_timeout(env.as_deref()))
+      .map_err(|e| e.to_string())?;
+    database::integration::lock(&ast.name(), &lock_holder(&args[1]), timeout, config).map_err(|e| config::redact(&e, config))
+}
+
+// who is migrating, told to anyone left waiting for the lock
+fn lock_holder(command: &str) -> String {
+    let user = env::var("USER").or_else(|_| env::var("USERNAME")).unwrap_or_else(|_| "someone".to_string());
+    let host = env::var("HOSTNAME").ok()
+      .or_else(|| fs::read_to_string("/etc/hostname").ok().map(|h| h.trim().to_string()))
+      .unwrap_or_else(|| "an unknown host".to_string());
+    format!("gimbal {} by {} on {} (pid {})", command, user, host, std::process::id())
+}
+
+fn flag_value(args: &[String], flag: &str) -> Option<String> {
+    args.iter().position(|a| a == flag).and_then(|i| args.get(i + 1)).cloned()
+}
+
+// a flag that can be given more than once
+fn flag_values(args: &[String], flag: &str) -> Vec<String> {
+    args.windows(2).filter(|w| w[0] == flag).map(|w| w[1].clone()).collect()
+}
+
+fn drift(args: &[String]) -> (String, i32) {
+    let ast = lang::ast_builder::build_from_main_file(&args[2]).unwrap();
+    let json = flag_value(args, "--format").as_deref() == Some("json");
+    let unreachable = |m: String| (if json { database::drift::unreachable_json(&m) } else { m }, database::drift::UNREACHABLE);
+    let config = match database_config(args, &ast) {
+      Err(e) => return unreachable(e)
+    , Ok(c) => c
+    };
+    let report = database::integration::introspect(Some(&database::integration::app_schemas(&ast, &config)), &config).map(|snapshot| {
+      let diffs = database::integration::diagnose_snapshot(&ast, &config, &snapshot);
+      database::drift::drift_report(&diffs, &database::integration::extra_tables(&diffs, &snapshot))
+    });
+    match report {
+      Err(m) => unreachable(config::redact(&m, &config))
+    , Ok(r) => (if json { r.to_json() } else { r.to_text() }, r.exit_code())
     }
 }
 
 fn migrate(args: &Vec<String>) -> String {
-    let config = database::meta::DatabaseConfig::Postgres("".to_string());
-    //let script = database::meta::DatabaseChange::SqlDb(vec![String::from("CREATE TABLE test__Person (first_name varchar(255), last_name varchar(255))")]);
-    let script_str: Vec<String>= fs::read_to_string("changes.sql").unwrap().split("\n").map(|s| s.to_string()).collect();
-    let script = database::meta::DatabaseChange::SqlDb(script_str);
-    let errors = database::integration::migrate_db(&script, &config);
-    match errors {
-      Ok(_) => {
-        "Migration completed".to_string()
+    let ast = lang::ast_builder::build_from_main_file(&args[2]).unwrap();
+    let config = match database_config(args, &ast) {
+      Err(e) => return e
+    , Ok(c) => c
+    };
+    let _lock = match lock(args, &ast, &config) {
+      Err(e) => return e
+    , Ok(l) => l
+    };
+    let policy = match policy(args, &ast) {
+      Err(e) => return e
+    , Ok(p) => p
+    };
+    match database::migrations::migrate(Path::new(database::migrations::MIGRATIONS_DIR), &policy, &config) {
+      Ok(versions) if versions.is_empty() => "There were no migrations to apply".to_string()
+    , Ok(versions) => {
+        format!("Migration completed, applied {}", versions.iter().map(|v| v.to_string()).collect::<Vec<String>>().join(", "))
       }
-    , Err(s) => s
+    , Err(s) => config::redact(&s, &config)
     }  
-}
\ No newline at end of file
+}
+
+fn rollback(args: &[String]) -> String {
+    let ast = lang::ast_builder::build_from_main_file(&args[2]).unwrap();
+    let config = match database_config(args, &ast) {
+      Err(e) => return e
+    , Ok(c) => c
+    };
+    let _lock = match lock(args, &ast, &config) {
+      Err(e) => return e
+    , Ok(l) => l
+    };
+    let policy = match policy(args, &ast) {
+      Err(e) => return e
+    , Ok(p) => p
+    };
+    let to_version = match flag_value(args, "--to").map(|v| v.parse::<i32>()) {
+      None => None
+    , Some(Ok(v)) => Some(v)
+    , Some(Err(_)) => return "--to needs a migration version number".to_string()
+    };
+    match database::migrations::rollback(Path::new(database::migrations::MIGRATIONS_DIR), to_version, &policy, &config) {
+      Ok(versions) if versions.is_empty() => "There were no migrations to roll back".to_string()
+    , Ok(versions) => {
+        format!("Rollback completed, undid {}", versions.iter().map(|v| v.to_string()).collect::<Vec<String>>().join(", "))
+      }
+    , Err(s) => config::redact(&s, &config)
+    }
+}
+
+fn copy(args: &[String]) -> String {
+    let ast = lang::ast_builder::build_from_main_file(&args[2]).unwrap();
+    let (from_env, to_env) = match (flag_value(args, "--from"), flag_value(args, "--to")) {
+      (Some(f), Some(t)) => (f, t)
+    , _ => return "copy needs the environments to copy --from and --to".to_string()
+    };
+    let configs = config::load(config::CONFIG_FILE, &ast.name())
+      .and_then(|c| Ok((c.database_config(Some(&from_env))?, c.database_config(Some(&to_env))?)));
+    let (from, to) = match configs {
+      Err(e) => return e.to_string()
+    , Ok(c) => c
+    };
+    match database::copy::copy_tables(&ast, &from, &to) {
+      Ok(tables) => format!("Copied {} from {} into {}", tables.join(", "), from_env, to_env)
+    , Err(s) => config::redact(&s, &from)
+    }
+}
+
+fn reverse(args: &[String]) -> String {
+    let ast = lang::ast_builder::build_from_main_file(&args[2]).unwrap();
+    let config = match database_config(args, &ast) {
+      Err(e) => return e
+    , Ok(c) => c
+    };
+    let schema = flag_value(args, "--schema");
+    let database = match database::integration::introspect(schema.as_ref().map(std::slice::from_ref), &config) {
+      Err(m) => return config::redact(&m, &config)
+    , Ok(d) => d
+    };
+    let (reversed, notes) = database::reverse::reverse(&ast.name(), &database, &config, schema.as_deref());
+    write_model(args, &reversed, &notes)
+}
+
+fn import(args: &[String]) -> String {
+    let path = match args.get(3) {
+      None => return "import needs the .sql or .json file to import".to_string()
+    , Some(p) => p
+    };
+    // read before building the ast makes the main file's directory the working directory
+    let text = match fs::read_to_string(path) {
+      Err(e) => return format!("I couldn't read {}: {}", path, e)
+    , Ok(t) => t
+    };
+    let ast = lang::ast_builder::build_from_main_file(&args[2]).unwrap();
+    let imported = if path.ends_with(".sql") {
+      database_config(args, &ast).map(|config| {
+        let (database, left_out) = database::import::ddl::read_ddl(&text, &config);
+        let (imported, mut notes) = database::reverse::reverse(&ast.name(), &database, &config, flag_value(args, "--schema").as_deref());
+        left_out.iter().for_each(|n| notes.note_app(n));
+        (imported, notes)
+      })
+    } else if path.ends_with(".json") {
+      let namespace = flag_value(args, "--namespace").unwrap_or_else(|| ast.name());
+      database::import::json_schema::read_json_schema(&ast.name(), &namespace, &text)
+    } else {
+      Err(format!("I can only import .sql DDL and .json JSON Schema files, not {}.", path))
+    };
+    match imported {
+      Err(m) => m
+    , Ok((imported, notes)) => write_model(args, &imported, &notes)
+    }
+}
+
+fn seed(args: &[String]) -> String {
+    // a fixture file that is named is read before building the ast moves to the main file's directory
+    let named = args.get(3).filter(|a| !a.starts_with("--")).map(|path| (path.clone(), fs::read_to_string(path)));
+    let ast = lang::ast_builder::build_from_main_file(&args[2]).unwrap();
+    let (path, text) = named.unwrap_or_else(|| (database::seed::FIXTURES_FILE.to_string(), fs::read_to_string(database::seed::FIXTURES_FILE)));
+    let text = match text {
+      Err(e) => return format!("I couldn't read {}: {}", path, e)
+    , Ok(t) => t
+    };
+    let config = match database_config(args, &ast) {
+      Err(e) => return e
+    , Ok(c) => c
+    };
+    let _lock = match lock(args, &ast, &config) {
+      Err(e) => return e
+    , Ok(l) => l
+    };
+    match database::seed::seed(&ast, &text, &config) {
+      Ok(tables) => {
+        let seeded = tables.iter().map(|(table, rows)| format!("{} row{} into {}", rows, if *rows == 1 { "" } else { "s" }, table)).collect::<Vec<String>>();
+        format!("Seeded {}", seeded.join(", "))
+      }
+    , Err(s) => format!("I couldn't seed from {}:\n{}", path, config::redact(&s, &config))
+    }
+}
+
+// the model's namespaces written beside the main file, which imports them
+fn write_model(args: &[String], model: &lang::ast::Application, notes: &lang::printer::Notes) -> String {
+    let sources = lang::printer::namespace_sources(model, notes);
+    if sources.is_empty() && notes.count() == 0 {
+      return "There were no tables to read".to_string();
+    } else if sources.is_empty() {
+      return format!("I couldn't make an entity of anything I read:\n{}", notes.app_notes().join("\n"));
+    }
+    // building the ast made the main file's directory the working directory
+    let main_path = Path::new(Path::new(&args[2]).file_name().unwrap_or_default());
+    match lang::printer::write_sources(main_path, model, &sources, notes) {
+      Err(m) => m
+    , Ok(files) => {
+        format!("Wrote {} with {} entities and {} notes on what gimbal can't express", files.join(", "), model.entity_types().len(), notes.count())
+      }
+    }
+}